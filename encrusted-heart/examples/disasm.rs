@@ -0,0 +1,51 @@
+extern crate clap;
+extern crate encrusted_heart;
+
+use std::fs;
+use std::process;
+
+use clap::{App, Arg};
+
+use encrusted_heart::blorb::Blorb;
+use encrusted_heart::disasm::Listing;
+use encrusted_heart::options::Options;
+use encrusted_heart::traits::BaseUI;
+use encrusted_heart::zmachine::Zmachine;
+
+fn main() {
+    let matches = App::new("disasm")
+        .about("Lists the routines in a Z-machine story file")
+        .arg(
+            Arg::with_name("FILE")
+                .help("The story file, or a Blorb containing one")
+                .required(true),
+        )
+        .get_matches();
+
+    let path = matches.value_of("FILE").unwrap();
+    let mut data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    if Blorb::is_blorb(&data) {
+        data = match Blorb::from_bytes(data).and_then(|blorb| blorb.into_story()) {
+            Ok(story) => story,
+            Err(e) => {
+                eprintln!("Couldn't read the story from {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+    }
+
+    if data.len() < 0x40 || data[0] == 0 || data[0] > 8 {
+        eprintln!("{} doesn't look like a Z-machine story file", path);
+        process::exit(1);
+    }
+
+    let zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+    print!("{}", Listing::new(&zvm));
+}
@@ -0,0 +1,283 @@
+extern crate clap;
+extern crate encrusted_heart;
+
+use std::fs;
+use std::process;
+
+use clap::{App, Arg};
+
+use encrusted_heart::blorb::Blorb;
+use encrusted_heart::disasm::Listing;
+use encrusted_heart::options::Options;
+use encrusted_heart::traits::BaseUI;
+use encrusted_heart::zmachine::Zmachine;
+use encrusted_heart::zscii::ZChar;
+
+const FLAGS1_V3: &[(u8, &str)] = &[
+    (1, "status line shows the time"),
+    (2, "story file split across discs"),
+    (4, "status line not available"),
+    (5, "screen splitting available"),
+    (6, "variable-pitch font by default"),
+];
+
+const FLAGS1_V4: &[(u8, &str)] = &[
+    (0, "colours available"),
+    (1, "pictures available"),
+    (2, "bold available"),
+    (3, "italic available"),
+    (4, "fixed-space font available"),
+    (5, "sound effects available"),
+    (7, "timed input available"),
+];
+
+const FLAGS2: &[(u8, &str)] = &[
+    (0, "transcripting on"),
+    (1, "fixed-pitch font forced"),
+    (2, "screen redraw requested"),
+    (3, "wants pictures"),
+    (4, "wants undo"),
+    (5, "wants a mouse"),
+    (6, "wants colours"),
+    (7, "wants sound effects"),
+    (8, "wants menus"),
+];
+
+fn word(data: &[u8], addr: usize) -> u16 {
+    u16::from_be_bytes([data[addr], data[addr + 1]])
+}
+
+fn flag_names(flags: u16, names: &[(u8, &str)]) -> String {
+    let set: Vec<&str> = names
+        .iter()
+        .filter(|(bit, _)| flags & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if set.is_empty() {
+        "none".to_string()
+    } else {
+        set.join(", ")
+    }
+}
+
+// The header as it is in the file, before the interpreter fills in its own fields.
+fn print_header(data: &[u8], zvm: &Zmachine<BaseUI>) {
+    let version = data[0];
+    let length_scale = match version {
+        1..=3 => 2,
+        4 | 5 => 4,
+        _ => 8,
+    };
+    let serial: String = data[0x12..0x18].iter().map(|&b| b as char).collect();
+    let flags1 = u16::from(data[0x01]);
+    let flags2 = word(data, 0x10);
+
+    println!("*** Header ***\n");
+    println!("Z-code version:           {}", version);
+    println!("Release number:           {}", word(data, 0x02));
+    println!("Serial number:            {}", serial);
+    println!(
+        "Interpreter flags:        {:02x} ({})",
+        flags1,
+        flag_names(flags1, if version <= 3 { FLAGS1_V3 } else { FLAGS1_V4 })
+    );
+    println!(
+        "Game flags:               {:04x} ({})",
+        flags2,
+        flag_names(flags2, FLAGS2)
+    );
+    println!("Size of resident memory:  {:05x}", word(data, 0x04));
+    if version == 6 || version == 7 {
+        println!("Main routine (packed):    {:04x}", word(data, 0x06));
+    } else {
+        println!("Start PC:                 {:05x}", word(data, 0x06));
+    }
+    println!("Dictionary address:       {:05x}", word(data, 0x08));
+    println!("Object table address:     {:05x}", word(data, 0x0A));
+    println!("Global variables address: {:05x}", word(data, 0x0C));
+    println!("Size of dynamic memory:   {:05x}", word(data, 0x0E));
+    println!("Abbreviations address:    {:05x}", word(data, 0x18));
+    println!(
+        "File size:                {:05x}",
+        word(data, 0x1A) as usize * length_scale
+    );
+    println!(
+        "Checksum:                 {:04x} ({})",
+        word(data, 0x1C),
+        if zvm.verify_story() {
+            "ok"
+        } else {
+            "doesn't match"
+        }
+    );
+    if version >= 5 {
+        println!("Terminating keys address: {:05x}", word(data, 0x2E));
+        println!("Alphabet address:         {:05x}", word(data, 0x34));
+        println!("Header extension address: {:05x}", word(data, 0x36));
+    }
+    if data[0x3C..0x40].iter().all(|b| b.is_ascii_graphic()) {
+        let inform: String = data[0x3C..0x40].iter().map(|&b| b as char).collect();
+        println!("Inform version:           {}", inform);
+    }
+    println!();
+}
+
+fn print_abbreviations(zvm: &Zmachine<BaseUI>) {
+    println!("*** Abbreviations ***\n");
+    for (i, abbreviation) in zvm.get_abbreviations().iter().enumerate() {
+        println!("[{:2}] {:?}", i, abbreviation);
+    }
+    println!();
+}
+
+fn print_dictionary(zvm: &Zmachine<BaseUI>) {
+    let entries = zvm.get_dictionary_entries();
+    let separators: String = zvm
+        .get_word_separators()
+        .iter()
+        .map(|&b| ZChar(b).to_char(zvm.unicode_table()))
+        .collect();
+
+    println!("*** Dictionary ***\n");
+    println!("Word separators: {:?}", separators);
+    println!("Word count:      {}\n", entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        let data: Vec<String> = entry.data.iter().map(|b| format!("{:02x}", b)).collect();
+        println!(
+            "[{:4}] @ {:05x} {:12} {}",
+            i + 1,
+            entry.addr,
+            entry.word,
+            data.join(" ")
+        );
+    }
+    println!();
+}
+
+fn print_alphabets(zvm: &Zmachine<BaseUI>) {
+    println!("*** Alphabets ***\n");
+    for (i, alphabet) in zvm.get_alphabets().iter().enumerate() {
+        // zchars 0 to 5 are spaces and shifts, and aren't part of the alphabet proper
+        let letters: String = alphabet[6..].concat();
+        println!("A{}: {:?}", i, letters);
+    }
+
+    println!("\n*** Unicode table ***\n");
+    for (i, c) in zvm.unicode_table().iter().enumerate() {
+        println!("{:3}: {} (U+{:04X})", 155 + i, c, *c as u32);
+    }
+    println!();
+}
+
+fn print_strings(zvm: &Zmachine<BaseUI>) {
+    println!("*** Strings ***\n");
+    for (addr, string) in Listing::new(zvm).strings(zvm) {
+        println!("{:05x}: {:?}", addr, string);
+    }
+    println!();
+}
+
+fn main() {
+    let matches = App::new("infodump")
+        .about("Describes the contents of a Z-machine story file")
+        .arg(
+            Arg::with_name("FILE")
+                .help("The story file, or a Blorb containing one")
+                .required(true),
+        )
+        .arg(Arg::with_name("header").short("i").help("Show the header"))
+        .arg(
+            Arg::with_name("abbreviations")
+                .short("a")
+                .help("Show the abbreviations"),
+        )
+        .arg(
+            Arg::with_name("dictionary")
+                .short("d")
+                .help("Show the dictionary"),
+        )
+        .arg(
+            Arg::with_name("objects")
+                .short("o")
+                .help("Show the objects, with their attributes and properties"),
+        )
+        .arg(
+            Arg::with_name("tree")
+                .short("t")
+                .help("Show the object tree"),
+        )
+        .arg(
+            Arg::with_name("alphabets")
+                .short("g")
+                .help("Show the alphabets and unicode table"),
+        )
+        .arg(
+            Arg::with_name("strings")
+                .short("s")
+                .help("Show the strings in high memory"),
+        )
+        .get_matches();
+
+    let path = matches.value_of("FILE").unwrap();
+    let mut data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    if Blorb::is_blorb(&data) {
+        data = match Blorb::from_bytes(data).and_then(|blorb| blorb.into_story()) {
+            Ok(story) => story,
+            Err(e) => {
+                eprintln!("Couldn't read the story from {}: {}", path, e);
+                process::exit(1);
+            }
+        };
+    }
+
+    if data.len() < 0x40 || data[0] == 0 || data[0] > 8 {
+        eprintln!("{} doesn't look like a Z-machine story file", path);
+        process::exit(1);
+    }
+
+    let zvm = Zmachine::new(data.clone(), BaseUI::new(), Options::default());
+
+    // with no options, show everything
+    let sections = [
+        "header",
+        "abbreviations",
+        "dictionary",
+        "objects",
+        "tree",
+        "alphabets",
+        "strings",
+    ];
+    let all = !sections.iter().any(|section| matches.is_present(section));
+    let show = |section: &str| all || matches.is_present(section);
+
+    if show("header") {
+        print_header(&data, &zvm);
+    }
+    if show("abbreviations") {
+        print_abbreviations(&zvm);
+    }
+    if show("dictionary") {
+        print_dictionary(&zvm);
+    }
+    if show("objects") {
+        println!("*** Objects ***\n");
+        println!("{}", zvm.get_object_tree().to_detailed_string());
+    }
+    if show("tree") {
+        println!("*** Object tree ***\n");
+        println!("{}", zvm.get_object_tree());
+    }
+    if show("alphabets") {
+        print_alphabets(&zvm);
+    }
+    if show("strings") {
+        print_strings(&zvm);
+    }
+}
@@ -8,35 +8,97 @@ extern crate regex;
 extern crate serde_json;
 extern crate termion;
 
-use std::fs::File;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
+use std::io::LineWriter;
 use std::path::{Path, PathBuf};
-use std::{io, mem, process};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{io, iter, mem, process, thread};
 
 use clap::{App, Arg};
 use regex::Regex;
-use termion::event::Key;
-use termion::input::TermRead;
+use termion::event::{self, Event, Key};
 use termion::raw::IntoRawMode;
 
+use encrusted_heart::blorb::Blorb;
+use encrusted_heart::debug::{Stop, Watch};
 use encrusted_heart::options::Options;
-use encrusted_heart::traits::{BaseOutput, BaseUI};
+use encrusted_heart::trace::{JsonTracer, RingTracer, TextTracer, Tracer};
+use encrusted_heart::traits::{BaseOutput, BaseUI, SoundEvent, TextStyle};
 use encrusted_heart::zmachine::{Step, Zmachine};
 use encrusted_heart::zscii::ZChar;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// Everything from stdin, a byte at a time, so that timed reads can give up waiting for it.
+// The start of a line that hasn't been finished yet waits in `pending`.
+struct Stdin {
+    bytes: Receiver<u8>,
+    pending: Vec<u8>,
+}
+
 lazy_static! {
     static ref ANSI_RE: Regex =
         Regex::new(r"[\x1b\x9b][\[()#;?]*(?:[0-9]{1,4}(?:;[0-9]{0,4})*)?[0-9A-PRZcf-nqry=><]")
             .unwrap();
+    static ref STDIN: Mutex<Stdin> = {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().bytes() {
+                match byte {
+                    Ok(byte) if tx.send(byte).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        Mutex::new(Stdin {
+            bytes: rx,
+            pending: vec![],
+        })
+    };
 }
 
-fn get_user_input() -> Option<String> {
-    let mut input = String::new();
-    let result = io::stdin()
-        .read_line(&mut input)
-        .expect("Error reading input");
+// What came of waiting for the player.
+enum Input<T> {
+    Got(T),
+    TimedOut,
+    Closed,
+}
+
+fn next_byte(stdin: &Stdin, deadline: Option<Instant>) -> Input<u8> {
+    let result = match deadline {
+        Some(deadline) => stdin
+            .bytes
+            .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        None => stdin
+            .bytes
+            .recv()
+            .map_err(|_| RecvTimeoutError::Disconnected),
+    };
+    match result {
+        Ok(byte) => Input::Got(byte),
+        Err(RecvTimeoutError::Timeout) => Input::TimedOut,
+        Err(RecvTimeoutError::Disconnected) => Input::Closed,
+    }
+}
+
+fn read_line(timeout: Option<Duration>) -> Input<String> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut stdin = STDIN.lock().unwrap();
+
+    loop {
+        match next_byte(&stdin, deadline) {
+            Input::Got(b'\n') => break,
+            Input::Got(byte) => stdin.pending.push(byte),
+            Input::TimedOut => return Input::TimedOut,
+            Input::Closed if stdin.pending.is_empty() => return Input::Closed,
+            Input::Closed => break,
+        }
+    }
+    let input = String::from_utf8_lossy(&mem::take(&mut stdin.pending)).into_owned();
 
     // trim, strip and control sequences that might have gotten in,
     // and then trim once more to get rid of any excess whitespace
@@ -46,10 +108,263 @@ fn get_user_input() -> Option<String> {
         .trim()
         .to_string();
 
-    if result == 0 {
-        None
+    Input::Got(trimmed)
+}
+
+// The part of a line the player has typed so far. On a terminal that's usually nothing, since it
+// holds on to the line until return is pressed.
+fn take_partial_line() -> String {
+    let mut stdin = STDIN.lock().unwrap();
+    String::from_utf8_lossy(&mem::take(&mut stdin.pending)).into_owned()
+}
+
+fn put_back_partial_line(partial: &str) {
+    STDIN.lock().unwrap().pending = partial.as_bytes().to_vec();
+}
+
+fn read_key(deadline: Option<Instant>) -> Input<Key> {
+    let stdin = STDIN.lock().unwrap();
+
+    let first = match next_byte(&stdin, deadline) {
+        Input::Got(byte) => byte,
+        Input::TimedOut => return Input::TimedOut,
+        Input::Closed => return Input::Closed,
+    };
+
+    // the rest of an escape sequence comes right after its first byte, and without it, it's
+    // just the escape key
+    let mut rest = iter::from_fn(|| {
+        let byte = stdin.bytes.recv_timeout(Duration::from_millis(10)).ok()?;
+        Some(Ok(byte))
+    })
+    .peekable();
+    if first == 0x1B && rest.peek().is_none() {
+        return Input::Got(Key::Esc);
+    }
+
+    match event::parse_event(first, &mut rest) {
+        Ok(Event::Key(key)) => Input::Got(key),
+        _ => Input::Got(Key::Null),
+    }
+}
+
+fn get_user_input() -> Option<String> {
+    match read_line(None) {
+        Input::Got(line) => Some(line),
+        Input::TimedOut | Input::Closed => None,
+    }
+}
+
+fn describe_sound(event: SoundEvent) -> String {
+    match event {
+        SoundEvent::Beep { high: true } => "high beep".to_string(),
+        SoundEvent::Beep { high: false } => "low beep".to_string(),
+        SoundEvent::Prepare(number) => format!("sound {} loaded", number),
+        SoundEvent::Play {
+            number,
+            volume,
+            repeats: 255,
+        } => format!("sound {} playing forever at volume {}", number, volume),
+        SoundEvent::Play {
+            number,
+            volume,
+            repeats,
+        } => format!(
+            "sound {} playing {} time(s) at volume {}",
+            number, repeats, volume
+        ),
+        SoundEvent::Stop(number) => format!("sound {} stopped", number),
+        SoundEvent::Unload(number) => format!("sound {} unloaded", number),
+    }
+}
+
+const DEBUGGER_HELP: &str = "\
+Commands (numbers are decimal, or hex with a 0x prefix):
+  c                      continue
+  s                      run one instruction
+  b ADDR / br ROUTINE    break at an instruction / when a routine is called
+  d ADDR                 delete a breakpoint
+  w g N / w m ADDR LEN / w a OBJ ATTR
+                         watch a global / a memory range / an object attribute
+  uw ...                 stop watching, with the same arguments as w
+  i                      list breakpoints and watches
+  bt                     show the call stack
+  p VAR                  print a variable: sp, local0-14 or g0-239
+  set VAR VALUE          change a variable
+  x ADDR [LEN]           dump memory
+  q                      quit";
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_variable(text: &str) -> Option<u8> {
+    if text == "sp" {
+        Some(0)
+    } else if let Some(local) = text.strip_prefix("local") {
+        local.parse::<u8>().ok().filter(|&n| n < 15).map(|n| n + 1)
+    } else if let Some(global) = text.strip_prefix('g') {
+        global
+            .parse::<u8>()
+            .ok()
+            .filter(|&n| n < 240)
+            .map(|n| n + 16)
     } else {
-        Some(trimmed)
+        None
+    }
+}
+
+fn parse_watch(args: &[&str]) -> Option<Watch> {
+    match args {
+        ["g", n] => Some(Watch::Global(parse_number(n)?.try_into().ok()?)),
+        ["m", start, length] => Some(Watch::Memory {
+            start: parse_number(start)?,
+            length: parse_number(length)?,
+        }),
+        ["a", object, attribute] => Some(Watch::Attribute {
+            object: parse_number(object)?.try_into().ok()?,
+            attribute: parse_number(attribute)?.try_into().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+fn describe_stop(stop: &Stop) -> String {
+    match stop {
+        Stop::Interrupted => "interrupted".to_string(),
+        Stop::Stepped => "stepped".to_string(),
+        Stop::Breakpoint(pc) => format!("breakpoint at {:05x}", pc),
+        Stop::Routine(addr) => format!("called routine {:05x}", addr),
+        Stop::Watch {
+            watch,
+            pc,
+            old,
+            new,
+        } => format!("{:?} changed by {:05x}: {:?} -> {:?}", watch, pc, old, new),
+    }
+}
+
+// Read and run debugger commands until the player asks to carry on with the game.
+fn debug_repl(zvm: &mut Zmachine<BaseUI>, stop: &Stop) {
+    println!("\n[{}]", describe_stop(stop));
+    match zvm.decode_instruction(zvm.pc()) {
+        Ok(instr) => println!("{}", instr),
+        Err(e) => println!("{}", e),
+    }
+
+    loop {
+        print!("(debug) ");
+        io::stdout().flush().unwrap();
+        let line = match get_user_input() {
+            None => process::exit(0),
+            Some(line) => line,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["c"] => {
+                zvm.debugger().set_stepping(false);
+                return;
+            }
+            ["s"] => {
+                zvm.debugger().set_stepping(true);
+                return;
+            }
+            ["b", addr] | ["br", addr] | ["d", addr] => match parse_number(addr) {
+                Some(addr) => {
+                    let debugger = zvm.debugger();
+                    match words[0] {
+                        "b" => debugger.add_breakpoint(addr),
+                        "br" => debugger.add_routine_breakpoint(addr),
+                        _ => {
+                            debugger.remove_breakpoint(addr)
+                                | debugger.remove_routine_breakpoint(addr)
+                        }
+                    };
+                }
+                None => println!("Bad address: {}", addr),
+            },
+            ["w", args @ ..] | ["uw", args @ ..] => match parse_watch(args) {
+                Some(watch) if words[0] == "w" => {
+                    zvm.debugger().watch(watch);
+                }
+                Some(watch) => {
+                    zvm.debugger().unwatch(&watch);
+                }
+                None => println!("Try w g N, w m ADDR LEN or w a OBJ ATTR"),
+            },
+            ["i"] => {
+                let debugger = zvm.debugger();
+                for pc in debugger.breakpoints() {
+                    println!("break at {:05x}", pc);
+                }
+                for addr in debugger.routine_breakpoints() {
+                    println!("break in routine {:05x}", addr);
+                }
+                for watch in debugger.watches() {
+                    println!("watch {:?}", watch);
+                }
+            }
+            ["bt"] => {
+                for (depth, frame) in zvm.frames().iter().enumerate().rev() {
+                    println!("#{} {}", depth, frame);
+                }
+            }
+            ["p", var] => match parse_variable(var).and_then(|v| zvm.peek_variable(v)) {
+                Some(value) => println!("{} = {} ({:#06x})", var, value, value),
+                None => println!("No such variable: {}", var),
+            },
+            ["set", var, value] => {
+                let poked = match (parse_variable(var), parse_number(value)) {
+                    (Some(var), Some(value)) => zvm.poke_variable(var, value as u16),
+                    _ => false,
+                };
+                if !poked {
+                    println!("Can't set {} to {}", var, value);
+                }
+            }
+            ["x", addr, rest @ ..] => {
+                let length = rest.first().and_then(|l| parse_number(l)).unwrap_or(16);
+                let memory = parse_number(addr)
+                    .and_then(|start| Some((start, zvm.read_memory(start, length)?)));
+                match memory {
+                    Some((start, bytes)) => {
+                        for (i, row) in bytes.chunks(16).enumerate() {
+                            let hex: Vec<String> =
+                                row.iter().map(|b| format!("{:02x}", b)).collect();
+                            println!("{:05x}: {}", start + i * 16, hex.join(" "));
+                        }
+                    }
+                    None => println!("Can't read that memory"),
+                }
+            }
+            ["q"] => process::exit(0),
+            _ => println!("{}", DEBUGGER_HELP),
+        }
+    }
+}
+
+fn print_colours(style: TextStyle) {
+    if let Some((r, g, b)) = style.foreground.rgb() {
+        print!("{}", termion::color::Fg(termion::color::Rgb(r, g, b)));
+    }
+    if let Some((r, g, b)) = style.background.rgb() {
+        print!("{}", termion::color::Bg(termion::color::Rgb(r, g, b)));
+    }
+}
+
+fn append_to_file(path: &Path, contents: &str) {
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+
+    if let Err(e) = result {
+        eprintln!("Can't write to {}: {:?}", path.to_string_lossy(), e);
     }
 }
 
@@ -70,11 +385,61 @@ fn main() {
                 .takes_value(false)
                 .help("Log verbose debugging information to stderr"),
         )
+        .arg(
+            Arg::with_name("debugger")
+                .long("debugger")
+                .takes_value(false)
+                .help("Stop before the first instruction, and start the debugger"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("TRACE_FILE")
+                .help("Write every instruction to a file as JSON lines, to diff against other interpreters"),
+        )
+        .arg(
+            Arg::with_name("backtrace")
+                .long("backtrace")
+                .takes_value(false)
+                .help("If the game fails, print the last few instructions it ran to stderr"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("COMMAND_FILE")
+                .help("Record every command you type to a file, to replay or share"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .takes_value(true)
+                .value_name("COMMAND_FILE")
+                .help("Replay the commands in a file before handing control back to you"),
+        )
+        .arg(
+            Arg::with_name("transcript")
+                .long("transcript")
+                .takes_value(true)
+                .value_name("TRANSCRIPT_FILE")
+                .help(
+                    "Where to write the transcript, if the game starts one [default: <FILE>.scr]",
+                ),
+        )
         .get_matches();
 
     let path = Path::new(matches.value_of("FILE").unwrap());
 
     let debug_log = matches.is_present("debug");
+    let record_path = match matches.value_of("record") {
+        Some(record) => PathBuf::from(record),
+        None => path.with_extension("rec"),
+    };
+    let transcript_path = match matches.value_of("transcript") {
+        Some(transcript) => PathBuf::from(transcript),
+        None => path.with_extension("scr"),
+    };
 
     if !path.is_file() {
         println!(
@@ -88,7 +453,19 @@ fn main() {
     let mut file = File::open(path).expect("Error opening file");
     file.read_to_end(&mut data).expect("Error reading file");
 
-    let version = data[0];
+    let (data, blorb) = match Blorb::split_story(data) {
+        Ok(split) => split,
+        Err(e) => {
+            println!(
+                "\nCouldn't read the story from \"{}\": {}\n",
+                path.to_string_lossy(),
+                e
+            );
+            process::exit(1);
+        }
+    };
+
+    let version = data.first().copied().unwrap_or(0);
 
     if version == 0 || version > 8 {
         println!(
@@ -116,13 +493,47 @@ fn main() {
     let rand32 = || rand::random();
     opts.rand_seed = [rand32(), rand32(), rand32(), rand32()];
     opts.dimensions = (term_width, term_height);
-    opts.log_instructions = debug_log;
 
     if is_tty {
         print!("{}", termion::clear::All);
     }
 
+    let tracer: Option<Box<dyn Tracer + Send>> = match matches.value_of("trace") {
+        Some(trace_path) => match File::create(trace_path) {
+            Ok(file) => Some(Box::new(JsonTracer::new(LineWriter::new(file)))),
+            Err(e) => {
+                println!(
+                    "\nCouldn't create trace file: \n   {}\n   {}\n",
+                    trace_path, e
+                );
+                process::exit(1);
+            }
+        },
+        None if debug_log => Some(Box::new(TextTracer::new(io::stderr()))),
+        None if matches.is_present("backtrace") => {
+            Some(Box::new(RingTracer::new(32, io::stderr())))
+        }
+        None => None,
+    };
+
     let mut zvm = Zmachine::new(data, ui, opts);
+    zvm.set_tracer(tracer);
+    if matches.is_present("debugger") {
+        zvm.debugger().interrupt();
+    }
+    zvm.set_command_recording(matches.is_present("record"));
+    if let Some(blorb) = blorb {
+        zvm.set_blorb(blorb);
+    }
+    if let Some(replay) = matches.value_of("replay") {
+        match std::fs::read_to_string(replay) {
+            Ok(commands) => zvm.replay_commands(&commands),
+            Err(e) => {
+                println!("\nCouldn't read command file: \n   {}\n   {}\n", replay, e);
+                process::exit(1);
+            }
+        }
+    }
     let mut x_position = 0;
 
     loop {
@@ -132,7 +543,7 @@ fn main() {
             print!("{}", termion::clear::All);
         }
         for BaseOutput {
-            style: _,
+            style,
             content: text,
         } in zvm.ui.drain_output()
         {
@@ -142,6 +553,8 @@ fn main() {
                 continue;
             }
 
+            print_colours(style);
+
             // `.lines()` discards trailing \n and collapses multiple \n's between lines
             let lines = text.split('\n').collect::<Vec<_>>();
             let num_lines = lines.len();
@@ -181,9 +594,39 @@ fn main() {
                 }
             });
 
+            print!("{}", termion::style::Reset);
             io::stdout().flush().unwrap();
         }
 
+        let transcript = zvm.ui.drain_transcript();
+        if !transcript.is_empty() {
+            append_to_file(&transcript_path, &transcript);
+        }
+        for command in zvm.ui.drain_commands() {
+            append_to_file(&record_path, &format!("{}\n", command));
+        }
+
+        // There's no audio here, so sounds are reported as text and end as soon as they start.
+        for event in zvm.ui.drain_sounds() {
+            if x_position > 0 {
+                println!();
+                x_position = 0;
+            }
+            println!("[{}]", describe_sound(event));
+
+            if let SoundEvent::Play {
+                number, repeats, ..
+            } = event
+            {
+                if repeats != 255 {
+                    if let Err(error) = zvm.handle_sound_finished(number) {
+                        println!("\n\nThe game has stopped with an error: {}", error);
+                        process::exit(1);
+                    }
+                }
+            }
+        }
+
         if is_tty {
             print!("{}", termion::cursor::Save);
             // eprintln!();
@@ -203,6 +646,7 @@ fn main() {
                     if style.reverse_video() {
                         print!("{}", termion::style::Invert);
                     }
+                    print_colours(style);
                     print!("{}{}", c, termion::style::Reset);
                 }
             }
@@ -210,10 +654,21 @@ fn main() {
             io::stdout().flush().unwrap();
         }
 
+        let step = match step {
+            Ok(step) => step,
+            Err(error) => {
+                println!("\n\nThe game has stopped with an error: {}", error);
+                process::exit(1);
+            }
+        };
+
         match step {
             Step::Done => {
                 break;
             }
+            // we don't set a budget, but there's nothing to do but carry on anyway
+            Step::Yield => {}
+            Step::Break(stop) => debug_repl(&mut zvm, &stop),
             Step::Save(data) => {
                 println!("\nFilename [{}]: ", &save_name);
                 let input = get_user_input().unwrap();
@@ -223,7 +678,10 @@ fn main() {
                 match input.to_lowercase().as_ref() {
                     "" | "yes" | "y" => path.push(&save_name),
                     "no" | "n" | "cancel" => {
-                        zvm.handle_save_result(false);
+                        if let Err(error) = zvm.handle_save_result(false) {
+                            println!("\n\nThe game has stopped with an error: {}", error);
+                            process::exit(1);
+                        }
                         continue;
                     }
                     _ => path.push(input),
@@ -233,7 +691,10 @@ fn main() {
                     file = handle;
                 } else {
                     println!("Can't save to that file, try another?\n");
-                    zvm.handle_save_result(false);
+                    if let Err(error) = zvm.handle_save_result(false) {
+                        println!("\n\nThe game has stopped with an error: {}", error);
+                        process::exit(1);
+                    }
                     return;
                 }
 
@@ -245,7 +706,10 @@ fn main() {
                 file.write_all(data.as_slice())
                     .expect("Error saving to file");
 
-                zvm.handle_save_result(true);
+                if let Err(error) = zvm.handle_save_result(true) {
+                    println!("\n\nThe game has stopped with an error: {}", error);
+                    process::exit(1);
+                }
             }
             Step::Restore => {
                 let mut file = loop {
@@ -265,18 +729,28 @@ fn main() {
                 // restore program counter position, stack frames, and dynamic memory
                 file.read_to_end(&mut save_data)
                     .expect("Error reading save file");
-                zvm.restore(&save_data);
+                if let Err(error) = zvm.restore(&save_data) {
+                    println!("Can't restore from that file: {}", error);
+                    if let Err(error) = zvm.handle_restore_result() {
+                        println!("\n\nThe game has stopped with an error: {}", error);
+                        process::exit(1);
+                    }
+                }
             }
-            Step::ReadChar => {
+            Step::ReadChar { timeout } => {
                 let zscii: ZChar = if is_tty {
                     let stdout = io::stdout().into_raw_mode().unwrap();
-                    let mut keys = io::stdin().keys();
+                    let deadline = timeout.map(|timeout| Instant::now() + timeout);
 
                     // While we expect just a single char, this loops in case unexpected characters
                     // are encountered. (We ignore them.)
                     let zch = loop {
-                        let key = keys.next().expect("Error reading input").unwrap();
-                        break match key {
+                        let key = match read_key(deadline) {
+                            Input::Got(key) => key,
+                            Input::TimedOut => break None,
+                            Input::Closed => return,
+                        };
+                        break Some(match key {
                             Key::Backspace => ZChar::DELETE,
                             Key::Delete => ZChar::DELETE,
                             Key::Esc => ZChar::ESC,
@@ -289,26 +763,60 @@ fn main() {
                                 Some(zch) => zch,
                             },
                             _ => continue,
-                        };
+                        });
                     };
                     mem::drop(stdout);
-                    zch
+
+                    match zch {
+                        Some(zch) => zch,
+                        None => {
+                            // the game's routine decides whether to carry on waiting
+                            if let Err(error) = zvm.handle_timeout("") {
+                                println!("\n\nThe game has stopped with an error: {}", error);
+                                process::exit(1);
+                            }
+                            continue;
+                        }
+                    }
                 } else {
                     ZChar::RETURN
                 };
 
-                zvm.handle_read_char(zscii);
+                if let Err(error) = zvm.handle_read_char(zscii) {
+                    println!("\n\nThe game has stopped with an error: {}", error);
+                    process::exit(1);
+                }
             }
-            Step::ReadLine => {
-                let input = match get_user_input() {
-                    None => return,
-                    Some(line) => line,
+            Step::ReadLine {
+                timeout, preloaded, ..
+            } => {
+                let input = match read_line(timeout) {
+                    Input::Got(line) => line,
+                    Input::Closed => return,
+                    Input::TimedOut => {
+                        let partial = take_partial_line();
+                        match zvm.handle_timeout(&(preloaded + &partial)) {
+                            // From V5, what the player typed comes back as preloaded text, so
+                            // it's only earlier versions that carry on with it here.
+                            Ok(false) if zvm.version() <= 4 => put_back_partial_line(&partial),
+                            Ok(_) => {}
+                            Err(error) => {
+                                println!("\n\nThe game has stopped with an error: {}", error);
+                                process::exit(1);
+                            }
+                        }
+                        continue;
+                    }
                 };
                 if !is_tty {
                     // This won't be automatically visible in stdout!
                     println!("{}", &input);
                 }
-                zvm.handle_input(input);
+                // Any preloaded text is already on screen, so the player carries on after it.
+                if let Err(error) = zvm.handle_input(preloaded + &input) {
+                    println!("\n\nThe game has stopped with an error: {}", error);
+                    process::exit(1);
+                }
             }
         }
     }
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use encrusted_heart::quetzal::QuetzalSave;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks how much dynamic memory the story has, so CMem can run past the end
+    if let Some((&length, save)) = data.split_first() {
+        let original = vec![0; usize::from(length) * 4];
+
+        // a corrupt save is an error, never a panic
+        let _ = QuetzalSave::from_bytes(save, &original);
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use encrusted_heart::quetzal::QuetzalSave;
+
+const ORIGINAL: [u8; 256] = [0x55; 256];
+
+fuzz_target!(|data: &[u8]| {
+    // whatever we can read, we should be able to write back out and read again unchanged
+    if let Ok(save) = QuetzalSave::from_bytes(data, &ORIGINAL) {
+        let bytes = QuetzalSave::make(
+            save.pc,
+            &save.memory,
+            &ORIGINAL,
+            &save.frames,
+            &save.story,
+            &save.chunks,
+        );
+        let again = QuetzalSave::from_bytes(&bytes, &ORIGINAL).expect("can't read our own save");
+
+        assert_eq!(again.pc, save.pc);
+        assert_eq!(again.memory, save.memory);
+        assert_eq!(again.frames, save.frames);
+        assert_eq!(again.story, save.story);
+        assert_eq!(again.chunks, save.chunks);
+    }
+});
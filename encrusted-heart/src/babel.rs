@@ -0,0 +1,211 @@
+//! The Treaty of Babel: how to identify a story (its IFID) and describe it (iFiction metadata).
+//! See https://babel.ifarchive.org/babel_rev11.html for the details.
+
+use crate::blorb::Blorb;
+
+/// What we know about a story: its IFID, plus whatever bibliographic data came with it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StoryInfo {
+    pub ifid: String,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub headline: Option<String>,
+    /// Usually a year, but the spec also allows a full `YYYY-MM-DD` date.
+    pub first_published: Option<String>,
+}
+
+impl StoryInfo {
+    /// Describe a story file, using the iFiction record from its Blorb if there is one.
+    pub fn new(story: &[u8], blorb: Option<&Blorb>) -> StoryInfo {
+        let mut info = StoryInfo {
+            ifid: ifid(story),
+            ..StoryInfo::default()
+        };
+
+        if let Some(xml) = blorb.and_then(|b| b.metadata()) {
+            info.read_ifiction(xml);
+        }
+
+        info
+    }
+
+    /// Fill in the bibliographic fields from an iFiction document. If the document describes
+    /// several stories, we use the one with our IFID, or the first one if none match.
+    pub fn read_ifiction(&mut self, xml: &str) {
+        let stories = elements(xml, "story");
+        let story = stories
+            .iter()
+            .find(|story| {
+                elements(story, "ifid")
+                    .iter()
+                    .any(|id| id.trim().eq_ignore_ascii_case(&self.ifid))
+            })
+            .or_else(|| stories.first());
+
+        let bibliographic = match story.and_then(|s| elements(s, "bibliographic").first().copied())
+        {
+            Some(b) => b,
+            None => return,
+        };
+
+        self.title = text(bibliographic, "title");
+        self.author = text(bibliographic, "author");
+        self.headline = text(bibliographic, "headline");
+        self.first_published = text(bibliographic, "firstpublished");
+    }
+
+    pub fn year(&self) -> Option<u16> {
+        self.first_published
+            .as_ref()
+            .and_then(|date| date.get(0..4))
+            .and_then(|year| year.parse().ok())
+    }
+}
+
+/// The IFID of a Z-code story. Modern Inform embeds a UUID in the story file; otherwise the IFID
+/// is made up from the release number, serial code and (for later games) the checksum.
+pub fn ifid(story: &[u8]) -> String {
+    const UUID_MARKER: &[u8] = b"UUID://";
+
+    if let Some(start) = story
+        .windows(UUID_MARKER.len())
+        .position(|window| window == UUID_MARKER)
+    {
+        let uuid = &story[(start + UUID_MARKER.len())..];
+        if let Some(end) = uuid.iter().position(|b| *b == b'/') {
+            if end > 0 {
+                return String::from_utf8_lossy(&uuid[..end]).into_owned();
+            }
+        }
+    }
+
+    if story.len() < 0x1E {
+        return "ZCODE-0-000000".to_string();
+    }
+
+    let release = u16::from_be_bytes([story[0x02], story[0x03]]);
+    let serial: String = story[0x12..0x18]
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_alphanumeric() {
+                b as char
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let checksum = u16::from_be_bytes([story[0x1C], story[0x1D]]);
+
+    // Infocom's serials are dates from the 80s, which are distinctive enough on their own.
+    let first = serial.as_bytes()[0];
+    if serial != "000000" && first.is_ascii_digit() && first != b'8' {
+        format!("ZCODE-{}-{}-{:04X}", release, serial, checksum)
+    } else {
+        format!("ZCODE-{}-{}", release, serial)
+    }
+}
+
+// The contents of each `<tag>...</tag>` element in the document, outermost first. This is not a
+// real XML parser, but iFiction is regular enough that we don't need one.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut found = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        let after_name = &rest[(start + open.len())..];
+        // make sure we matched the whole tag name, and not eg. <storyteller>
+        if !after_name.starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            rest = after_name;
+            continue;
+        }
+
+        let body_start = match after_name.find('>') {
+            Some(i) => i + 1,
+            None => break,
+        };
+        let body = &after_name[body_start..];
+        match body.find(&close) {
+            Some(end) => {
+                found.push(&body[..end]);
+                rest = &body[(end + close.len())..];
+            }
+            None => break,
+        }
+    }
+
+    found
+}
+
+fn text(xml: &str, tag: &str) -> Option<String> {
+    let raw = elements(xml, tag).first()?.trim();
+    if raw.is_empty() {
+        return None;
+    }
+
+    let unescaped = raw
+        .replace("<br/>", "\n")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn story_file(release: u16, serial: &[u8; 6], checksum: u16) -> Vec<u8> {
+        let mut story = vec![0; 0x40];
+        story[0] = 5;
+        story[0x02..0x04].copy_from_slice(&release.to_be_bytes());
+        story[0x12..0x18].copy_from_slice(serial);
+        story[0x1C..0x1E].copy_from_slice(&checksum.to_be_bytes());
+        story
+    }
+
+    #[test]
+    fn test_ifid() {
+        assert_eq!(ifid(&story_file(88, b"840726", 0xA129)), "ZCODE-88-840726");
+        assert_eq!(
+            ifid(&story_file(2, b"021021", 0x0BEF)),
+            "ZCODE-2-021021-0BEF"
+        );
+
+        let mut story = story_file(1, b"061030", 0x1234);
+        story.extend(b"UUID://E3C1DD9A-3E1A-4EC0-8DAE-1C6F2A1E8AE5//");
+        assert_eq!(ifid(&story), "E3C1DD9A-3E1A-4EC0-8DAE-1C6F2A1E8AE5");
+    }
+
+    #[test]
+    fn test_read_ifiction() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <ifindex version="1.0" xmlns="http://babel.ifarchive.org/protocol/iFiction/">
+              <story>
+                <identification>
+                  <ifid>ZCODE-2-021021-0BEF</ifid>
+                  <format>zcode</format>
+                </identification>
+                <bibliographic>
+                  <title>Curses &amp; Blessings</title>
+                  <author>Graham Nelson</author>
+                  <headline>An Interactive Diversion</headline>
+                  <firstpublished>1993-10-01</firstpublished>
+                </bibliographic>
+              </story>
+            </ifindex>"#;
+
+        let mut info = StoryInfo {
+            ifid: "ZCODE-2-021021-0BEF".to_string(),
+            ..StoryInfo::default()
+        };
+        info.read_ifiction(xml);
+        assert_eq!(info.title.as_deref(), Some("Curses & Blessings"));
+        assert_eq!(info.author.as_deref(), Some("Graham Nelson"));
+        assert_eq!(info.headline.as_deref(), Some("An Interactive Diversion"));
+        assert_eq!(info.year(), Some(1993));
+    }
+}
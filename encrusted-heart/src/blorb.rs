@@ -0,0 +1,388 @@
+//! Blorb is the IFF container format used to ship a story file together with its metadata,
+//! cover art, pictures and sounds. See https://eblong.com/zarf/blorb/blorb.html for the spec.
+
+use std::fmt;
+use std::ops::Range;
+
+/// What a resource in the index is used for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Usage {
+    Picture,
+    Sound,
+    Data,
+    Executable,
+}
+
+impl Usage {
+    fn from_id(id: &[u8]) -> Option<Usage> {
+        match id {
+            b"Pict" => Some(Usage::Picture),
+            b"Snd " => Some(Usage::Sound),
+            b"Data" => Some(Usage::Data),
+            b"Exec" => Some(Usage::Executable),
+            _ => None,
+        }
+    }
+}
+
+/// An entry in the resource index.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Resource {
+    pub usage: Usage,
+    pub number: u32,
+    /// The chunk type of the resource: eg. `ZCOD`, `PNG ` or `AIFF`.
+    pub kind: String,
+    body: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlorbError {
+    NotBlorb,
+    Truncated { offset: usize },
+    BadResourceIndex(String),
+    NoStory,
+}
+
+impl fmt::Display for BlorbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlorbError::NotBlorb => write!(f, "not a Blorb file: missing FORM/IFRS header"),
+            BlorbError::Truncated { offset } => {
+                write!(
+                    f,
+                    "chunk at offset {:#x} runs past the end of the file",
+                    offset
+                )
+            }
+            BlorbError::BadResourceIndex(reason) => write!(f, "bad resource index: {}", reason),
+            BlorbError::NoStory => write!(f, "no Z-machine story file in the resource index"),
+        }
+    }
+}
+
+impl std::error::Error for BlorbError {}
+
+#[derive(Debug)]
+pub struct Blorb {
+    data: Vec<u8>,
+    resources: Vec<Resource>,
+    metadata: Option<Range<usize>>,
+    cover: Option<u32>,
+    loops: Option<Range<usize>>,
+}
+
+impl Blorb {
+    /// A cheap check for the Blorb header, to tell containers apart from raw story files.
+    pub fn is_blorb(data: &[u8]) -> bool {
+        data.len() >= 12 && &data[0..4] == b"FORM" && &data[8..12] == b"IFRS"
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Blorb, BlorbError> {
+        if !Blorb::is_blorb(&data) {
+            return Err(BlorbError::NotBlorb);
+        }
+
+        let (_, form_body) = Blorb::read_chunk(&data, 0)?;
+        let mut index = None;
+        let mut metadata = None;
+        let mut cover = None;
+        let mut loops = None;
+
+        // skip the IFRS string at the start
+        let mut offset = form_body.start + 4;
+        while offset + 8 <= form_body.end {
+            let (header, body) = Blorb::read_chunk(&data, offset)?;
+            match &header {
+                b"RIdx" => index = Some(body.clone()),
+                b"IFmd" => metadata = Some(body.clone()),
+                b"Fspc" if body.len() >= 4 => cover = Some(read_u32(&data, body.start)),
+                b"Loop" => loops = Some(body.clone()),
+                _ => {}
+            }
+
+            // chunks get padded with an empty 0 byte if they have an odd length
+            offset = body.end + body.len() % 2;
+        }
+
+        let index = index.ok_or_else(|| BlorbError::BadResourceIndex("missing".to_string()))?;
+        let resources = Blorb::read_index(&data, index)?;
+
+        Ok(Blorb {
+            data,
+            resources,
+            metadata,
+            cover,
+            loops,
+        })
+    }
+
+    fn read_chunk(data: &[u8], offset: usize) -> Result<([u8; 4], Range<usize>), BlorbError> {
+        if offset + 8 > data.len() {
+            return Err(BlorbError::Truncated { offset });
+        }
+
+        let mut header = [0; 4];
+        header.copy_from_slice(&data[offset..(offset + 4)]);
+        let length = read_u32(data, offset + 4) as usize;
+
+        let start = offset + 8;
+        let end = start
+            .checked_add(length)
+            .filter(|end| *end <= data.len())
+            .ok_or(BlorbError::Truncated { offset })?;
+
+        Ok((header, start..end))
+    }
+
+    fn read_index(data: &[u8], index: Range<usize>) -> Result<Vec<Resource>, BlorbError> {
+        if index.len() < 4 {
+            return Err(BlorbError::BadResourceIndex("too short".to_string()));
+        }
+
+        // 1 long for the count, then 3 longs for each entry: usage, number and chunk offset
+        let count = read_u32(data, index.start) as usize;
+        if index.len() < 4 + count.saturating_mul(12) {
+            return Err(BlorbError::BadResourceIndex(format!(
+                "{} entries don't fit in {} bytes",
+                count,
+                index.len()
+            )));
+        }
+
+        let mut resources = Vec::with_capacity(count);
+        for i in 0..count {
+            let entry = index.start + 4 + i * 12;
+            let usage = Usage::from_id(&data[entry..(entry + 4)]).ok_or_else(|| {
+                BlorbError::BadResourceIndex(format!(
+                    "unknown usage {:?}",
+                    String::from_utf8_lossy(&data[entry..(entry + 4)])
+                ))
+            })?;
+            let number = read_u32(data, entry + 4);
+            let start = read_u32(data, entry + 8) as usize;
+
+            let (kind, body) = Blorb::read_chunk(data, start)?;
+            resources.push(Resource {
+                usage,
+                number,
+                kind: String::from_utf8_lossy(&kind).into_owned(),
+                body,
+            });
+        }
+
+        Ok(resources)
+    }
+
+    /// All the resources in the index, in the order they were listed.
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
+
+    pub fn resource(&self, usage: Usage, number: u32) -> Option<&Resource> {
+        self.resources
+            .iter()
+            .find(|r| r.usage == usage && r.number == number)
+    }
+
+    /// The contents of a resource's chunk.
+    pub fn data(&self, resource: &Resource) -> &[u8] {
+        &self.data[resource.body.clone()]
+    }
+
+    /// The Z-machine story file, if there is one. Blorbs for other systems (eg. Glulx's `GLUL`)
+    /// don't have one.
+    pub fn story(&self) -> Option<&[u8]> {
+        self.resource(Usage::Executable, 0)
+            .filter(|r| r.kind == "ZCOD" && !r.body.is_empty())
+            .map(|r| self.data(r))
+    }
+
+    /// Split a game file into its story and, if it was packaged in one, the Blorb with its
+    /// pictures, sounds and metadata. Plain story files come back as they are.
+    pub fn split_story(data: Vec<u8>) -> Result<(Vec<u8>, Option<Blorb>), BlorbError> {
+        if !Blorb::is_blorb(&data) {
+            return Ok((data, None));
+        }
+        let blorb = Blorb::from_bytes(data)?;
+        let story = blorb.story().ok_or(BlorbError::NoStory)?.to_vec();
+        Ok((story, Some(blorb)))
+    }
+
+    /// Consume the Blorb and return just the story file, ready for `Zmachine::new`.
+    pub fn into_story(self) -> Result<Vec<u8>, BlorbError> {
+        self.story().map(|s| s.to_vec()).ok_or(BlorbError::NoStory)
+    }
+
+    /// The raw iFiction XML from the `IFmd` chunk.
+    pub fn metadata(&self) -> Option<&str> {
+        self.metadata
+            .clone()
+            .and_then(|range| std::str::from_utf8(&self.data[range]).ok())
+    }
+
+    /// The (width, height) of a picture resource, read from its PNG or JPEG header.
+    pub fn image_size(&self, resource: &Resource) -> Option<(u32, u32)> {
+        let data = self.data(resource);
+        match resource.kind.as_str() {
+            // the IHDR chunk always comes first, right after the 8-byte signature
+            "PNG " if data.len() >= 24 => Some((read_u32(data, 16), read_u32(data, 20))),
+            "JPEG" => jpeg_size(data),
+            _ => None,
+        }
+    }
+
+    /// How many times a sound should play according to the `Loop` chunk, where 0 means forever.
+    /// Only V3 games rely on this: later ones give a repeat count to sound_effect themselves.
+    pub fn sound_repeats(&self, number: u32) -> Option<u32> {
+        let loops = self.loops.clone()?;
+        // pairs of longs: the sound number, then the repeat count
+        (loops.start..loops.end.saturating_sub(7))
+            .step_by(8)
+            .find(|&entry| read_u32(&self.data, entry) == number)
+            .map(|entry| read_u32(&self.data, entry + 4))
+    }
+
+    /// The picture resource the author chose as the cover art, from the `Fspc` chunk.
+    pub fn cover_image(&self) -> Option<&Resource> {
+        self.cover
+            .and_then(|number| self.resource(Usage::Picture, number))
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+// JPEGs keep their size in the "start of frame" segment, which may come after any number of others.
+fn jpeg_size(data: &[u8]) -> Option<(u32, u32)> {
+    let read_u16 = |offset: usize| -> Option<u32> {
+        let bytes = data.get(offset..(offset + 2))?;
+        Some(u32::from(bytes[0]) << 8 | u32::from(bytes[1]))
+    };
+
+    // skip the start of image marker
+    let mut offset = 2;
+    while *data.get(offset)? == 0xFF {
+        let marker = *data.get(offset + 1)?;
+        match marker {
+            // SOF0 to SOF15, except for the few markers in that range that mean something else
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                let height = read_u16(offset + 5)?;
+                let width = read_u16(offset + 7)?;
+                return Some((width, height));
+            }
+            // markers without a length
+            0x01 | 0xD0..=0xD9 => offset += 2,
+            _ => offset += 2 + read_u16(offset + 2)? as usize,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(header: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.extend(&(body.len() as u32).to_be_bytes());
+        bytes.extend(body);
+        if !body.len().is_multiple_of(2) {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn make_blorb() -> Vec<u8> {
+        let story = chunk(b"ZCOD", &[3, 0, 0, 1, 2]);
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend(&320u32.to_be_bytes());
+        png.extend(&200u32.to_be_bytes());
+        let picture = chunk(b"PNG ", &png);
+        let metadata = chunk(b"IFmd", b"<ifindex/>");
+        let cover = chunk(b"Fspc", &1u32.to_be_bytes());
+
+        // FORM header + IFRS + RIdx chunk with two entries
+        let index_len = 8 + 4 + 2 * 12;
+        let story_start = 12 + index_len;
+        let picture_start = story_start + story.len();
+
+        let mut index = 2u32.to_be_bytes().to_vec();
+        index.extend(b"Exec");
+        index.extend(&0u32.to_be_bytes());
+        index.extend(&(story_start as u32).to_be_bytes());
+        index.extend(b"Pict");
+        index.extend(&1u32.to_be_bytes());
+        index.extend(&(picture_start as u32).to_be_bytes());
+
+        let mut form_body = b"IFRS".to_vec();
+        form_body.extend(chunk(b"RIdx", &index));
+        form_body.extend(story);
+        form_body.extend(picture);
+        form_body.extend(metadata);
+        form_body.extend(cover);
+        chunk(b"FORM", &form_body)
+    }
+
+    #[test]
+    fn test_read_blorb() {
+        let data = make_blorb();
+        assert!(Blorb::is_blorb(&data));
+
+        let blorb = Blorb::from_bytes(data).unwrap();
+        assert_eq!(blorb.resources().len(), 2);
+        assert_eq!(blorb.story(), Some(&[3, 0, 0, 1, 2][..]));
+        assert_eq!(blorb.metadata(), Some("<ifindex/>"));
+
+        let cover = blorb.cover_image().unwrap();
+        assert_eq!(cover.kind, "PNG ");
+        assert_eq!(&blorb.data(cover)[1..4], b"PNG");
+        assert_eq!(blorb.image_size(cover), Some((320, 200)));
+    }
+
+    #[test]
+    fn test_split_story() {
+        let (story, blorb) = Blorb::split_story(make_blorb()).unwrap();
+        assert_eq!(story, [3, 0, 0, 1, 2]);
+        assert!(blorb.is_some());
+
+        let (story, blorb) = Blorb::split_story(vec![3, 0, 0, 1]).unwrap();
+        assert_eq!(story, [3, 0, 0, 1]);
+        assert!(blorb.is_none());
+
+        // a Glulx game, or an empty story chunk, is no good to us
+        for (kind, body) in [(b"GLUL", &[3, 0, 0, 1, 2][..]), (b"ZCOD", &[])] {
+            let mut index = 1u32.to_be_bytes().to_vec();
+            index.extend(b"Exec");
+            index.extend(&0u32.to_be_bytes());
+            index.extend(&36u32.to_be_bytes());
+            let mut form_body = b"IFRS".to_vec();
+            form_body.extend(chunk(b"RIdx", &index));
+            form_body.extend(chunk(kind, body));
+            assert_eq!(
+                Blorb::split_story(chunk(b"FORM", &form_body)).unwrap_err(),
+                BlorbError::NoStory
+            );
+        }
+    }
+
+    #[test]
+    fn test_truncated_blorb() {
+        let mut data = make_blorb();
+        data.truncate(data.len() - 10);
+        assert!(matches!(
+            Blorb::from_bytes(data),
+            Err(BlorbError::Truncated { .. })
+        ));
+        assert_eq!(
+            Blorb::from_bytes(vec![3, 0, 0, 1]).unwrap_err(),
+            BlorbError::NotBlorb
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Breakpoints, watchpoints and single-stepping, for poking at a game while it runs. Attach a
+//! debugger with `Zmachine::debugger`, and `step` returns `Step::Break` whenever it stops; call
+//! `step` again to carry on.
+//!
+//! Interrupt routines (for timed input and finished sounds) run all at once, so the debugger
+//! can't stop inside them: their breakpoints are ignored, and any watched values they change
+//! show up at the next instruction after.
+
+use std::collections::BTreeSet;
+use std::mem;
+
+/// Something to keep an eye on: the debugger stops whenever its value changes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Watch {
+    /// A global variable, counting from 0 (so it's variable 16 + n).
+    Global(u8),
+    /// A range of bytes in memory.
+    Memory { start: usize, length: usize },
+    /// One of an object's attributes.
+    Attribute { object: u16, attribute: u16 },
+}
+
+/// Why the debugger stopped the machine.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Stop {
+    /// Someone called `Debugger::interrupt`.
+    Interrupted,
+    /// We're single-stepping, and just ran an instruction.
+    Stepped,
+    /// The next instruction has a breakpoint on it.
+    Breakpoint(usize),
+    /// A routine with a breakpoint was just called, and the pc is at its first instruction.
+    Routine(usize),
+    /// A watched value changed while running the instruction at `pc`. There's one value for
+    /// globals and attributes, and one for each byte of a memory range.
+    Watch {
+        watch: Watch,
+        pc: usize,
+        old: Vec<u16>,
+        new: Vec<u16>,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    routine_breakpoints: BTreeSet<usize>,
+    // we don't know a watch's starting value until the machine next checks on it
+    watches: Vec<(Watch, Option<Vec<u16>>)>,
+    stepping: bool,
+    pending: Option<Stop>,
+    // the breakpoint we just stopped at, which shouldn't stop us again when we carry on
+    skip_breakpoint: Option<usize>,
+    last_pc: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    /// Stop before running the instruction at `pc`. Returns false if there was one already.
+    pub fn add_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Stop whenever the routine at this (unpacked) address gets called.
+    pub fn add_routine_breakpoint(&mut self, addr: usize) -> bool {
+        self.routine_breakpoints.insert(addr)
+    }
+
+    pub fn remove_routine_breakpoint(&mut self, addr: usize) -> bool {
+        self.routine_breakpoints.remove(&addr)
+    }
+
+    pub fn routine_breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.routine_breakpoints.iter().copied()
+    }
+
+    /// Returns false if we were already watching it.
+    pub fn watch(&mut self, watch: Watch) -> bool {
+        if self.watches.iter().any(|(w, _)| *w == watch) {
+            return false;
+        }
+        self.watches.push((watch, None));
+        true
+    }
+
+    pub fn unwatch(&mut self, watch: &Watch) -> bool {
+        let count = self.watches.len();
+        self.watches.retain(|(w, _)| w != watch);
+        self.watches.len() != count
+    }
+
+    pub fn watches(&self) -> impl Iterator<Item = &Watch> {
+        self.watches.iter().map(|(watch, _)| watch)
+    }
+
+    /// While stepping, every call to `step` runs just one instruction.
+    pub fn set_stepping(&mut self, stepping: bool) {
+        self.stepping = stepping;
+    }
+
+    pub fn is_stepping(&self) -> bool {
+        self.stepping
+    }
+
+    /// Stop as soon as possible: before the next instruction runs.
+    pub fn interrupt(&mut self) {
+        self.pending = Some(Stop::Interrupted);
+    }
+
+    // Called when a routine starts running.
+    pub(crate) fn called(&mut self, addr: usize) {
+        if self.routine_breakpoints.contains(&addr) {
+            self.pending = Some(Stop::Routine(addr));
+        }
+    }
+
+    // Called before each instruction, with a way to read the current value of a watch. `resumed`
+    // is true for the first instruction after the machine was paused, which hasn't run yet.
+    pub(crate) fn check(
+        &mut self,
+        pc: usize,
+        resumed: bool,
+        mut read: impl FnMut(&Watch) -> Vec<u16>,
+    ) -> Option<Stop> {
+        if let Some(stop) = self.pending.take() {
+            return Some(stop);
+        }
+
+        for (watch, seen) in &mut self.watches {
+            let value = read(watch);
+            match seen {
+                Some(old) if *old != value => {
+                    let old = mem::replace(old, value.clone());
+                    return Some(Stop::Watch {
+                        watch: watch.clone(),
+                        pc: self.last_pc,
+                        old,
+                        new: value,
+                    });
+                }
+                _ => *seen = Some(value),
+            }
+        }
+
+        let skip = self.skip_breakpoint.take();
+        if self.breakpoints.contains(&pc) && skip != Some(pc) {
+            self.skip_breakpoint = Some(pc);
+            return Some(Stop::Breakpoint(pc));
+        }
+
+        if self.stepping && !resumed {
+            return Some(Stop::Stepped);
+        }
+
+        self.last_pc = pc;
+        None
+    }
+}
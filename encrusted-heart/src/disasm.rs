@@ -0,0 +1,425 @@
+//! A disassembler in the style of txd: finds the routines in a story file and lists their
+//! instructions, with labels for the routines and for the targets of branches and jumps.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::instruction::{Branch, Instruction, Opcode, Operand};
+use crate::traits::UI;
+use crate::zmachine::Zmachine;
+
+// The longest an instruction can be, not counting any inline text: a double-VAR opcode with 8 large
+// operands, a store and a long branch. We don't try to decode anything closer to the end of the
+// file than this, since the decoder assumes the whole instruction is there.
+const MAX_INSTRUCTION_LENGTH: usize = 22;
+
+/// A routine and its instructions, in address order.
+pub struct Routine {
+    pub addr: usize,
+    /// The starting values of the locals, which are always 0 from V5 on.
+    pub locals: Vec<u16>,
+    pub instructions: Vec<Instruction>,
+}
+
+impl Routine {
+    /// The address just past the last instruction.
+    pub fn end(&self) -> usize {
+        self.instructions.last().map_or(self.addr, |i| i.next)
+    }
+}
+
+pub struct Listing {
+    version: u8,
+    routines: Vec<Routine>,
+    // routine and branch target addresses, numbered in order
+    routine_labels: BTreeMap<usize, usize>,
+    labels: BTreeMap<usize, usize>,
+    // the routine called by the call instruction at each address
+    calls: BTreeMap<usize, usize>,
+}
+
+impl Listing {
+    /// Find all the routines we can, starting from the main routine and following calls. Other
+    /// constants that unpack to somewhere in high memory might be routines too, and so might any
+    /// gaps between the routines we know about: games keep plenty of routines in tables and
+    /// object properties, where we'd never see them called.
+    pub fn new<ZUI: UI>(zvm: &Zmachine<ZUI>) -> Listing {
+        let version = zvm.version();
+        let high_memory = read_word(zvm, 0x04).unwrap_or(0) as usize;
+        let initial_pc = read_word(zvm, 0x06).unwrap_or(0);
+
+        // V6 starts with a call to the main routine, and earlier versions just start running
+        // somewhere after a routine header with no locals.
+        let main = if version == 6 {
+            zvm.unpack_routine_addr(initial_pc)
+        } else {
+            (initial_pc as usize).saturating_sub(1)
+        };
+
+        let mut calls = vec![main];
+        let mut candidates = vec![];
+        let mut rejected = BTreeSet::new();
+        let mut routines: BTreeMap<usize, Routine> = BTreeMap::new();
+        let mut call_targets = BTreeMap::new();
+
+        loop {
+            let (addr, called) = match calls.pop() {
+                Some(addr) => (addr, true),
+                None => match candidates
+                    .pop()
+                    .or_else(|| first_gap(&routines, &rejected, version))
+                {
+                    Some(addr) => (addr, false),
+                    None => break,
+                },
+            };
+            if routines.contains_key(&addr) || rejected.contains(&addr) {
+                continue;
+            }
+            // we follow all the calls we're sure about before guessing, so a guess that lands
+            // inside a routine we already know about must be something else
+            if !called
+                && routines
+                    .range(..addr)
+                    .next_back()
+                    .is_some_and(|(_, r)| addr < r.end())
+            {
+                continue;
+            }
+
+            let routine = match read_routine(zvm, addr) {
+                Some(routine) => routine,
+                None => {
+                    rejected.insert(addr);
+                    continue;
+                }
+            };
+            let overlaps = routines
+                .range(addr..)
+                .next()
+                .is_some_and(|(&next, _)| routine.end() > next);
+            if !called && overlaps {
+                rejected.insert(addr);
+                continue;
+            }
+
+            for instr in &routine.instructions {
+                if instr.does_call(version) {
+                    if let Some(&Operand::Large(packed)) = instr.operands.first() {
+                        let target = zvm.unpack_routine_addr(packed);
+                        call_targets.insert(instr.addr, target);
+                        // a guess that turns out wrong could call anything, so it's all guesswork
+                        // from here
+                        if called {
+                            calls.push(target);
+                        } else {
+                            candidates.push(target);
+                        }
+                    }
+                    continue;
+                }
+                // print_paddr takes a packed address too, but for a string
+                if instr.opcode == Opcode::OP1_141 {
+                    continue;
+                }
+                for operand in &instr.operands {
+                    if let Operand::Large(packed) = *operand {
+                        let addr = zvm.unpack_routine_addr(packed);
+                        if addr >= high_memory && addr != 0 {
+                            candidates.push(addr);
+                        }
+                    }
+                }
+            }
+            routines.insert(addr, routine);
+        }
+
+        let routine_labels = routines
+            .keys()
+            .enumerate()
+            .map(|(i, &a)| (a, i + 1))
+            .collect();
+        let targets: BTreeSet<usize> = routines
+            .values()
+            .flat_map(|r| r.instructions.iter())
+            .filter_map(target)
+            .collect();
+        let labels = targets
+            .into_iter()
+            .enumerate()
+            .map(|(i, a)| (a, i + 1))
+            .collect();
+
+        Listing {
+            version,
+            routines: routines.into_values().collect(),
+            routine_labels,
+            labels,
+            calls: call_targets,
+        }
+    }
+
+    /// All the routines we found, in address order.
+    pub fn routines(&self) -> &[Routine] {
+        &self.routines
+    }
+
+    /// The routine that starts at `addr`, if we found one.
+    pub fn routine(&self, addr: usize) -> Option<&Routine> {
+        self.routines
+            .binary_search_by_key(&addr, |r| r.addr)
+            .ok()
+            .map(|i| &self.routines[i])
+    }
+
+    /// The name we give the routine at `addr` in the listing, eg. `R0001`.
+    pub fn routine_label(&self, addr: usize) -> Option<String> {
+        self.routine_labels.get(&addr).map(|n| format!("R{:04}", n))
+    }
+
+    /// The name we give the branch or jump target at `addr`, eg. `L0001`.
+    pub fn label(&self, addr: usize) -> Option<String> {
+        self.labels.get(&addr).map(|n| format!("L{:04}", n))
+    }
+
+    /// The strings that follow the code in high memory, with their addresses. These are the
+    /// ones the game prints with print_paddr, along with anything else it keeps there.
+    pub fn strings<ZUI: UI>(&self, zvm: &Zmachine<ZUI>) -> Vec<(usize, String)> {
+        let scale = packing_scale(self.version);
+        let mut addr = match self.routines.last() {
+            Some(routine) => routine.end().next_multiple_of(scale),
+            None => return vec![],
+        };
+
+        let mut strings = vec![];
+        // the last word of a string has its top bit set; if we run out of file first, it
+        // wasn't really a string
+        while let Some(end) = (addr..)
+            .step_by(2)
+            .map_while(|word| zvm.read_memory(word, 2))
+            .position(|word| word[0] & 0x80 != 0)
+            .map(|words| addr + words * 2 + 2)
+        {
+            strings.push((addr, zvm.read_zstring(addr).unwrap_or_default()));
+            addr = end.next_multiple_of(scale);
+        }
+        strings
+    }
+
+    fn write_instruction(&self, f: &mut fmt::Formatter, instr: &Instruction) -> fmt::Result {
+        let label = self.label(instr.addr).map(|l| l + ":").unwrap_or_default();
+        write!(
+            f,
+            "{:7}{:5x}: {:16}",
+            label,
+            instr.addr,
+            Instruction::name(instr.opcode, self.version)
+        )?;
+
+        // the first operand of a call or jump gets replaced by the label of where it goes
+        let destination = match self.calls.get(&instr.addr) {
+            Some(&routine) => self.routine_label(routine),
+            None if instr.opcode == Opcode::OP1_140 => target(instr).and_then(|t| self.label(t)),
+            None => None,
+        };
+        for (i, operand) in instr.operands.iter().enumerate() {
+            match &destination {
+                Some(name) if i == 0 => write!(f, " {}", name)?,
+                _ => write!(f, " {}", operand)?,
+            }
+        }
+
+        if let Some(store) = instr.store {
+            write!(f, " -> {}", Operand::Variable(store))?;
+        }
+
+        if let Some(Branch {
+            address,
+            returns,
+            condition,
+        }) = instr.branch
+        {
+            let negate = if condition == 0 { "~" } else { "" };
+            match (address.and_then(|a| self.label(a)), returns) {
+                (Some(label), _) => write!(f, " ?{}{}", negate, label)?,
+                (None, Some(0)) => write!(f, " ?{}rfalse", negate)?,
+                (None, Some(_)) => write!(f, " ?{}rtrue", negate)?,
+                (None, None) => {}
+            }
+        }
+
+        if let Some(text) = &instr.text {
+            write!(f, " {:?}", text)?;
+        }
+
+        writeln!(f)
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for routine in &self.routines {
+            let locals: Vec<String> = routine
+                .locals
+                .iter()
+                .map(|l| format!("{:04x}", l))
+                .collect();
+            writeln!(
+                f,
+                "Routine {} at {:05x}, {} locals ({})\n",
+                self.routine_label(routine.addr).unwrap_or_default(),
+                routine.addr,
+                routine.locals.len(),
+                locals.join(", ")
+            )?;
+            for instr in &routine.instructions {
+                self.write_instruction(f, instr)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+// The first packed address between two routines that we haven't tried yet, or else the one right
+// after the last routine, in case the code carries on.
+fn first_gap(
+    routines: &BTreeMap<usize, Routine>,
+    rejected: &BTreeSet<usize>,
+    version: u8,
+) -> Option<usize> {
+    let scale = packing_scale(version);
+    let mut known = routines.values();
+    let mut previous = known.next()?;
+    for next in known {
+        let mut addr = previous.end().next_multiple_of(scale);
+        while addr < next.addr {
+            if !rejected.contains(&addr) {
+                return Some(addr);
+            }
+            addr += scale;
+        }
+        previous = next;
+    }
+
+    Some(previous.end().next_multiple_of(scale)).filter(|addr| !rejected.contains(addr))
+}
+
+fn packing_scale(version: u8) -> usize {
+    match version {
+        1..=3 => 2,
+        4..=7 => 4,
+        _ => 8,
+    }
+}
+
+fn read_word<ZUI>(zvm: &Zmachine<ZUI>, addr: usize) -> Option<u16> {
+    zvm.read_memory(addr, 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+// Where a branch or jump goes, if it goes somewhere other than the next instruction.
+fn target(instr: &Instruction) -> Option<usize> {
+    if let Some(Branch {
+        address: Some(addr),
+        ..
+    }) = instr.branch
+    {
+        return Some(addr);
+    }
+
+    match (instr.opcode, instr.operands.first()) {
+        (Opcode::OP1_140, Some(&Operand::Large(offset))) => {
+            Some((instr.next as isize + offset as i16 as isize - 2) as usize)
+        }
+        _ => None,
+    }
+}
+
+// Decode instructions until we reach one that doesn't carry on to the next, and that isn't
+// skipped over by any branch or jump. Anything that doesn't decode isn't a routine after all.
+fn read_routine<ZUI: UI>(zvm: &Zmachine<ZUI>, addr: usize) -> Option<Routine> {
+    let count = *zvm.read_memory(addr, 1)?.first()? as usize;
+    if count > 15 {
+        return None;
+    }
+
+    let (locals, mut pc) = if zvm.version() <= 4 {
+        let values = zvm.read_memory(addr + 1, count * 2)?;
+        let locals = values
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect();
+        (locals, addr + 1 + count * 2)
+    } else {
+        (vec![0; count], addr + 1)
+    };
+
+    let mut furthest = pc;
+    let mut instructions = vec![];
+    loop {
+        zvm.read_memory(pc, MAX_INSTRUCTION_LENGTH)?;
+        let instr = zvm.decode_instruction(pc).ok()?;
+        if let Some(target) = target(&instr) {
+            furthest = furthest.max(target);
+        }
+        pc = instr.next;
+        let ends = !instr.advances();
+        instructions.push(instr);
+
+        if ends && pc > furthest {
+            break;
+        }
+    }
+
+    Some(Routine {
+        addr,
+        locals,
+        instructions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use crate::traits::BaseUI;
+
+    #[test]
+    fn test_disassemble() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+        let listing = Listing::new(&zvm);
+
+        // the main routine comes right before the initial pc
+        let main = listing.routine(zvm.pc() - 1).unwrap();
+        assert!(main.locals.is_empty());
+        assert_eq!(main.instructions[0].addr, zvm.pc());
+        assert!(listing.routines().len() > 300);
+
+        // routines don't overlap, and every branch lands inside its own routine
+        for pair in listing.routines().windows(2) {
+            assert!(pair[0].end() <= pair[1].addr);
+        }
+        for routine in listing.routines() {
+            for instr in &routine.instructions {
+                if let Some(addr) = target(instr) {
+                    assert!(routine.addr < addr && addr < routine.end());
+                    assert!(listing.label(addr).is_some());
+                }
+            }
+        }
+
+        let text = listing.to_string();
+        assert!(text.starts_with(&format!(
+            "Routine R0001 at {:05x}",
+            listing.routines()[0].addr
+        )));
+        assert!(text.contains("call             R"));
+        assert!(text.contains("print_ret"));
+
+        let strings = listing.strings(&zvm);
+        assert!(strings.iter().any(|(_, s)| s == "Nothing happens.\n"));
+        assert!(strings[0].0 >= listing.routines().last().unwrap().end());
+    }
+}
@@ -93,6 +93,15 @@ impl Frame {
         }
     }
 
+    pub fn locals(&self) -> &[u16] {
+        &self.locals
+    }
+
+    /// The routine's evaluation stack, with the top of the stack last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
     pub fn read_local(&self, index: u8) -> u16 {
         let index = index as usize;
 
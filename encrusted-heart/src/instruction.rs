@@ -4,7 +4,7 @@ use std::fmt;
 
 #[allow(non_camel_case_types)]
 #[repr(u16)]
-#[derive(Clone, Copy, Debug, PartialEq, TryFromPrimitive)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, TryFromPrimitive)]
 pub enum Opcode {
     // Two-operand opcodes (2OP)
     OP2_1 = 1,
@@ -169,6 +169,8 @@ pub struct Instruction {
     pub branch: Option<Branch>,
     pub text: Option<String>,
     pub next: usize,
+    /// The version of the story it came from, which some opcodes' names depend on.
+    pub version: u8,
 }
 
 impl Instruction {
@@ -415,8 +417,7 @@ impl fmt::Display for Instruction {
             f,
             "{:5x}: {:16}",
             self.addr,
-            // TODO: preserve the version?
-            Instruction::name(self.opcode, 5)
+            Instruction::name(self.opcode, self.version)
         )?;
 
         for op in &self.operands {
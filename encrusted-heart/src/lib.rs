@@ -3,11 +3,18 @@ extern crate rand;
 extern crate serde;
 extern crate serde_json;
 
+pub mod babel;
+pub mod blorb;
 pub mod buffer;
+pub mod debug;
+pub mod disasm;
 pub mod frame;
 pub mod instruction;
+pub mod object;
 pub mod options;
 pub mod quetzal;
+pub mod trace;
 pub mod traits;
+pub mod window;
 pub mod zmachine;
 pub mod zscii;
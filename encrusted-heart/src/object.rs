@@ -0,0 +1,131 @@
+//! A read-only view of the object tree as it is right now, for frontends and tools that want to
+//! know where things are without decoding the object table themselves. Get one with
+//! `Zmachine::object`, `Zmachine::objects` or `Zmachine::player`.
+//!
+//! V1-3 and V4+ lay their objects out differently (32 or 48 attributes, byte or word links, and
+//! different property headers), which the view takes care of.
+
+use std::fmt;
+use std::iter;
+
+use crate::zmachine::Zmachine;
+
+/// An object in the game's object table. Like the `Zmachine` it borrows, it doesn't change: take
+/// another look after running the game some more.
+pub struct ObjectView<'a, ZUI> {
+    zvm: &'a Zmachine<ZUI>,
+    number: u16,
+}
+
+impl<'a, ZUI> ObjectView<'a, ZUI> {
+    pub(crate) fn new(zvm: &'a Zmachine<ZUI>, number: u16) -> Option<Self> {
+        if number == 0 || number > zvm.get_total_object_count() {
+            None
+        } else {
+            Some(ObjectView { zvm, number })
+        }
+    }
+
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    /// The object's short name, which can be empty. Also empty if the name can't be decoded.
+    pub fn name(&self) -> String {
+        self.zvm.get_object_name(self.number).unwrap_or_default()
+    }
+
+    pub fn parent(&self) -> Option<Self> {
+        ObjectView::new(self.zvm, self.zvm.get_parent(self.number))
+    }
+
+    /// The first of the object's children.
+    pub fn child(&self) -> Option<Self> {
+        ObjectView::new(self.zvm, self.zvm.get_child(self.number))
+    }
+
+    /// The next child of the object's parent.
+    pub fn sibling(&self) -> Option<Self> {
+        ObjectView::new(self.zvm, self.zvm.get_sibling(self.number))
+    }
+
+    /// The object's children, in the order the game keeps them (usually the newest first).
+    pub fn children(&self) -> impl Iterator<Item = ObjectView<'a, ZUI>> {
+        // a broken tree could loop, but it can't have more children than there are objects
+        let limit = self.zvm.get_total_object_count() as usize;
+        iter::successors(self.child(), |object| object.sibling()).take(limit)
+    }
+
+    /// The object's parent, its parent's parent and so on up to the top of the tree.
+    pub fn ancestors(&self) -> impl Iterator<Item = ObjectView<'a, ZUI>> {
+        let limit = self.zvm.get_total_object_count() as usize;
+        iter::successors(self.parent(), |object| object.parent()).take(limit)
+    }
+
+    /// Whether this object is somewhere inside `other`, however deeply.
+    pub fn is_in(&self, other: &ObjectView<'a, ZUI>) -> bool {
+        self.ancestors().any(|object| object.number == other.number)
+    }
+
+    /// How many attributes objects have: 32 in V1-3 and 48 from V4 on.
+    pub fn attribute_count(&self) -> u16 {
+        self.zvm.get_attribute_count()
+    }
+
+    /// Whether the object has an attribute set. Attributes past the last one are never set.
+    pub fn has_attribute(&self, attribute: u16) -> bool {
+        attribute < self.attribute_count() && self.zvm.test_attr(self.number, attribute) == Ok(1)
+    }
+
+    /// The attributes the object has set, in increasing order.
+    pub fn attributes(&self) -> Vec<u16> {
+        self.zvm.get_object_attributes(self.number)
+    }
+
+    /// The raw data of one of the object's properties, or `None` if it doesn't have it (in which
+    /// case the game would use the property's default).
+    pub fn property(&self, number: u8) -> Option<&'a [u8]> {
+        self.properties()
+            .into_iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, data)| data)
+    }
+
+    /// The number and raw data of each property the object has, in the order they're stored
+    /// (decreasing property number).
+    pub fn properties(&self) -> Vec<(u8, &'a [u8])> {
+        self.zvm.get_object_properties(self.number)
+    }
+}
+
+// derive would want ZUI to be Clone and Copy too
+impl<ZUI> Clone for ObjectView<'_, ZUI> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<ZUI> Copy for ObjectView<'_, ZUI> {}
+
+impl<ZUI> PartialEq for ObjectView<'_, ZUI> {
+    fn eq(&self, other: &Self) -> bool {
+        self.number == other.number && std::ptr::eq(self.zvm, other.zvm)
+    }
+}
+
+impl<ZUI> Eq for ObjectView<'_, ZUI> {}
+
+impl<ZUI> fmt::Debug for ObjectView<'_, ZUI> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ObjectView")
+            .field("number", &self.number)
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+impl<ZUI> fmt::Display for ObjectView<'_, ZUI> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.number)
+    }
+}
@@ -1,18 +1,20 @@
 #[derive(Debug)]
 pub struct Options {
-    pub log_instructions: bool,
     pub rand_seed: [u32; 4],
     pub dimensions: (u16, u16),
     pub undo_limit: usize,
+    /// How many instructions `step` runs before it gives up and returns `Step::Yield`, so a game
+    /// stuck in a loop can't hang the frontend. `None` means no limit.
+    pub instruction_budget: Option<usize>,
 }
 
 impl Options {
     pub fn default() -> Options {
         Options {
-            log_instructions: false,
             rand_seed: [90, 111, 114, 107],
             dimensions: (80, 255), // 255 is "infinite scrolling"
             undo_limit: 16,
+            instruction_budget: None,
         }
     }
 }
@@ -1,12 +1,129 @@
 use crate::frame::Frame;
 use std::fmt;
 
+/// What a save says about the story it came from, all copied from the story's header. Other
+/// interpreters check these match before they restore, and so do we.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StoryId {
+    pub release: u16,
+    pub serial: [u8; 6],
+    pub chksum: u16,
+}
+
+impl StoryId {
+    /// Describes how `self` (from a save) differs from the running story, or `None` if the save
+    /// belongs to it.
+    pub fn mismatch(&self, story: &StoryId) -> Option<String> {
+        if self.release != story.release || self.serial != story.serial {
+            Some(format!("it was made by {}, but this is {}", self, story))
+        } else if self.chksum != story.chksum {
+            Some(format!(
+                "its checksum is {:#06x}, but the story's is {:#06x}; the story file may have been modified",
+                self.chksum, story.chksum
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for StoryId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "release {} / serial {}",
+            self.release,
+            String::from_utf8_lossy(&self.serial)
+        )
+    }
+}
+
+/// Why a save couldn't be read. Saves that were only partly written (say the device lost power)
+/// usually come out as `Truncated`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum QuetzalError {
+    /// The file isn't an IFF form of type IFZS.
+    NotQuetzal,
+    /// Something needed more bytes than were left: `needed` bytes starting at `offset` in the
+    /// file, when only `available` were there.
+    Truncated {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// A chunk's id isn't 4 printable ASCII characters, which means we've lost track of where
+    /// the chunks are.
+    BadChunkId { offset: usize },
+    /// A required chunk is missing (or empty, for Stks): IFhd, Stks, or one of CMem and UMem.
+    MissingChunk(&'static str),
+    /// There's more than one IFhd or Stks chunk, or more than one CMem or UMem between them, so
+    /// it isn't clear which to believe.
+    DuplicateChunk(&'static str),
+    /// The IFhd chunk is shorter than its 13 bytes.
+    ShortHeader(usize),
+    /// The frame starting at `offset` in the Stks chunk runs past the end of it.
+    BadFrame { offset: usize },
+    /// The CMem chunk ends with a 0 that's missing the length byte after it.
+    BadCMem,
+    /// The saved memory would be longer than the story's dynamic memory.
+    MemoryTooLong { length: usize, max: usize },
+    /// The UMem chunk is shorter than the story's dynamic memory.
+    MemoryTooShort { length: usize, expected: usize },
+}
+
+impl fmt::Display for QuetzalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuetzalError::NotQuetzal => write!(f, "it isn't a Quetzal save file"),
+            QuetzalError::Truncated {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "it's cut short: {} bytes were needed at {:#x}, but only {} were left",
+                needed, offset, available
+            ),
+            QuetzalError::BadChunkId { offset } => {
+                write!(f, "there's a garbled chunk id at {:#x}", offset)
+            }
+            QuetzalError::MissingChunk(chunk) => write!(f, "it has no {} chunk", chunk),
+            QuetzalError::DuplicateChunk(chunk) => {
+                write!(f, "it has more than one {} chunk", chunk)
+            }
+            QuetzalError::ShortHeader(length) => {
+                write!(f, "its IFhd chunk is {} bytes instead of 13", length)
+            }
+            QuetzalError::BadFrame { offset } => {
+                write!(f, "the stack frame at {:#x} in Stks is cut short", offset)
+            }
+            QuetzalError::BadCMem => write!(f, "its CMem chunk ends in the middle of a run"),
+            QuetzalError::MemoryTooLong { length, max } => write!(
+                f,
+                "its memory is {} bytes, but the story only has {}",
+                length, max
+            ),
+            QuetzalError::MemoryTooShort { length, expected } => write!(
+                f,
+                "its memory is {} bytes, but the story has {}",
+                length, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QuetzalError {}
+
 #[derive(Debug)]
 pub struct QuetzalSave {
     pub pc: usize,
     pub memory: Vec<u8>,
     pub frames: Vec<Frame>,
-    pub chksum: u16,
+    pub story: StoryId,
+    /// The chunks we don't use ourselves, like the author (AUTH), annotations (ANNO) and other
+    /// interpreters' own data (IntD), in the order they came. Pass them back to `make` to keep
+    /// them when the game is saved again.
+    pub chunks: Vec<(String, Vec<u8>)>,
 }
 
 impl QuetzalSave {
@@ -15,48 +132,84 @@ impl QuetzalSave {
             pc: 0,
             memory: Vec::new(),
             frames: Vec::new(),
-            chksum: 0,
+            story: StoryId::default(),
+            chunks: Vec::new(),
         }
     }
 
-    pub fn from_bytes(save_data: &[u8], original_dynamic: &[u8]) -> QuetzalSave {
+    pub fn from_bytes(
+        save_data: &[u8],
+        original_dynamic: &[u8],
+    ) -> Result<QuetzalSave, QuetzalError> {
         let mut save = QuetzalSave::empty();
 
-        let (form_header, _, form_body) = QuetzalSave::read_chunk(save_data);
-        if form_header != "FORM" {
-            panic!("Can't find FORM header, bad save file?")
+        let (form_header, _, form_body) = QuetzalSave::read_chunk(save_data, 0)?;
+        if form_header != "FORM" || !form_body.starts_with(b"IFZS") {
+            return Err(QuetzalError::NotQuetzal);
         }
 
-        let chunks = &form_body[4..]; // skip the IFZS string at the start
-        let mut offset = 0;
+        // chunks start after the IFZS string, and their offsets count from the start of the file
+        let end = 8 + form_body.len();
+        let mut offset = 12;
+        let mut has_ifhd = false;
+        let mut has_stks = false;
+        let mut has_memory = false;
 
-        while offset < chunks.len() {
-            let next = &chunks[offset..];
-            let (header, length, body) = QuetzalSave::read_chunk(next);
+        while offset < end {
+            let (header, length, body) = QuetzalSave::read_chunk(&save_data[..end], offset)?;
 
             if header == "IFhd" {
-                save.read_ifhd_body(body);
+                if has_ifhd {
+                    return Err(QuetzalError::DuplicateChunk("IFhd"));
+                }
+                save.read_ifhd_body(body)?;
+                has_ifhd = true;
             } else if header == "Stks" {
-                save.read_stks_body(body);
+                if has_stks {
+                    return Err(QuetzalError::DuplicateChunk("Stks"));
+                }
+                save.read_stks_body(body)?;
+                has_stks = true;
             } else if header == "CMem" {
-                save.read_cmem_body(body, original_dynamic);
+                if has_memory {
+                    return Err(QuetzalError::DuplicateChunk("CMem"));
+                }
+                save.read_cmem_body(body, original_dynamic)?;
+                has_memory = true;
             } else if header == "UMem" {
+                if has_memory {
+                    return Err(QuetzalError::DuplicateChunk("UMem"));
+                }
+                if body.len() > original_dynamic.len() {
+                    return Err(QuetzalError::MemoryTooLong {
+                        length: body.len(),
+                        max: original_dynamic.len(),
+                    });
+                } else if body.len() < original_dynamic.len() {
+                    return Err(QuetzalError::MemoryTooShort {
+                        length: body.len(),
+                        expected: original_dynamic.len(),
+                    });
+                }
                 save.memory = body.to_vec();
-            }
-
-            // skip any other unnecessary chunks
-            if save.is_complete() {
-                break;
+                has_memory = true;
+            } else {
+                // hang on to everything else, so it isn't lost when the game saves again
+                save.chunks.push((header, body.to_vec()));
             }
 
             offset += length;
         }
 
-        if !save.is_complete() {
-            panic!("Save file doesn't contain all necessary fields: {}", &save);
+        if !has_ifhd {
+            Err(QuetzalError::MissingChunk("IFhd"))
+        } else if save.frames.is_empty() {
+            Err(QuetzalError::MissingChunk("Stks"))
+        } else if !has_memory {
+            Err(QuetzalError::MissingChunk("CMem"))
+        } else {
+            Ok(save)
         }
-
-        save
     }
 
     pub fn make(
@@ -64,46 +217,59 @@ impl QuetzalSave {
         current: &[u8],
         original: &[u8],
         frames: &[Frame],
-        chksum: u16,
-        release: u16,
-        serial: &[u8],
+        story: &StoryId,
+        chunks: &[(String, Vec<u8>)],
     ) -> Vec<u8> {
         let mut save_data = Vec::new();
         let mut form_body = Vec::from(&b"IFZS"[..]); // Form starts w/ "IFZS"
 
-        let ifhd_body = QuetzalSave::make_ifhd_body(release, serial, chksum, pc);
+        let ifhd_body = QuetzalSave::make_ifhd_body(story, pc);
         let stks_body = QuetzalSave::make_stks_body(frames);
         let cmem_body = QuetzalSave::make_cmem_body(current, original);
 
         QuetzalSave::write_chunk(&mut form_body, "IFhd", &ifhd_body[..]);
         QuetzalSave::write_chunk(&mut form_body, "Stks", &stks_body[..]);
         QuetzalSave::write_chunk(&mut form_body, "CMem", &cmem_body[..]);
+        for (header, body) in chunks {
+            QuetzalSave::write_chunk(&mut form_body, header, body);
+        }
         QuetzalSave::write_chunk(&mut save_data, "FORM", &form_body[..]);
 
         save_data
     }
 
-    fn read_chunk(data: &[u8]) -> (String, usize, &[u8]) {
-        let header = String::from_utf8_lossy(&data[0..4]).into_owned();
+    // Reads the chunk at `offset`, returning its id, its length including the header and any
+    // padding, and its body.
+    fn read_chunk(data: &[u8], offset: usize) -> Result<(String, usize, &[u8]), QuetzalError> {
+        let truncated = |needed| QuetzalError::Truncated {
+            offset,
+            needed,
+            available: data.len() - offset,
+        };
+
+        let header = data.get(offset..offset + 8).ok_or_else(|| truncated(8))?;
+        if !header[0..4].iter().all(|&b| (0x20..0x7F).contains(&b)) {
+            return Err(QuetzalError::BadChunkId { offset });
+        }
+        let id = String::from_utf8_lossy(&header[0..4]).into_owned();
 
-        let mut body_length = 0;
-        body_length += (data[4] as usize) << 24;
-        body_length += (data[5] as usize) << 16;
-        body_length += (data[6] as usize) << 8;
-        body_length += data[7] as usize;
+        // the length is 32 bits, which might not fit alongside the header in a 32 bit usize
+        let body_length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let chunk_length = usize::try_from(body_length)
+            .ok()
+            .and_then(|length| length.checked_add(8))
+            .ok_or_else(|| truncated(usize::MAX))?;
 
-        let body = &data[8..(8 + body_length)];
+        let body = data
+            .get(offset + 8..)
+            .and_then(|rest| rest.get(..chunk_length - 8))
+            .ok_or_else(|| truncated(chunk_length))?;
 
         // chunks get padded with an empty 0 byte if they have an odd length
-        let mut chunk_length = 8 + body_length;
-        if chunk_length % 2 != 0 {
-            chunk_length += 1;
-        }
-
-        (header, chunk_length, body)
+        Ok((id, chunk_length + chunk_length % 2, body))
     }
 
-    fn write_chunk(bytes: &mut Vec<u8>, header: &str, body: &[u8]) {
+    pub(crate) fn write_chunk(bytes: &mut Vec<u8>, header: &str, body: &[u8]) {
         // 4 bytes for the header string
         bytes.extend(&header.as_bytes()[0..4]);
 
@@ -124,42 +290,39 @@ impl QuetzalSave {
         }
     }
 
-    fn is_complete(&self) -> bool {
-        self.pc != 0 && !self.frames.is_empty() && !self.memory.is_empty()
-    }
+    fn read_ifhd_body(&mut self, bytes: &[u8]) -> Result<(), QuetzalError> {
+        if bytes.len() < 13 {
+            return Err(QuetzalError::ShortHeader(bytes.len()));
+        }
+
+        // 1 word for release
+        self.story.release = u16::from(bytes[0]) << 8 | u16::from(bytes[1]);
 
-    fn read_ifhd_body(&mut self, bytes: &[u8]) {
-        // 1 word for release (skip, we don't use)
-        // 6 bytes for serial number (also skip)
+        // 6 bytes for serial number
+        self.story.serial.copy_from_slice(&bytes[2..8]);
 
         // 1 word for checksum
-        self.chksum += u16::from(bytes[8]) << 8;
-        self.chksum += u16::from(bytes[9]);
+        self.story.chksum = u16::from(bytes[8]) << 8 | u16::from(bytes[9]);
 
         // 3 bytes for PC
-        self.pc += usize::from(bytes[10]) << 16;
-        self.pc += usize::from(bytes[11]) << 8;
-        self.pc += usize::from(bytes[12]);
+        self.pc =
+            usize::from(bytes[10]) << 16 | usize::from(bytes[11]) << 8 | usize::from(bytes[12]);
+        Ok(())
     }
 
-    fn make_ifhd_body(release: u16, serial: &[u8], chksum: u16, pc: usize) -> [u8; 13] {
+    fn make_ifhd_body(story: &StoryId, pc: usize) -> [u8; 13] {
         let mut bytes = [0; 13]; // ifhd body is always 13 bytes
 
         // 1 word for release
-        bytes[0] = ((release & 0xFF00) >> 8) as u8;
-        bytes[1] = (release & 0x00FF) as u8;
+        bytes[0] = ((story.release & 0xFF00) >> 8) as u8;
+        bytes[1] = (story.release & 0x00FF) as u8;
 
         // 6 bytes for serial number
-        bytes[2] = serial[0];
-        bytes[3] = serial[1];
-        bytes[4] = serial[2];
-        bytes[5] = serial[3];
-        bytes[6] = serial[4];
-        bytes[7] = serial[5];
+        bytes[2..8].copy_from_slice(&story.serial);
 
         // 1 word for checksum
-        bytes[8] = ((chksum & 0xFF00) >> 8) as u8;
-        bytes[9] = (chksum & 0x00FF) as u8;
+        bytes[8] = ((story.chksum & 0xFF00) >> 8) as u8;
+        bytes[9] = (story.chksum & 0x00FF) as u8;
 
         // 3 bytes for PC
         bytes[10] = ((pc & 0xFF_0000) >> 16) as u8;
@@ -169,39 +332,50 @@ impl QuetzalSave {
         bytes
     }
 
-    fn read_cmem_body(&mut self, compressed: &[u8], original: &[u8]) {
-        let mut uncompressed = Vec::new();
+    fn read_cmem_body(&mut self, compressed: &[u8], original: &[u8]) -> Result<(), QuetzalError> {
+        // Non-zero bytes are bytes that are different than the original. Zero bytes are followed by
+        // a length byte, indicating how many more 0s come after them. Work out how much memory that
+        // adds up to first, so a corrupt save can't make us go past the end.
+        let mut length = 0;
         let mut index = 0;
 
         while index < compressed.len() {
-            let byte = compressed[index];
-
-            // non-zero bytes are bytes that are different that the original
-            if byte != 0 {
-                uncompressed.push(byte);
+            if compressed[index] != 0 {
+                length += 1;
                 index += 1;
-            // zero bytes are followed by a length byte, indicating how many
-            // 0s go between the previous non-zero byte (above) and the next
             } else {
                 // +1 for the 0 before the length byte:
-                let length = compressed[index + 1] as usize;
-                uncompressed.extend(vec![0; length + 1]);
+                let zeros = compressed.get(index + 1).ok_or(QuetzalError::BadCMem)?;
+                length += *zeros as usize + 1;
                 index += 2;
             }
         }
 
-        let difference = original.len() - uncompressed.len();
+        if length > original.len() {
+            return Err(QuetzalError::MemoryTooLong {
+                length,
+                max: original.len(),
+            });
+        }
+
+        // XOR the changes with the original to restore; anything past the end hasn't changed
+        let mut memory = original.to_vec();
+        let mut addr = 0;
+        index = 0;
 
-        if difference > 0 {
-            uncompressed.extend(vec![0; difference]);
+        while index < compressed.len() {
+            if compressed[index] != 0 {
+                memory[addr] ^= compressed[index];
+                addr += 1;
+                index += 1;
+            } else {
+                addr += compressed[index + 1] as usize + 1;
+                index += 2;
+            }
         }
 
-        // XOR uncompressed with original to restore
-        self.memory = uncompressed
-            .iter()
-            .zip(original.iter())
-            .map(|(a, b)| a ^ b)
-            .collect();
+        self.memory = memory;
+        Ok(())
     }
 
     fn make_cmem_body(current: &[u8], original: &[u8]) -> Vec<u8> {
@@ -239,36 +413,55 @@ impl QuetzalSave {
             .0 // <- compressed is the first field in the tuple
     }
 
-    fn read_stks_body(&mut self, bytes: &[u8]) {
+    fn read_stks_body(&mut self, bytes: &[u8]) -> Result<(), QuetzalError> {
         let mut frames = Vec::new();
         let mut offset = 0;
 
-        while offset < bytes.len() - 1 {
+        // a frame is at least 8 bytes, so any bytes left over are the start of a broken one
+        while offset < bytes.len() {
+            let header = bytes
+                .get(offset..offset + 8)
+                .ok_or(QuetzalError::BadFrame { offset })?;
+
             // variable lengths found here:
-            let num_locals = bytes[offset + 3] & 0b0000_1111;
-            let mut stack_length = 0;
-            stack_length += u16::from(bytes[offset + 6]) << 8;
-            stack_length += u16::from(bytes[offset + 7]);
+            let num_locals = header[3] & 0b0000_1111;
+            let stack_length = u16::from_be_bytes([header[6], header[7]]);
 
             // locals start @ byte 8, stack values start after locals
             // each value is a 2 byte word
             let end = offset + 8 + num_locals as usize * 2 + stack_length as usize * 2;
 
-            let slice = &bytes[offset..end];
-            let frame = Frame::from_bytes(slice);
+            let slice = bytes
+                .get(offset..end)
+                .ok_or(QuetzalError::BadFrame { offset })?;
+            let mut frame = Frame::from_bytes(slice);
+
+            // the first frame (a dummy one, or V6's main routine) has nowhere to return a result
+            // to, whatever its flags say (other interpreters write them all as 0)
+            if frames.is_empty() {
+                frame.store = None;
+            }
 
             frames.push(frame);
-            offset += slice.len();
+            offset = end;
         }
 
         self.frames = frames;
+        Ok(())
     }
 
     fn make_stks_body(frames: &[Frame]) -> Vec<u8> {
         let mut bytes = Vec::new();
 
-        for frame in frames.iter() {
-            bytes.extend(frame.to_vec());
+        for (i, frame) in frames.iter().enumerate() {
+            let mut frame_bytes = frame.to_vec();
+
+            // write the first frame's flags all clear, the way other interpreters do
+            if i == 0 {
+                frame_bytes[3] &= !0b0001_0000;
+            }
+
+            bytes.extend(frame_bytes);
         }
 
         bytes
@@ -279,9 +472,10 @@ impl fmt::Display for QuetzalSave {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(
             f,
-            "PC: {:#04x} Chksum: {:#04x} Mem Length: {}",
+            "PC: {:#04x} Story: {} Chksum: {:#04x} Mem Length: {}",
             self.pc,
-            self.chksum,
+            self.story,
+            self.story.chksum,
             self.memory.len()
         )?;
 
@@ -296,6 +490,92 @@ impl fmt::Display for QuetzalSave {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // A save with the given chunks, plus an empty IFhd if they don't have one.
+    fn form(chunks: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut form_body = b"IFZS".to_vec();
+        if !chunks.iter().any(|(id, _)| *id == "IFhd") {
+            QuetzalSave::write_chunk(&mut form_body, "IFhd", &[0; 13]);
+        }
+        for (id, body) in chunks {
+            QuetzalSave::write_chunk(&mut form_body, id, body);
+        }
+        let mut bytes = vec![];
+        QuetzalSave::write_chunk(&mut bytes, "FORM", &form_body);
+        bytes
+    }
+
+    fn random_save(rng: &mut XorShiftRng) -> (QuetzalSave, Vec<u8>) {
+        let length = rng.gen_range(1, 600);
+        let original: Vec<u8> = (0..length).map(|_| rng.gen()).collect();
+        // mostly unchanged, so there are runs of zeros to compress
+        let memory = original
+            .iter()
+            .map(|&b| {
+                if rng.gen_weighted_bool(8) {
+                    rng.gen()
+                } else {
+                    b
+                }
+            })
+            .collect();
+
+        let frames = (0..rng.gen_range(1, 6))
+            .map(|i| {
+                let locals = (0..rng.gen_range(0, 16)).map(|_| rng.gen()).collect();
+                let arguments: Vec<u16> = (0..rng.gen_range(0, 8)).map(|_| rng.gen()).collect();
+                let store = if i > 0 && rng.gen() {
+                    Some(rng.gen())
+                } else {
+                    None
+                };
+                let mut frame = Frame::new(rng.gen_range(0, 0x100_0000), store, locals, &[]);
+                frame.arg_count = arguments.len() as u8;
+                for _ in 0..rng.gen_range(0, 20) {
+                    frame.stack_push(rng.gen());
+                }
+                frame
+            })
+            .collect();
+
+        let chunks = (0..rng.gen_range(0, 3))
+            .map(|_| {
+                let id = (0..4)
+                    .map(|_| rng.gen_range(0x20u8, 0x7F) as char)
+                    .collect();
+                let body = (0..rng.gen_range(0, 40)).map(|_| rng.gen()).collect();
+                (id, body)
+            })
+            .filter(|(id, _): &(String, Vec<u8>)| {
+                !["IFhd", "Stks", "CMem", "UMem"].contains(&&id[..])
+            })
+            .collect();
+
+        let save = QuetzalSave {
+            pc: rng.gen_range(0, 0x100_0000),
+            memory,
+            frames,
+            story: StoryId {
+                release: rng.gen(),
+                serial: rng.gen(),
+                chksum: rng.gen(),
+            },
+            chunks,
+        };
+        (save, original)
+    }
+
+    fn make(save: &QuetzalSave, original: &[u8]) -> Vec<u8> {
+        QuetzalSave::make(
+            save.pc,
+            &save.memory,
+            original,
+            &save.frames,
+            &save.story,
+            &save.chunks,
+        )
+    }
 
     #[test]
     fn test_roundtrip() {
@@ -303,15 +583,236 @@ mod tests {
         let current: &[u8] = &[1, 2, 3, 4, 0, 0, 0, 5, 6];
         let original: &[u8] = &[1, 2, 3, 0, 0, 9, 0, 5, 6];
         let frames: &[Frame] = &[Frame::new(16, None, vec![], &[])];
-        let chksum: u16 = 33;
-        let release: u16 = 235;
-        let serial: &[u8] = &[1, 2, 3, 4, 5, 6];
-
-        let bytes = QuetzalSave::make(pc, current, original, frames, chksum, release, serial);
-        let save_data = QuetzalSave::from_bytes(&bytes, original);
-        assert_eq!(save_data.chksum, chksum);
+        let story = StoryId {
+            release: 235,
+            serial: *b"123456",
+            chksum: 33,
+        };
+        let chunks = vec![(String::from("ANNO"), b"odd".to_vec())];
+
+        let bytes = QuetzalSave::make(pc, current, original, frames, &story, &chunks);
+        let save_data = QuetzalSave::from_bytes(&bytes, original).unwrap();
+        assert_eq!(save_data.story, story);
         assert_eq!(save_data.pc, pc);
         assert_eq!(save_data.memory, current);
         assert_eq!(save_data.frames, frames);
+        assert_eq!(save_data.chunks, chunks);
+    }
+
+    // Laid out the way Frotz writes them: the memory comes before the stacks, and the dummy frame
+    // has its flags all clear.
+    #[test]
+    fn test_other_interpreters() {
+        let original: &[u8] = &[0, 0, 0, 0];
+
+        let mut form_body = b"IFZS".to_vec();
+        QuetzalSave::write_chunk(
+            &mut form_body,
+            "IFhd",
+            &[
+                0, 88, b'8', b'4', b'0', b'7', b'2', b'6', 0x12, 0x34, 0x01, 0x23, 0x45,
+            ],
+        );
+        QuetzalSave::write_chunk(&mut form_body, "AUTH", b"Someone");
+        QuetzalSave::write_chunk(&mut form_body, "CMem", &[0, 1, 7]);
+        #[rustfmt::skip]
+        QuetzalSave::write_chunk(&mut form_body, "Stks", &[
+            // dummy frame, with 1 value on its stack
+            0, 0, 0, 0, 0, 0, 0, 1, 0, 9,
+            // 2 locals and 1 argument, storing to g0
+            0, 0x45, 0x67, 0x02, 0x10, 0b1, 0, 0, 0, 1, 0, 2,
+            // no locals and 3 arguments, throwing the result away
+            0, 0x56, 0x78, 0x10, 0, 0b111, 0, 0,
+        ]);
+        QuetzalSave::write_chunk(
+            &mut form_body,
+            "IntD",
+            &[b'U', b'N', b'I', b'X', 0, 0, 0, 0],
+        );
+        let mut bytes = vec![];
+        QuetzalSave::write_chunk(&mut bytes, "FORM", &form_body);
+
+        let save = QuetzalSave::from_bytes(&bytes, original).unwrap();
+        assert_eq!(save.story.release, 88);
+        assert_eq!(&save.story.serial, b"840726");
+        assert_eq!(save.story.chksum, 0x1234);
+        assert_eq!(save.pc, 0x012345);
+        assert_eq!(save.memory, [0, 0, 7, 0]);
+
+        let mut dummy = Frame::empty();
+        dummy.stack_push(9);
+        let mut caller = Frame::new(0x4567, Some(0x10), vec![0, 0], &[1]);
+        caller.write_local(1, 2);
+        let called = Frame::new(0x5678, None, vec![], &[1, 2, 3]);
+        assert_eq!(save.frames, [dummy, caller, called]);
+
+        let chunk_names: Vec<&str> = save.chunks.iter().map(|(name, _)| &name[..]).collect();
+        assert_eq!(chunk_names, ["AUTH", "IntD"]);
+
+        // saving again writes the dummy frame the same way, and keeps the other chunks
+        let again = QuetzalSave::make(
+            save.pc,
+            &save.memory,
+            original,
+            &save.frames,
+            &save.story,
+            &save.chunks,
+        );
+        let stks = QuetzalSave::make_stks_body(&save.frames);
+        assert_eq!(stks[..10], [0, 0, 0, 0, 0, 0, 0, 1, 0, 9]);
+        assert_eq!(
+            QuetzalSave::from_bytes(&again, original).unwrap().chunks,
+            save.chunks
+        );
+    }
+
+    #[test]
+    fn test_story_mismatch() {
+        let story = StoryId {
+            release: 88,
+            serial: *b"840726",
+            chksum: 0x1234,
+        };
+        assert_eq!(story.mismatch(&story), None);
+
+        let other_release = StoryId {
+            release: 119,
+            serial: *b"880429",
+            ..story
+        };
+        assert_eq!(
+            story.mismatch(&other_release).unwrap(),
+            "it was made by release 88 / serial 840726, but this is release 119 / serial 880429"
+        );
+
+        let modified = StoryId {
+            chksum: 0x4321,
+            ..story
+        };
+        assert!(story
+            .mismatch(&modified)
+            .unwrap()
+            .contains("checksum is 0x1234, but the story's is 0x4321"));
+    }
+
+    #[test]
+    fn test_random_roundtrips() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        for _ in 0..200 {
+            let (save, original) = random_save(&mut rng);
+            let bytes = make(&save, &original);
+            let restored = QuetzalSave::from_bytes(&bytes, &original).unwrap();
+
+            assert_eq!(restored.pc, save.pc);
+            assert_eq!(restored.memory, save.memory);
+            assert_eq!(restored.frames, save.frames);
+            assert_eq!(restored.story, save.story);
+            assert_eq!(restored.chunks, save.chunks);
+            assert_eq!(make(&restored, &original), bytes);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_saves() {
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+
+        for _ in 0..50 {
+            let (save, original) = random_save(&mut rng);
+            let bytes = make(&save, &original);
+
+            // every partly written save is an error, rather than a panic
+            for length in 0..bytes.len() {
+                assert!(QuetzalSave::from_bytes(&bytes[..length], &original).is_err());
+            }
+
+            // and garbled ones don't panic either, and write back out as they read
+            for _ in 0..50 {
+                let mut garbled = bytes.clone();
+                for _ in 0..rng.gen_range(1, 4) {
+                    let index = rng.gen_range(0, garbled.len());
+                    garbled[index] = rng.gen();
+                }
+                if let Ok(save) = QuetzalSave::from_bytes(&garbled, &original) {
+                    let again = QuetzalSave::from_bytes(&make(&save, &original), &original);
+                    assert_eq!(again.unwrap().frames, save.frames);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let original = [0; 4];
+        let stks: &[u8] = &[0; 8];
+        let parse = |bytes: &[u8]| QuetzalSave::from_bytes(bytes, &original).unwrap_err();
+
+        assert_eq!(parse(b"FORM\0\0\0\x04IFRS"), QuetzalError::NotQuetzal);
+        assert_eq!(
+            parse(b"FORM\0\0\0\x10IFZS"),
+            QuetzalError::Truncated {
+                offset: 0,
+                needed: 0x18,
+                available: 12
+            }
+        );
+        assert_eq!(
+            parse(&form(&[("IFh\n", &[0; 13])])[..]),
+            QuetzalError::BadChunkId { offset: 34 }
+        );
+        assert_eq!(
+            parse(&form(&[("IFhd", &[0; 12])])),
+            QuetzalError::ShortHeader(12)
+        );
+        assert_eq!(
+            parse(&form(&[("CMem", &[])])),
+            QuetzalError::MissingChunk("Stks")
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", &[0; 10])])),
+            QuetzalError::BadFrame { offset: 8 }
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", stks), ("CMem", &[1, 0])])),
+            QuetzalError::BadCMem
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", stks), ("CMem", &[0, 255, 0, 255])])),
+            QuetzalError::MemoryTooLong {
+                length: 512,
+                max: 4
+            }
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", stks), ("UMem", &[1, 2, 3])])),
+            QuetzalError::MemoryTooShort {
+                length: 3,
+                expected: 4
+            }
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", stks)])),
+            QuetzalError::MissingChunk("CMem")
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", &[0; 9])])),
+            QuetzalError::BadFrame { offset: 8 }
+        );
+        assert_eq!(
+            parse(&form(&[("IFhd", &[0; 13]), ("IFhd", &[0; 13])])),
+            QuetzalError::DuplicateChunk("IFhd")
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", stks), ("Stks", stks)])),
+            QuetzalError::DuplicateChunk("Stks")
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", stks), ("CMem", &[]), ("CMem", &[])])),
+            QuetzalError::DuplicateChunk("CMem")
+        );
+        assert_eq!(
+            parse(&form(&[("Stks", stks), ("CMem", &[]), ("UMem", &[0; 4])])),
+            QuetzalError::DuplicateChunk("UMem")
+        );
     }
 }
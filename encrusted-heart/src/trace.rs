@@ -0,0 +1,289 @@
+//! Hooks for watching the machine run, one instruction at a time. Useful for debugging the
+//! interpreter, or for diffing a game's execution against another interpreter's trace.
+
+use std::collections::VecDeque;
+use std::io::Write;
+
+use serde_json::json;
+
+use crate::instruction::{Instruction, Operand};
+use crate::zmachine::ZmachineError;
+
+/// Gets told about everything the machine does. All the methods do nothing by default, so
+/// implementations only need to pick out the events they care about.
+pub trait Tracer {
+    /// An instruction is about to run. `instr.addr` is the pc, and `args` are its operands, with
+    /// any variables already read.
+    fn instruction(&mut self, _instr: &Instruction, _args: &[u16]) {}
+    /// An instruction or a returning routine stored its result in a variable: 0 is the stack,
+    /// 1 to 15 are locals and the rest are globals.
+    fn store(&mut self, _variable: u8, _value: u16) {}
+    /// A branch instruction decided whether or not to branch.
+    fn branch(&mut self, _taken: bool) {}
+    /// A routine was called at this (unpacked) address.
+    fn call(&mut self, _addr: usize, _args: &[u16]) {}
+    /// The current routine returned.
+    fn ret(&mut self, _value: u16) {}
+    /// `step` has failed, and the machine won't get any further.
+    fn error(&mut self, _error: &ZmachineError) {}
+}
+
+fn describe_variable(variable: u8) -> String {
+    Operand::Variable(variable).to_string()
+}
+
+/// A human-readable log, with a line for each instruction and an indented line for each event.
+pub struct TextTracer<W> {
+    out: W,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(out: W) -> TextTracer<W> {
+        TextTracer { out }
+    }
+
+    fn line(&mut self, line: &str) {
+        // tracing is best-effort, and shouldn't stop the game if eg. the pipe closes
+        let _ = writeln!(self.out, "{}", line);
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn instruction(&mut self, instr: &Instruction, args: &[u16]) {
+        self.line(&format!("{} {:?}", instr, args));
+    }
+
+    fn store(&mut self, variable: u8, value: u16) {
+        self.line(&format!("    {} = {}", describe_variable(variable), value));
+    }
+
+    fn branch(&mut self, taken: bool) {
+        self.line(if taken {
+            "    branch taken"
+        } else {
+            "    branch not taken"
+        });
+    }
+
+    fn call(&mut self, addr: usize, args: &[u16]) {
+        self.line(&format!("    call {:05x} {:?}", addr, args));
+    }
+
+    fn ret(&mut self, value: u16) {
+        self.line(&format!("    return {}", value));
+    }
+
+    fn error(&mut self, error: &ZmachineError) {
+        self.line(&format!("error: {}", error));
+    }
+}
+
+/// Keeps the text log for the last few instructions, and writes it out if the machine fails.
+/// Cheap enough to leave on all the time, so there's some context for any bug reports.
+pub struct RingTracer<W> {
+    capacity: usize,
+    lines: VecDeque<String>,
+    out: W,
+}
+
+impl<W: Write> RingTracer<W> {
+    /// Remember `capacity` instructions, and dump them to `out` on error.
+    pub fn new(capacity: usize, out: W) -> RingTracer<W> {
+        RingTracer {
+            capacity,
+            lines: VecDeque::with_capacity(capacity),
+            out,
+        }
+    }
+
+    /// The log so far, oldest first, with the events for each instruction on the same line.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|line| line.as_str())
+    }
+
+    // Events get tacked onto the line for the instruction that caused them.
+    fn annotate(&mut self, event: &str) {
+        if let Some(line) = self.lines.back_mut() {
+            line.push_str("; ");
+            line.push_str(event);
+        }
+    }
+}
+
+impl<W: Write> Tracer for RingTracer<W> {
+    fn instruction(&mut self, instr: &Instruction, args: &[u16]) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(format!("{} {:?}", instr, args));
+    }
+
+    fn store(&mut self, variable: u8, value: u16) {
+        self.annotate(&format!("{} = {}", describe_variable(variable), value));
+    }
+
+    fn branch(&mut self, taken: bool) {
+        self.annotate(if taken { "branch" } else { "no branch" });
+    }
+
+    fn call(&mut self, addr: usize, args: &[u16]) {
+        self.annotate(&format!("call {:05x} {:?}", addr, args));
+    }
+
+    fn ret(&mut self, value: u16) {
+        self.annotate(&format!("return {}", value));
+    }
+
+    fn error(&mut self, error: &ZmachineError) {
+        let _ = writeln!(
+            self.out,
+            "error: {}\nlast {} instructions:",
+            error,
+            self.lines.len()
+        );
+        for line in &self.lines {
+            let _ = writeln!(self.out, "{}", line);
+        }
+    }
+}
+
+/// One JSON object per line, for each instruction and event: easy to diff, or to load into
+/// another tool. Numbers are all decimal.
+pub struct JsonTracer<W> {
+    out: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> JsonTracer<W> {
+        JsonTracer { out }
+    }
+
+    fn record(&mut self, value: serde_json::Value) {
+        let _ = writeln!(self.out, "{}", value);
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn instruction(&mut self, instr: &Instruction, args: &[u16]) {
+        self.record(json!({
+            "pc": instr.addr,
+            "opcode": Instruction::name(instr.opcode, instr.version),
+            "args": args,
+        }));
+    }
+
+    fn store(&mut self, variable: u8, value: u16) {
+        self.record(json!({ "store": variable, "value": value }));
+    }
+
+    fn branch(&mut self, taken: bool) {
+        self.record(json!({ "branch": taken }));
+    }
+
+    fn call(&mut self, addr: usize, args: &[u16]) {
+        self.record(json!({ "call": addr, "args": args }));
+    }
+
+    fn ret(&mut self, value: u16) {
+        self.record(json!({ "return": value }));
+    }
+
+    fn error(&mut self, error: &ZmachineError) {
+        self.record(json!({ "error": error.to_string() }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::Opcode;
+    use arrayvec::ArrayVec;
+
+    fn instruction(addr: usize, opcode: Opcode) -> Instruction {
+        Instruction {
+            addr,
+            opcode,
+            operands: ArrayVec::new(),
+            store: None,
+            branch: None,
+            text: None,
+            next: addr + 1,
+            version: 5,
+        }
+    }
+
+    #[test]
+    fn test_ring_tracer() {
+        let mut out = vec![];
+        {
+            let mut tracer = RingTracer::new(2, &mut out);
+            tracer.instruction(&instruction(0x100, Opcode::OP0_180), &[]);
+            tracer.instruction(&instruction(0x101, Opcode::OP2_20), &[1, 2]);
+            tracer.store(0, 3);
+            tracer.instruction(&instruction(0x105, Opcode::OP1_128), &[3]);
+            tracer.branch(false);
+
+            let lines: Vec<_> = tracer.lines().collect();
+            assert_eq!(lines.len(), 2);
+            assert!(lines[0].contains("add") && lines[0].ends_with("; sp = 3"));
+            assert!(lines[1].contains("jz") && lines[1].ends_with("; no branch"));
+
+            tracer.error(&ZmachineError::BadSave("oops".to_string()));
+        }
+
+        let dump = String::from_utf8(out).unwrap();
+        assert!(dump.starts_with("error: "));
+        assert!(!dump.contains("nop"));
+        assert_eq!(dump.lines().count(), 4);
+    }
+
+    #[test]
+    fn test_json_tracer() {
+        let mut out = vec![];
+        {
+            let mut tracer = JsonTracer::new(&mut out);
+            tracer.instruction(&instruction(0x101, Opcode::OP2_20), &[1, 2]);
+            tracer.store(16, 3);
+        }
+
+        let records: Vec<serde_json::Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            [
+                json!({ "pc": 0x101, "opcode": "add", "args": [1, 2] }),
+                json!({ "store": 16, "value": 3 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_version_names() {
+        // some opcodes changed meaning in V4 and V5, so they're named for the story's version
+        let pop = Instruction {
+            version: 3,
+            ..instruction(0x100, Opcode::OP0_185)
+        };
+        let catch = instruction(0x100, Opcode::OP0_185);
+
+        let mut out = vec![];
+        {
+            let mut tracer = JsonTracer::new(&mut out);
+            tracer.instruction(&pop, &[]);
+            tracer.instruction(&catch, &[]);
+        }
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().next().unwrap().contains(r#""opcode":"pop""#));
+        assert!(out.lines().nth(1).unwrap().contains(r#""opcode":"catch""#));
+
+        let mut tracer = RingTracer::new(1, vec![]);
+        tracer.instruction(&pop, &[]);
+        assert!(tracer.lines().next().unwrap().contains("pop"));
+    }
+}
@@ -1,36 +1,140 @@
+use std::collections::BTreeMap;
+
+use crate::window::WindowProps;
+
 #[derive(Ord, PartialOrd, Eq, PartialEq, Debug, Copy, Clone)]
 pub enum Window {
     Lower,
     Upper,
+    /// V6 windows 2 to 7. (Windows 0 and 1 are `Lower` and `Upper`.)
+    Other(u8),
+}
+
+/// A picture from the story's Blorb file, for V6's picture opcodes.
+#[derive(Debug, Clone, Copy)]
+pub struct Picture<'a> {
+    pub number: u16,
+    /// The Blorb chunk type: `PNG ` or `JPEG`.
+    pub kind: &'a str,
+    pub data: &'a [u8],
+    /// (height, width), if we could find it in the image header.
+    pub size: Option<(u16, u16)>,
+}
+
+/// A sound from the story's Blorb file, for `sound_effect`.
+#[derive(Debug, Clone, Copy)]
+pub struct Sound<'a> {
+    pub number: u16,
+    /// The Blorb chunk type: `AIFF`, `OGGV` or `MOD `.
+    pub kind: &'a str,
+    pub data: &'a [u8],
+}
+
+/// A sound_effect call, as recorded by `BaseUI`.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum SoundEvent {
+    Beep {
+        high: bool,
+    },
+    Prepare(u16),
+    Play {
+        number: u16,
+        volume: u8,
+        repeats: u8,
+    },
+    Stop(u16),
+    Unload(u16),
+}
+
+/// A colour set by `set_colour` or `set_true_colour`.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum Colour {
+    /// Whatever the frontend normally uses for text or background.
+    Default,
+    /// A 15-bit colour, with five bits each for blue, green and red from high to low.
+    True(u16),
+}
+
+impl Colour {
+    /// Translate one of the standard colour numbers, using the true colours suggested by the spec.
+    /// Returns `None` for 0, which means "keep the current colour", and for unknown numbers.
+    pub fn from_standard(number: u16) -> Option<Colour> {
+        let true_colour = match number {
+            1 => return Some(Colour::Default),
+            2 => 0x0000,  // black
+            3 => 0x001D,  // red
+            4 => 0x0340,  // green
+            5 => 0x03BD,  // yellow
+            6 => 0x59A0,  // blue
+            7 => 0x7C1F,  // magenta
+            8 => 0x77A0,  // cyan
+            9 => 0x7FFF,  // white
+            10 => 0x5AD6, // light grey
+            11 => 0x4631, // medium grey
+            12 => 0x2D6B, // dark grey
+            _ => return None,
+        };
+        Some(Colour::True(true_colour))
+    }
+
+    /// The colour as 8-bit red, green and blue values.
+    pub fn rgb(self) -> Option<(u8, u8, u8)> {
+        match self {
+            Colour::Default => None,
+            Colour::True(c) => {
+                let channel = |shift: u16| {
+                    let five_bits = (c >> shift) & 0x1F;
+                    (five_bits * 255 / 31) as u8
+                };
+                Some((channel(0), channel(5), channel(10)))
+            }
+        }
+    }
+
+    /// The brightness of the colour, for frontends that can only show shades of gray.
+    pub fn gray(self) -> Option<u8> {
+        self.rgb().map(|(r, g, b)| {
+            let luma = 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+            (luma / 1000) as u8
+        })
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
-pub struct TextStyle(pub u16);
+pub struct TextStyle {
+    pub flags: u16,
+    pub foreground: Colour,
+    pub background: Colour,
+}
 
 impl TextStyle {
     pub fn new(flags: u16) -> TextStyle {
-        TextStyle(flags)
+        TextStyle {
+            flags,
+            foreground: Colour::Default,
+            background: Colour::Default,
+        }
     }
     pub fn roman(self) -> bool {
-        self.0 == 0
+        self.flags == 0
     }
     pub fn reverse_video(self) -> bool {
-        self.0 & 0b0001 != 0
+        self.flags & 0b0001 != 0
     }
     pub fn bold(self) -> bool {
-        self.0 & 0b0010 != 0
+        self.flags & 0b0010 != 0
     }
     pub fn italic(self) -> bool {
-        self.0 & 0b0100 != 0
+        self.flags & 0b0100 != 0
     }
     pub fn fixed_pitch(self) -> bool {
-        self.0 & 0b1000 != 0
+        self.flags & 0b1000 != 0
     }
 }
 
 impl Default for TextStyle {
     fn default() -> Self {
-        TextStyle(0)
+        TextStyle::new(0)
     }
 }
 
@@ -44,6 +148,37 @@ pub trait UI {
     fn set_window(&mut self, _window: Window) {}
     fn erase_window(&mut self, _window: Window) {}
     fn set_cursor(&mut self, _line: u16, _column: u16) {}
+    /// Erase from the cursor to the end of the line in the current window.
+    fn erase_line(&mut self) {}
+    /// The cursor position in the current window, as (line, column) counting from 1.
+    fn get_cursor(&self) -> (u16, u16) {
+        (1, 1)
+    }
+
+    /// V6: a window has been moved, resized, or had its margins or attributes changed.
+    fn update_window(&mut self, _window: Window, _props: &WindowProps) {}
+    /// V6: draw a picture with its top-left corner at (y, x) in the current window.
+    fn draw_picture(&mut self, _picture: &Picture, _y: u16, _x: u16) {}
+    /// V6: fill the area a picture would cover with the background colour.
+    fn erase_picture(&mut self, _picture: &Picture, _y: u16, _x: u16) {}
+
+    /// sound_effect 1 and 2: a high or low bleep.
+    fn beep(&mut self, _high: bool) {}
+    /// Load a sound ahead of time, so it can start promptly when it's played.
+    fn prepare_sound(&mut self, _sound: &Sound) {}
+    /// Start a sound, replacing any sound that's already playing. The volume runs from 1 to 8,
+    /// and a repeat count of 255 means forever. When a sound ends on its own, the frontend should
+    /// call `Zmachine::handle_sound_finished` so the game's end routine gets a chance to run.
+    fn play_sound(&mut self, _sound: &Sound, _volume: u8, _repeats: u8) {}
+    /// Stop a sound without running its end routine. Sound 0 means whatever is playing.
+    fn stop_sound(&mut self, _number: u16) {}
+    /// The game is done with a sound, so it can be unloaded. Sound 0 means all of them.
+    fn unload_sound(&mut self, _number: u16) {}
+
+    /// Output stream 2: a copy of the lower window's text, along with the player's input.
+    fn transcript(&mut self, _text: &str) {}
+    /// Output stream 4: each line or keypress the player enters, one command per call.
+    fn record_command(&mut self, _command: &str) {}
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
@@ -59,7 +194,11 @@ pub struct BaseUI {
     requested_height: usize, // https://eblong.com/zarf/glk/quote-box.html
     cleared: bool,
     output: Vec<BaseOutput>,
+    other_output: BTreeMap<u8, Vec<BaseOutput>>,
     status_line: Option<(String, String)>,
+    transcript: String,
+    commands: Vec<String>,
+    sounds: Vec<SoundEvent>,
 }
 
 impl BaseUI {
@@ -71,7 +210,11 @@ impl BaseUI {
             requested_height: 0,
             cleared: true,
             output: vec![],
+            other_output: BTreeMap::new(),
             status_line: None,
+            transcript: String::new(),
+            commands: vec![],
+            sounds: vec![],
         }
     }
 
@@ -101,23 +244,45 @@ impl BaseUI {
         self.cleared = false;
         std::mem::take(&mut self.output)
     }
+
+    /// Text printed to V6 windows other than the lower and upper ones, by window number.
+    pub fn drain_other_output(&mut self) -> Vec<(u8, Vec<BaseOutput>)> {
+        std::mem::take(&mut self.other_output).into_iter().collect()
+    }
+
+    pub fn drain_transcript(&mut self) -> String {
+        std::mem::take(&mut self.transcript)
+    }
+
+    pub fn drain_commands(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.commands)
+    }
+
+    pub fn drain_sounds(&mut self) -> Vec<SoundEvent> {
+        std::mem::take(&mut self.sounds)
+    }
 }
 
 impl UI for BaseUI {
     fn print(&mut self, text: &str, style: TextStyle) {
-        match self.current_window {
-            Window::Lower => match self.output.last_mut() {
+        fn push_output(output: &mut Vec<BaseOutput>, text: &str, style: TextStyle) {
+            match output.last_mut() {
                 Some(BaseOutput {
                     style: old_style,
                     content,
                 }) if *old_style == style => {
                     content.push_str(text);
                 }
-                _ => self.output.push(BaseOutput {
+                _ => output.push(BaseOutput {
                     style,
                     content: text.to_string(),
                 }),
-            },
+            }
+        }
+
+        match self.current_window {
+            Window::Lower => push_output(&mut self.output, text, style),
+            Window::Other(n) => push_output(self.other_output.entry(n).or_default(), text, style),
             Window::Upper => {
                 self.resolve_upper_height();
                 for c in text.chars() {
@@ -191,6 +356,9 @@ impl UI for BaseUI {
                     line.clear();
                 }
             }
+            Window::Other(n) => {
+                self.other_output.remove(&n);
+            }
         }
     }
 
@@ -203,4 +371,92 @@ impl UI for BaseUI {
             self.upper_cursor = (to_index(line), to_index(column));
         }
     }
+
+    fn erase_line(&mut self) {
+        // The lower window is a stream of text with no fixed positions, so there's nothing to do.
+        if self.current_window == Window::Upper {
+            let (line_number, column_number) = self.upper_cursor;
+            if let Some(line) = self.upper_lines.get_mut(line_number) {
+                // Anything past the end of the line is blank anyway.
+                line.truncate(column_number);
+            }
+        }
+    }
+
+    fn get_cursor(&self) -> (u16, u16) {
+        match self.current_window {
+            Window::Lower | Window::Other(_) => (1, 1),
+            Window::Upper => {
+                let (line_number, column_number) = self.upper_cursor;
+                (line_number as u16 + 1, column_number as u16 + 1)
+            }
+        }
+    }
+
+    fn beep(&mut self, high: bool) {
+        self.sounds.push(SoundEvent::Beep { high });
+    }
+
+    fn prepare_sound(&mut self, sound: &Sound) {
+        self.sounds.push(SoundEvent::Prepare(sound.number));
+    }
+
+    fn play_sound(&mut self, sound: &Sound, volume: u8, repeats: u8) {
+        self.sounds.push(SoundEvent::Play {
+            number: sound.number,
+            volume,
+            repeats,
+        });
+    }
+
+    fn stop_sound(&mut self, number: u16) {
+        self.sounds.push(SoundEvent::Stop(number));
+    }
+
+    fn unload_sound(&mut self, number: u16) {
+        self.sounds.push(SoundEvent::Unload(number));
+    }
+
+    fn transcript(&mut self, text: &str) {
+        self.transcript.push_str(text);
+    }
+
+    fn record_command(&mut self, command: &str) {
+        self.commands.push(command.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upper_text(ui: &BaseUI) -> Vec<String> {
+        ui.upper_window()
+            .iter()
+            .map(|line| line.iter().map(|(_, c)| c).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_upper_cursor() {
+        let mut ui = BaseUI::new();
+        ui.split_window(2);
+        ui.set_window(Window::Upper);
+        assert_eq!(ui.get_cursor(), (1, 1));
+
+        ui.print("Score: 10", TextStyle::default());
+        assert_eq!(ui.get_cursor(), (1, 10));
+
+        ui.set_cursor(2, 3);
+        ui.print("Moves: 5", TextStyle::default());
+        assert_eq!(ui.get_cursor(), (2, 11));
+
+        ui.set_cursor(1, 7);
+        ui.erase_line();
+        assert_eq!(upper_text(&ui), vec!["Score:", "  Moves: 5"]);
+        assert_eq!(ui.get_cursor(), (1, 7));
+
+        ui.set_window(Window::Lower);
+        assert_eq!(ui.get_cursor(), (1, 1));
+    }
 }
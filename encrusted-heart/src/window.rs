@@ -0,0 +1,96 @@
+//! The V6 window model: eight windows, each with a position, size, cursor, margins and a handful
+//! of other properties that the game can read and write with get_wind_prop and put_wind_prop.
+//! Coordinates count from 1 at the top left, in the same units as the screen size in the header.
+
+pub const Y_COORD: u16 = 0;
+pub const X_COORD: u16 = 1;
+pub const Y_SIZE: u16 = 2;
+pub const X_SIZE: u16 = 3;
+pub const Y_CURSOR: u16 = 4;
+pub const X_CURSOR: u16 = 5;
+pub const LEFT_MARGIN: u16 = 6;
+pub const RIGHT_MARGIN: u16 = 7;
+pub const NEWLINE_INTERRUPT: u16 = 8;
+pub const INTERRUPT_COUNTDOWN: u16 = 9;
+pub const TEXT_STYLE: u16 = 10;
+pub const COLOUR_DATA: u16 = 11;
+pub const FONT_NUMBER: u16 = 12;
+pub const FONT_SIZE: u16 = 13;
+pub const ATTRIBUTES: u16 = 14;
+pub const LINE_COUNT: u16 = 15;
+pub const TRUE_FOREGROUND: u16 = 16;
+pub const TRUE_BACKGROUND: u16 = 17;
+
+const PROPERTY_COUNT: usize = 18;
+
+/// The bits of the `ATTRIBUTES` property, as set by window_style.
+pub const WRAPPING: u16 = 0b0001;
+pub const SCROLLING: u16 = 0b0010;
+pub const TRANSCRIPT: u16 = 0b0100;
+pub const BUFFERED: u16 = 0b1000;
+
+pub const WINDOW_COUNT: usize = 8;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WindowProps([u16; PROPERTY_COUNT]);
+
+impl WindowProps {
+    /// A window of the given size, in the top-left corner of the screen.
+    pub fn new(height: u16, width: u16, attributes: u16) -> WindowProps {
+        let mut props = [0; PROPERTY_COUNT];
+        props[Y_COORD as usize] = 1;
+        props[X_COORD as usize] = 1;
+        props[Y_SIZE as usize] = height;
+        props[X_SIZE as usize] = width;
+        props[Y_CURSOR as usize] = 1;
+        props[X_CURSOR as usize] = 1;
+        props[FONT_NUMBER as usize] = 1;
+        // font height in the top byte, and width in the bottom
+        props[FONT_SIZE as usize] = 0x0101;
+        props[ATTRIBUTES as usize] = attributes;
+        // default colours, with -1 as "default" for the true colours
+        props[COLOUR_DATA as usize] = 0x0101;
+        props[TRUE_FOREGROUND as usize] = 0xFFFF;
+        props[TRUE_BACKGROUND as usize] = 0xFFFF;
+        WindowProps(props)
+    }
+
+    pub fn get(&self, property: u16) -> Option<u16> {
+        self.0.get(property as usize).copied()
+    }
+
+    /// Returns false if there's no such property.
+    pub fn set(&mut self, property: u16, value: u16) -> bool {
+        match self.0.get_mut(property as usize) {
+            Some(prop) => {
+                *prop = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// (y, x) of the top-left corner.
+    pub fn position(&self) -> (u16, u16) {
+        (self.0[Y_COORD as usize], self.0[X_COORD as usize])
+    }
+
+    /// (height, width)
+    pub fn size(&self) -> (u16, u16) {
+        (self.0[Y_SIZE as usize], self.0[X_SIZE as usize])
+    }
+
+    /// (y, x) relative to the window's top-left corner.
+    pub fn cursor(&self) -> (u16, u16) {
+        (self.0[Y_CURSOR as usize], self.0[X_CURSOR as usize])
+    }
+
+    /// (left, right)
+    pub fn margins(&self) -> (u16, u16) {
+        (self.0[LEFT_MARGIN as usize], self.0[RIGHT_MARGIN as usize])
+    }
+
+    pub fn attributes(&self) -> u16 {
+        self.0[ATTRIBUTES as usize]
+    }
+}
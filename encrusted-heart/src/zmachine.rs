@@ -1,21 +1,27 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
 use std::fmt;
 
 use std::str;
 
 use rand::{Rng, SeedableRng};
 
+use crate::blorb::{Blorb, Usage};
 use crate::buffer::Buffer;
 use crate::buffer::Reader;
+use crate::debug::{Debugger, Stop, Watch};
 use crate::frame::Frame;
 use crate::instruction::*;
+use crate::object::ObjectView;
 use crate::options::Options;
-use crate::quetzal::QuetzalSave;
-use crate::traits::{TextStyle, Window, UI};
+use crate::quetzal::{QuetzalSave, StoryId};
+use crate::trace::Tracer;
+use crate::traits::{Colour, Picture, Sound, TextStyle, Window, UI};
+use crate::window::{self, WindowProps};
 use crate::zscii::{ZChar, DEFAULT_UNICODE_TABLE};
 use arrayvec::ArrayVec;
 use std::cmp::Ordering;
 use std::convert::{TryFrom, TryInto};
+use std::time::Duration;
 
 #[derive(Debug)]
 enum ZStringState {
@@ -25,17 +31,47 @@ enum ZStringState {
     Tenbit2(u8),
 }
 
+// A dictionary's word separators, and where its entries are. Each entry starts with the encoded
+// word, and the entries are usually sorted by it, which lets us binary search; games can also
+// make their own unsorted dictionaries for tokenise, which they mark with a negative count.
+#[derive(Debug, Clone, Default)]
+struct Dictionary {
+    separators: Vec<u8>,
+    entry_start: usize,
+    entry_length: usize,
+    entry_count: usize,
+    sorted: bool,
+}
+
+impl Dictionary {
+    fn entry(&self, n: usize) -> usize {
+        self.entry_start + n * self.entry_length
+    }
+}
+
+/// A dictionary word, as stored in the story file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DictionaryEntry {
+    pub addr: usize,
+    pub word: String,
+    /// The bytes after the encoded word, which the game uses for its own purposes: usually
+    /// flags for the parser, like whether the word is a verb.
+    pub data: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct Object {
     number: u16,
     name: String,
+    attributes: Vec<u16>,
+    properties: Vec<(u8, Vec<u8>)>,
     children: Vec<Object>,
 }
 
 impl Object {
     fn new<T>(number: u16, zvm: &Zmachine<T>) -> Object {
         let mut name = if number > 0 {
-            zvm.get_object_name(number)
+            zvm.get_object_name(number).unwrap_or_default()
         } else {
             String::from("(Null Object)")
         };
@@ -44,14 +80,50 @@ impl Object {
             name += "(No Name)";
         }
 
+        let (attributes, properties) = if number > 0 {
+            (
+                zvm.get_object_attributes(number),
+                zvm.get_object_properties(number)
+                    .into_iter()
+                    .map(|(num, data)| (num, data.to_vec()))
+                    .collect(),
+            )
+        } else {
+            (vec![], vec![])
+        };
+
         Object {
             number,
             name,
+            attributes,
+            properties,
             children: Vec::new(),
         }
     }
 
-    fn print_tree(&self, indent: &str, mut depth: u8, is_last: bool) -> String {
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The attributes the object has set.
+    pub fn attributes(&self) -> &[u16] {
+        &self.attributes
+    }
+
+    /// The number and raw data of each property the object has, in the order they're stored.
+    pub fn properties(&self) -> &[(u8, Vec<u8>)] {
+        &self.properties
+    }
+
+    pub fn children(&self) -> &[Object] {
+        &self.children
+    }
+
+    fn print_tree(&self, indent: &str, mut depth: u8, is_last: bool, details: bool) -> String {
         let mut next = String::from(indent);
         let mut out = String::new();
 
@@ -69,11 +141,20 @@ impl Object {
             next += if is_last { "    " } else { "|   " };
         }
 
+        if details && self.number > 0 {
+            let attributes: Vec<String> = self.attributes.iter().map(|a| a.to_string()).collect();
+            out += &format!("{}    attributes: [{}]\n", next, attributes.join(", "));
+            for (number, data) in &self.properties {
+                let bytes: Vec<String> = data.iter().map(|b| format!("{:02x}", b)).collect();
+                out += &format!("{}    property {}: {}\n", next, number, bytes.join(" "));
+            }
+        }
+
         depth += 1;
 
         for (i, child) in self.children.iter().enumerate() {
             let is_last_child = i == self.children.len() - 1;
-            out += &child.print_tree(&next, depth, is_last_child);
+            out += &child.print_tree(&next, depth, is_last_child, details);
         }
 
         out
@@ -81,11 +162,16 @@ impl Object {
 
     fn to_string(&self) -> String {
         if !self.children.is_empty() {
-            self.print_tree("", 0, false)
+            self.print_tree("", 0, false, false)
         } else {
             format!("{} ({})", self.name, self.number)
         }
     }
+
+    /// Like the `Display` tree, but with each object's attributes and properties as well.
+    pub fn to_detailed_string(&self) -> String {
+        self.print_tree("", 0, false, true)
+    }
 }
 
 impl fmt::Display for Object {
@@ -118,10 +204,111 @@ pub enum Step {
     Done,
     Save(Vec<u8>),
     Restore,
-    ReadChar,
-    ReadLine,
+    /// Waiting for a single keypress. If there's a timeout, the frontend should call
+    /// `handle_timeout` whenever that much time passes without the player pressing a key.
+    ReadChar {
+        timeout: Option<Duration>,
+    },
+    /// Waiting for a line of input, with a timeout like `ReadChar`. As well as return, the line
+    /// can be ended by any of the `terminators` (function keys, arrows and the like), in which
+    /// case the frontend should pass the key to `handle_terminated_input`.
+    ///
+    /// V5+ games can leave `preloaded` text in the buffer, usually when they carry on with a line
+    /// that was interrupted. It's already on screen; the frontend should let the player keep
+    /// editing it, and pass back the whole line.
+    ReadLine {
+        timeout: Option<Duration>,
+        terminators: Vec<ZChar>,
+        preloaded: String,
+    },
+    /// The game ran through its instruction budget without needing input. Call `step` again to
+    /// carry on where it left off.
+    Yield,
+    /// The debugger stopped the game before its next instruction. Call `step` again to carry on.
+    Break(Stop),
+}
+
+/// Something went wrong while running the game. The machine is left paused at the
+/// failing instruction; frontends can report the error and offer to restore a save.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum ZmachineError {
+    /// The bytes at `pc` don't decode to any opcode in the spec.
+    UnknownOpcode { pc: usize, opcode: u16 },
+    /// A real opcode that we don't handle, at least not with this many operands.
+    UnimplementedOpcode {
+        pc: usize,
+        opcode: Opcode,
+        operands: usize,
+        version: u8,
+    },
+    /// An instruction operand was outside the range the spec allows, eg. a window number.
+    IllegalOperand {
+        pc: usize,
+        operand: &'static str,
+        value: u16,
+    },
+    /// The game tried to read or write past the end of memory.
+    MemoryOutOfBounds { pc: usize, address: usize },
+    /// The save data was corrupt, or was made by a different game.
+    BadSave(String),
+    /// The frontend handed over input while the game wasn't waiting for any.
+    NotPaused { pc: usize },
+    /// An interrupt routine (eg. for a timed read) used up the instruction budget without
+    /// returning. It's abandoned, leaving the game where it was before the interrupt, so unlike
+    /// other errors the frontend can report this one and `step` again.
+    InterruptBudget { routine: usize },
+}
+
+impl fmt::Display for ZmachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZmachineError::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {} @ {:#04x}", opcode, pc)
+            }
+            ZmachineError::UnimplementedOpcode {
+                pc,
+                opcode,
+                operands,
+                version,
+            } => write!(
+                f,
+                "Opcode not yet implemented: {} ({:?}/{}) @ {:#04x}",
+                Instruction::name(*opcode, *version),
+                opcode,
+                operands,
+                pc
+            ),
+            ZmachineError::IllegalOperand { pc, operand, value } => {
+                write!(f, "Illegal {}: {} @ {:#04x}", operand, *value as i16, pc)
+            }
+            ZmachineError::MemoryOutOfBounds { pc, address } => {
+                write!(
+                    f,
+                    "Memory access out of bounds: {:#04x} @ {:#04x}",
+                    address, pc
+                )
+            }
+            ZmachineError::BadSave(reason) => write!(f, "Invalid save: {}", reason),
+            ZmachineError::NotPaused { pc } => {
+                write!(
+                    f,
+                    "Got input, but the game isn't waiting for any @ {:#04x}",
+                    pc
+                )
+            }
+            ZmachineError::InterruptBudget { routine } => {
+                write!(
+                    f,
+                    "Interrupt routine {:#04x} ran out of instructions without returning",
+                    routine
+                )
+            }
+        }
+    }
 }
 
+impl std::error::Error for ZmachineError {}
+
 pub struct Zmachine<ZUI> {
     pub ui: ZUI,
     pub options: Options,
@@ -134,8 +321,7 @@ pub struct Zmachine<ZUI> {
     alphabet: [Vec<String>; 3],
     unicode_table: Option<Vec<char>>,
     abbrev_table: usize,
-    separators: Vec<char>,
-    dictionary: HashMap<String, usize>,
+    dictionary: Dictionary,
     frames: Vec<Frame>,
     initial_pc: usize,
     pc: usize,
@@ -145,12 +331,27 @@ pub struct Zmachine<ZUI> {
     obj_size: usize,
     attr_width: usize,
     paused_instr: Option<Instruction>,
+    paused_args: ArrayVec<u16, 8>,
     undos: VecDeque<Vec<u8>>,
+    // chunks from the last restored save that we don't use, to write back out on the next save
+    save_chunks: Vec<(String, Vec<u8>)>,
     rng: rand::XorShiftRng,
     disable_output: bool,
+    record_commands: bool,
+    command_file: VecDeque<String>,
+    input_from_file: bool,
+    current_window: Window,
+    windows: Vec<WindowProps>,
+    blorb: Option<Blorb>,
+    // the sound that's playing and the routine to call when it ends, and then an ended routine
+    // that's waiting to run
+    sound_routine: Option<(u16, u16)>,
+    sound_interrupt: Option<u16>,
     memory_output: Vec<(usize, usize)>,
     current_style: TextStyle,
     current_font: u16,
+    tracer: Option<Box<dyn Tracer + Send>>,
+    debugger: Option<Debugger>,
 }
 
 impl<ZUI> Zmachine<ZUI> {
@@ -165,7 +366,7 @@ impl<ZUI> Zmachine<ZUI> {
         let alphabet = if version >= 5 {
             Zmachine::<ZUI>::load_alphabet(&memory)
         } else {
-            Zmachine::<ZUI>::default_alphabet()
+            Zmachine::<ZUI>::default_alphabet(version)
         };
 
         let unicode_table = if version >= 5 {
@@ -204,29 +405,42 @@ impl<ZUI> Zmachine<ZUI> {
             string_offset: memory.read_word(0x2A) as usize,
             static_start,
             initial_pc,
-            pc: initial_pc,
-            frames: vec![Frame::empty()],
+            pc: 0,
+            frames: vec![],
             alphabet,
             unicode_table,
             abbrev_table: memory.read_word(0x18) as usize,
-            separators: Vec::new(),
-            dictionary: HashMap::new(),
+            dictionary: Dictionary::default(),
             prop_defaults,
             obj_table_addr: prop_defaults + (if version <= 3 { 31 } else { 63 }) * 2,
             obj_size: if version <= 3 { 9 } else { 14 },
             attr_width: if version <= 3 { 4 } else { 6 },
             paused_instr: None,
+            paused_args: ArrayVec::new(),
             undos: VecDeque::new(),
+            save_chunks: Vec::new(),
             rng: rand::SeedableRng::from_seed(options.rand_seed),
             memory,
             options,
             disable_output: false,
+            record_commands: false,
+            command_file: VecDeque::new(),
+            input_from_file: false,
+            current_window: Window::Lower,
+            windows: vec![],
+            blorb: None,
+            sound_routine: None,
+            sound_interrupt: None,
             memory_output: vec![],
             current_style: TextStyle::default(),
             current_font: 1,
+            tracer: None,
+            debugger: None,
         };
 
         zvm.set_dynamic_headers();
+        zvm.reset_windows();
+        zvm.start();
 
         // read into dictionary & word separators
         zvm.populate_dictionary();
@@ -234,16 +448,52 @@ impl<ZUI> Zmachine<ZUI> {
         zvm
     }
 
-    #[allow(dead_code)]
-    fn calculate_checksum(memory: &Buffer) -> u16 {
-        let mut sum: usize = 0;
-        let len = memory.read_byte(0x1A) as usize * 2;
+    // Before V6 the game starts at the address in the header, in a dummy frame with no routine.
+    // V6 games have a packed address for their main routine instead, which gets a real frame.
+    fn start(&mut self) {
+        self.frames.clear();
+        if self.version == 6 {
+            let routine = self.unpack_routine_addr(self.initial_pc as u16);
+            // locals start at 0 from V5 on; a bad address is an error on the first step
+            let locals = if routine < self.memory.len() {
+                vec![0; usize::from(self.memory.read_byte(routine))]
+            } else {
+                vec![]
+            };
+            self.pc = routine + 1;
+            self.frames.push(Frame::new(0, None, locals, &[]));
+        } else {
+            self.pc = self.initial_pc;
+            self.frames.push(Frame::empty());
+        }
+    }
 
-        for i in 0x40..len {
-            sum += memory.read_byte(i) as usize;
+    // The sum of the story file's bytes from 0x40 up to the length given in the header, or None
+    // if the file is shorter than that.
+    fn calculate_checksum(&self) -> Option<u16> {
+        let multiplier = match self.version {
+            1..=3 => 2,
+            4..=5 => 4,
+            _ => 8,
+        };
+        let len = match self.memory.read_word(0x1A) as usize * multiplier {
+            // very early games leave the length out, so all we can do is check the whole file
+            0 => self.memory.len(),
+            len => len,
+        };
+        if len > self.memory.len() {
+            return None;
         }
 
-        (sum % 0x1_0000) as u16
+        // The game may have written over dynamic memory by now, so use the original bytes.
+        let sum = (0x40..len).fold(0u16, |sum, i| {
+            let byte = match self.original_dynamic.get(i) {
+                Some(byte) => *byte,
+                None => self.memory.read_byte(i),
+            };
+            sum.wrapping_add(byte as u16)
+        });
+        Some(sum)
     }
 
     fn to_alphabet_entry(s: &str) -> Vec<String> {
@@ -251,10 +501,15 @@ impl<ZUI> Zmachine<ZUI> {
     }
 
     #[allow(non_snake_case)]
-    fn default_alphabet() -> [Vec<String>; 3] {
+    fn default_alphabet(version: u8) -> [Vec<String>; 3] {
         let A0 = " .....abcdefghijklmnopqrstuvwxyz";
         let A1 = " .....ABCDEFGHIJKLMNOPQRSTUVWXYZ";
-        let A2 = " ......\n0123456789.,!?_#'\"/\\-:()";
+        // V1 has no newline in A2 (it's zchar 1 instead) so everything shifts over for a '<'.
+        let A2 = if version == 1 {
+            " ......0123456789.,!?_#'\"/\\<-:()"
+        } else {
+            " ......\n0123456789.,!?_#'\"/\\-:()"
+        };
 
         [
             Zmachine::<()>::to_alphabet_entry(A0),
@@ -268,7 +523,7 @@ impl<ZUI> Zmachine<ZUI> {
         let alphabet_addr = memory.read_word(0x34) as usize;
 
         if alphabet_addr == 0 {
-            Zmachine::<()>::default_alphabet()
+            Zmachine::<()>::default_alphabet(memory.read_byte(0x00))
         } else {
             let A0 = format!(
                 " .....{}",
@@ -305,36 +560,54 @@ impl<ZUI> Zmachine<ZUI> {
         }
     }
 
-    fn unpack_routine_addr(&self, addr: u16) -> usize {
-        match self.unpack(addr) {
-            x @ 6..=7 => x + self.routine_offset * 8,
-            x => x,
+    pub(crate) fn unpack_routine_addr(&self, addr: u16) -> usize {
+        match self.version {
+            6..=7 => self.unpack(addr) + self.routine_offset * 8,
+            _ => self.unpack(addr),
         }
     }
 
     fn unpack_print_paddr(&self, addr: u16) -> usize {
-        match self.unpack(addr) {
-            x @ 6..=7 => x + self.string_offset * 8,
-            x => x,
+        match self.version {
+            6..=7 => self.unpack(addr) + self.string_offset * 8,
+            _ => self.unpack(addr),
         }
     }
 
-    fn read_global(&self, index: u8) -> u16 {
-        if index > 240 {
-            panic!("Can't read global{}!", index);
+    // NB: only valid while handling an instruction, since we assume the pc hasn't moved yet.
+    fn illegal_operand(&self, operand: &'static str, value: u16) -> ZmachineError {
+        ZmachineError::IllegalOperand {
+            pc: self.pc,
+            operand,
+            value,
         }
-
-        let addr = self.globals_addr + index as usize * 2;
-        self.memory.read_word(addr)
     }
 
-    fn write_global(&mut self, index: u8, value: u16) {
-        if index > 240 {
-            panic!("Can't write global{}!", index);
+    // Check that the `length` bytes starting at `addr` are all inside memory.
+    fn check_bounds(&self, addr: usize, length: usize) -> Result<(), ZmachineError> {
+        if addr + length > self.memory.len() {
+            Err(ZmachineError::MemoryOutOfBounds {
+                pc: self.pc,
+                address: addr.max(self.memory.len()),
+            })
+        } else {
+            Ok(())
         }
+    }
+
+    fn read_global(&self, index: u8) -> Result<u16, ZmachineError> {
+        let addr = self.globals_addr + index as usize * 2;
+        self.check_bounds(addr, 2)
+            .map_err(|_| self.illegal_operand("variable", u16::from(index) + 16))?;
+        Ok(self.memory.read_word(addr))
+    }
 
+    fn write_global(&mut self, index: u8, value: u16) -> Result<(), ZmachineError> {
         let addr = self.globals_addr + index as usize * 2;
+        self.check_bounds(addr, 2)
+            .map_err(|_| self.illegal_operand("variable", u16::from(index) + 16))?;
         self.memory.write_word(addr, value);
+        Ok(())
     }
 
     fn read_local(&self, index: u8) -> u16 {
@@ -372,64 +645,162 @@ impl<ZUI> Zmachine<ZUI> {
             .stack_peek()
     }
 
-    fn read_variable(&mut self, index: u8) -> u16 {
+    fn read_variable(&mut self, index: u8) -> Result<u16, ZmachineError> {
         #[allow(unreachable_patterns)]
         match index {
-            0 => self.stack_pop(),
-            1..=15 => self.read_local(index - 1),
+            0 => Ok(self.stack_pop()),
+            1..=15 => Ok(self.read_local(index - 1)),
             16..=255 => self.read_global(index - 16),
             _ => unreachable!(),
         }
     }
 
-    fn read_indirect_variable(&mut self, index: u8) -> u16 {
+    fn read_indirect_variable(&mut self, index: u8) -> Result<u16, ZmachineError> {
         #[allow(unreachable_patterns)]
         match index {
-            0 => self.stack_peek(),
-            1..=15 => self.read_local(index - 1),
+            0 => Ok(self.stack_peek()),
+            1..=15 => Ok(self.read_local(index - 1)),
             16..=255 => self.read_global(index - 16),
             _ => unreachable!(),
         }
     }
 
-    fn write_variable(&mut self, index: u8, value: u16) {
+    fn write_variable(&mut self, index: u8, value: u16) -> Result<(), ZmachineError> {
         match index {
             0 => self.stack_push(value),
             1..=15 => self.write_local(index - 1, value),
-            _ => self.write_global(index - 16, value),
+            _ => return self.write_global(index - 16, value),
         }
+        Ok(())
     }
 
-    fn write_indirect_variable(&mut self, index: u8, value: u16) {
+    fn write_indirect_variable(&mut self, index: u8, value: u16) -> Result<(), ZmachineError> {
         match index {
             0 => {
                 self.stack_pop();
                 self.stack_push(value);
             }
             1..=15 => self.write_local(index - 1, value),
-            _ => self.write_global(index - 16, value),
+            _ => return self.write_global(index - 16, value),
         }
+        Ok(())
     }
 
-    fn get_abbrev(&self, index: u8, abbreviation_stack: &mut Vec<u8>) -> String {
-        if index > 96 {
-            panic!("Bad abbrev index: {}", index);
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// The address of the next instruction to run.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The call stack, starting from the outermost routine.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Read a variable without side effects: variable 0 is the top of the stack, and isn't
+    /// popped. `None` if the current routine has no such local, or the stack is empty.
+    pub fn peek_variable(&self, index: u8) -> Option<u16> {
+        match index {
+            0 => self.frames.last()?.stack().last().copied(),
+            1..=15 => self
+                .frames
+                .last()?
+                .locals()
+                .get(index as usize - 1)
+                .copied(),
+            _ => self.read_global(index - 16).ok(),
+        }
+    }
+
+    /// Change a variable, replacing the top of the stack for variable 0. Returns false if there's
+    /// no such variable.
+    pub fn poke_variable(&mut self, index: u8, value: u16) -> bool {
+        if self.peek_variable(index).is_none() {
+            return false;
+        }
+        self.write_indirect_variable(index, value).is_ok()
+    }
+
+    /// A slice of memory, or `None` if it runs off the end.
+    pub fn read_memory(&self, addr: usize, length: usize) -> Option<&[u8]> {
+        let end = addr.checked_add(length)?;
+        if end > self.memory.len() {
+            return None;
+        }
+        Some(self.memory.read(addr, length))
+    }
+
+    /// Attach a debugger, if there isn't one already, and return it.
+    pub fn debugger(&mut self) -> &mut Debugger {
+        self.debugger.get_or_insert_with(Debugger::new)
+    }
+
+    /// Remove the debugger, so the game runs at full speed again.
+    pub fn detach_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.take()
+    }
+
+    fn check_debugger(&mut self, resumed: bool) -> Option<Stop> {
+        let mut debugger = self.debugger.take()?;
+        let stop = debugger.check(self.pc, resumed, |watch| self.watched_value(watch));
+        self.debugger = Some(debugger);
+        stop
+    }
+
+    // Empty if the watch doesn't point at anything real, eg. an object that doesn't exist.
+    fn watched_value(&self, watch: &Watch) -> Vec<u16> {
+        match *watch {
+            Watch::Global(index) => index
+                .checked_add(16)
+                .and_then(|variable| self.peek_variable(variable))
+                .into_iter()
+                .collect(),
+            Watch::Memory { start, length } => self
+                .read_memory(start, length)
+                .map(|bytes| bytes.iter().map(|&b| u16::from(b)).collect())
+                .unwrap_or_default(),
+            Watch::Attribute { object, attribute } => {
+                if object == 0 || object > self.get_total_object_count() {
+                    return vec![];
+                }
+                self.test_attr(object, attribute).into_iter().collect()
+            }
         }
+    }
 
+    fn get_abbrev(
+        &self,
+        index: u8,
+        abbreviation_stack: &mut Vec<u8>,
+    ) -> Result<String, ZmachineError> {
         let offset = 2 * index as usize;
+        self.check_bounds(self.abbrev_table + offset, 2)?;
         let word_addr = self.memory.read_word(self.abbrev_table + offset);
         let addr = word_addr * 2; // "Word addresses are used only in the abbreviations table" - 1.2.2
 
         self.read_zstring_impl(addr as usize, abbreviation_stack)
     }
 
-    fn read_zstring(&self, addr: usize) -> String {
+    /// Decode the zstring at `addr`. Fails if it runs off the end of memory, or uses an
+    /// abbreviation that (eventually) contains itself.
+    pub fn read_zstring(&self, addr: usize) -> Result<String, ZmachineError> {
         self.read_zstring_impl(addr, &mut vec![])
     }
 
-    fn read_zstring_impl(&self, addr: usize, abbreviation_stack: &mut Vec<u8>) -> String {
+    fn read_zstring_impl(
+        &self,
+        addr: usize,
+        abbreviation_stack: &mut Vec<u8>,
+    ) -> Result<String, ZmachineError> {
         use self::ZStringState::*;
 
+        // In V1 and V2, the shift lock characters change the alphabet we return to after each
+        // character; later versions only have single-character shifts, so this is always A0.
+        let version = self.version;
+        let mut lock = 0;
         let mut state = Alphabet(0);
         let mut index = addr;
         let mut zstring = String::new();
@@ -437,8 +808,26 @@ impl<ZUI> Zmachine<ZUI> {
         // this closure borrows the zstring while it steps through each zchar.
         // (wrapped here in its own scope to force the borrow to end)
         {
-            let mut step = |zchar: u8| {
+            let mut step = |zchar: u8| -> Result<(), ZmachineError> {
                 state = match (zchar, &state) {
+                    // V1 has a newline where the later versions have abbreviations...
+                    (1, &Alphabet(_)) if version == 1 => {
+                        zstring.push('\n');
+                        Alphabet(lock)
+                    }
+                    // ...and V2 only has the first 32 abbreviations.
+                    (1, &Alphabet(_)) if version == 2 => Abbrev(1),
+                    // shift up or down for the next zchar, or lock the shift with 4 and 5
+                    (2..=5, &Alphabet(num)) if version <= 2 => {
+                        let shifted = match zchar {
+                            2 | 4 => (num + 1) % 3,
+                            _ => (num + 2) % 3,
+                        };
+                        if zchar >= 4 {
+                            lock = shifted;
+                        }
+                        Alphabet(shifted)
+                    }
                     // the next zchar will be an abbrev index
                     (zch, &Alphabet(_)) if (1..=3).contains(&zch) => Abbrev(zch),
                     // shift character for the next zchar
@@ -450,42 +839,42 @@ impl<ZUI> Zmachine<ZUI> {
                     (_, &Tenbit2(first)) => {
                         let letter = ZChar((first << 5) + zchar).to_char(self.unicode_table());
                         zstring.push_str(&letter.to_string());
-                        Alphabet(0)
+                        Alphabet(lock)
                     }
                     // get the abbrev at this addr
                     (_, &Abbrev(num)) => {
                         // NB: recursive abbreviations are banned by the spec, but some game files clearly use them!
                         // Instead of banning them entirely, check that we're not in an infinite loop.
                         let index = (num - 1) * 32 + zchar;
-                        assert!(
-                            !abbreviation_stack.contains(&index),
-                            "Recursive abbreviation at {}",
-                            index
-                        );
+                        if abbreviation_stack.contains(&index) {
+                            return Err(self.illegal_operand("abbreviation", u16::from(index)));
+                        }
                         abbreviation_stack.push(index);
                         let abbrev = self.get_abbrev(index, abbreviation_stack);
                         abbreviation_stack.pop();
-                        zstring.push_str(&abbrev);
-                        Alphabet(0)
+                        zstring.push_str(&abbrev?);
+                        Alphabet(lock)
                     }
                     // normal case, adds letter from correct alphabet and resets to A0
                     (_, &Alphabet(num)) => {
                         let letter = &self.alphabet[num][zchar as usize];
                         zstring.push_str(letter);
-                        Alphabet(0)
+                        Alphabet(lock)
                     }
                 };
+                Ok(())
             };
 
             // 3 zchars per each 16 bit word + a "stop" bit on top
             // 0 10101 01010 10101
             loop {
+                self.check_bounds(index, 2)?;
                 let word = self.memory.read_word(index);
                 index += 2;
 
-                step(((word >> 10) & 0b0001_1111) as u8);
-                step(((word >> 5) & 0b0001_1111) as u8);
-                step((word & 0b0001_1111) as u8);
+                step(((word >> 10) & 0b0001_1111) as u8)?;
+                step(((word >> 5) & 0b0001_1111) as u8)?;
+                step((word & 0b0001_1111) as u8)?;
 
                 // stop bit
                 if word & 0x8000 != 0 {
@@ -494,7 +883,7 @@ impl<ZUI> Zmachine<ZUI> {
             }
         } // <- drop process closure, ending zstring borrow
 
-        zstring
+        Ok(zstring)
     }
 
     // reads the ENCODED byte length of a zstring, how many consecutive
@@ -549,12 +938,34 @@ impl<ZUI> Zmachine<ZUI> {
 
             let mut flags1 = self.memory.read_byte(0x01);
             flags1 |= 0b0001_1100;
+            if self.version >= 5 {
+                // Colours are available, defaulting to black text on a white background.
+                flags1 |= 0b0000_0001;
+                self.memory.write_byte(0x2C, 9);
+                self.memory.write_byte(0x2D, 2);
+            }
             self.memory.write_byte(0x01, flags1);
         }
 
-        // clear bits for features we don't support: pictures, mouse, sound, menus
+        if self.version == 6 && self.has_pictures() {
+            let flags1 = self.memory.read_byte(0x01);
+            self.memory.write_byte(0x01, flags1 | 0b0000_0010);
+        }
+        if self.version == 6 && self.has_sounds() {
+            let flags1 = self.memory.read_byte(0x01);
+            self.memory.write_byte(0x01, flags1 | 0b0010_0000);
+        }
+
+        // clear bits for features we don't support: pictures and sound (without a Blorb), mouse, menus
+        let mut unsupported = 0b0000_0001_0010_0000;
+        if !self.has_pictures() {
+            unsupported |= 0b0000_0000_0000_1000;
+        }
+        if !self.has_sounds() {
+            unsupported |= 0b0000_0000_1000_0000;
+        }
         let mut flags2 = self.memory.read_word(0x10);
-        flags2 &= 0b1111_1110_0101_0111;
+        flags2 &= !unsupported;
         self.memory.write_word(0x10, flags2);
 
         // Claim to satisfy standard version 1.0.
@@ -562,73 +973,228 @@ impl<ZUI> Zmachine<ZUI> {
         self.memory.write_byte(0x33, 0);
     }
 
+    fn has_pictures(&self) -> bool {
+        self.blorb
+            .as_ref()
+            .is_some_and(|b| b.resources().iter().any(|r| r.usage == Usage::Picture))
+    }
+
+    fn has_sounds(&self) -> bool {
+        self.blorb
+            .as_ref()
+            .is_some_and(|b| b.resources().iter().any(|r| r.usage == Usage::Sound))
+    }
+
+    // The V6 window layout at startup: window 0 fills the screen, and the rest are empty.
+    fn reset_windows(&mut self) {
+        let (width, height) = self.options.dimensions;
+        let all = window::WRAPPING | window::SCROLLING | window::TRANSCRIPT | window::BUFFERED;
+        self.windows = (0..window::WINDOW_COUNT)
+            .map(|n| match n {
+                0 => WindowProps::new(height, width, all),
+                _ => WindowProps::new(0, 0, window::WRAPPING),
+            })
+            .collect();
+    }
+
     fn populate_dictionary(&mut self) {
         let dictionary_start = self.memory.read_word(0x08) as usize;
-        let mut read = self.memory.get_reader(dictionary_start);
+        // Without a dictionary the game can still run, but it won't recognise any words.
+        let mut dictionary = match self.read_dictionary(dictionary_start) {
+            Ok(dictionary) => dictionary,
+            Err(_) => return,
+        };
 
-        let separator_count = read.byte();
+        // The spec says the entries are in order, but it's cheap to double-check that.
+        let key_length = self.encoded_word_length();
+        dictionary.sorted &= (1..dictionary.entry_count).all(|n| {
+            let previous = self.memory.read(dictionary.entry(n - 1), key_length);
+            previous <= self.memory.read(dictionary.entry(n), key_length)
+        });
+        self.dictionary = dictionary;
+    }
 
-        for _ in 0..separator_count {
-            self.separators.push(read.byte() as char);
-        }
+    fn read_dictionary(&self, addr: usize) -> Result<Dictionary, ZmachineError> {
+        self.check_bounds(addr, 1)?;
+        let separator_count = self.memory.read_byte(addr) as usize;
+        self.check_bounds(addr, separator_count + 4)?;
+
+        let mut read = self.memory.get_reader(addr + 1);
+        let separators = (0..separator_count).map(|_| read.byte()).collect();
 
         let entry_length = read.byte() as usize;
-        let entry_count = read.word() as usize;
+        let entry_count = read.word() as i16;
         let entry_start = read.position();
 
-        for n in 0..entry_count {
-            let addr = entry_start + n * entry_length;
-            let entry = self.read_zstring(addr);
+        let dictionary = Dictionary {
+            separators,
+            entry_start,
+            entry_length,
+            entry_count: entry_count.unsigned_abs() as usize,
+            sorted: entry_count >= 0,
+        };
+        self.check_bounds(entry_start, entry_length * dictionary.entry_count)?;
+        Ok(dictionary)
+    }
 
-            self.dictionary.insert(entry, addr);
+    // Dictionary words are 6 zchars long up to V3, and 9 after that.
+    fn encoded_word_length(&self) -> usize {
+        if self.version <= 3 {
+            4
+        } else {
+            6
         }
     }
 
-    fn check_dict(&self, word: &str) -> usize {
-        let length = if self.version <= 3 { 6 } else { 9 };
-        let mut short = word.to_string();
-        short.truncate(length);
+    // Encode a word the way the dictionary stores it: as zchars from the game's alphabets, cut
+    // off or padded out to the dictionary length, and packed three to a word.
+    fn encode_zstring(&self, word: &str) -> Vec<u8> {
+        let length = self.encoded_word_length() / 2 * 3;
+        // V1 and V2 shift to A1 and A2 with 2 and 3, and later versions with 4 and 5.
+        let shifts = if self.version <= 2 { [2, 3] } else { [4, 5] };
+        let mut zchars = Vec::with_capacity(length + 3);
+
+        for ch in word.chars() {
+            if zchars.len() >= length {
+                break;
+            }
+            if ch == ' ' {
+                zchars.push(0);
+                continue;
+            }
+
+            // Skip the placeholders for the special zchars, and A2's escape and newline.
+            let found = self
+                .alphabet
+                .iter()
+                .enumerate()
+                .find_map(|(num, alphabet)| {
+                    let first = if num == 2 { 7 } else { 6 };
+                    alphabet
+                        .iter()
+                        .enumerate()
+                        .skip(first)
+                        .find(|(_, letter)| letter.chars().eq([ch]))
+                        .map(|(index, _)| (num, index as u8))
+                });
+
+            match found {
+                Some((0, index)) => zchars.push(index),
+                Some((num, index)) => zchars.extend([shifts[num - 1], index]),
+                // anything else is written out in full as a 10-bit ZSCII code
+                None => {
+                    let zscii = ZChar::from_char(ch, self.unicode_table()).map_or(b'?', |z| z.0);
+                    zchars.extend([shifts[1], 6, zscii >> 5, zscii & 0b0001_1111]);
+                }
+            }
+        }
+
+        zchars.truncate(length);
+        zchars.resize(length, 5);
 
-        match self.dictionary.get(&short) {
-            Some(addr) => *addr,
-            None => 0,
+        let mut encoded = Vec::with_capacity(length / 3 * 2);
+        for (i, chunk) in zchars.chunks(3).enumerate() {
+            let mut word = (chunk[0] as u16) << 10 | (chunk[1] as u16) << 5 | chunk[2] as u16;
+            // stop bit on the last word
+            if (i + 1) * 3 == length {
+                word |= 0x8000;
+            }
+            encoded.extend(word.to_be_bytes());
         }
+        encoded
     }
 
-    fn tokenise(&mut self, text: &str, parse_addr: usize) {
-        // v1-4 start storing @ byte 1, v5+ start @2;
-        let start = if self.version <= 4 { 1 } else { 2 };
-        let mut input = String::from(text);
-        let mut found = HashMap::new();
+    // The address of the word's dictionary entry, or 0 if it's not in the dictionary.
+    fn check_dict(&self, dictionary: &Dictionary, word: &str) -> usize {
+        let encoded = self.encode_zstring(word);
+        let key = |n: usize| self.memory.read(dictionary.entry(n), encoded.len());
 
-        for sep in &self.separators {
-            input = input.replace(&sep.to_string(), &format!(" {} ", sep))
+        if !dictionary.sorted {
+            return (0..dictionary.entry_count)
+                .find(|&n| key(n) == encoded.as_slice())
+                .map_or(0, |n| dictionary.entry(n));
         }
 
-        let tokens: Vec<_> = input
-            .split_whitespace()
-            .filter(|token| !token.is_empty())
-            .map(|token| {
-                let offset = found.entry(token).or_insert(0);
-                let position = text[*offset..].find(token).unwrap();
+        let (mut low, mut high) = (0, dictionary.entry_count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match key(mid).cmp(encoded.as_slice()) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return dictionary.entry(mid),
+            }
+        }
+        0
+    }
 
-                let dict_addr = self.check_dict(token);
-                let token_addr = *offset + position + start;
+    // Split the ZSCII text into words and look them up, in the game's dictionary or the one at
+    // `dictionary_addr`. With `skip_unknown`, the parse buffer entries for words that aren't in
+    // the dictionary are left as they were.
+    fn tokenise(
+        &mut self,
+        text: &[u8],
+        parse_addr: usize,
+        dictionary_addr: usize,
+        skip_unknown: bool,
+    ) -> Result<(), ZmachineError> {
+        // v1-4 start storing @ byte 1, v5+ start @2;
+        let start = if self.version <= 4 { 1 } else { 2 };
 
-                *offset += position + token.len();
+        let user_dictionary = match dictionary_addr {
+            0 => None,
+            addr => Some(self.read_dictionary(addr)?),
+        };
+        let dictionary = user_dictionary.as_ref().unwrap_or(&self.dictionary);
+
+        // Words are split by spaces, and by separators, which are words of their own.
+        let mut words = vec![];
+        let mut word_start = None;
+        for (i, byte) in text.iter().enumerate() {
+            let is_separator = dictionary.separators.contains(byte);
+            if *byte == b' ' || is_separator {
+                if let Some(word_start) = word_start.take() {
+                    words.push(word_start..i);
+                }
+                if is_separator {
+                    words.push(i..(i + 1));
+                }
+            } else if word_start.is_none() {
+                word_start = Some(i);
+            }
+        }
+        if let Some(word_start) = word_start {
+            words.push(word_start..text.len());
+        }
 
-                (dict_addr, token.len(), token_addr)
+        let mut tokens: Vec<_> = words
+            .into_iter()
+            .map(|word| {
+                let dict_addr =
+                    self.check_dict(dictionary, &self.bytes_to_string(&text[word.clone()]));
+                (dict_addr, word.len(), word.start + start)
             })
             .collect();
 
+        // the first byte of the parse buffer is how many words it has room for
+        self.check_bounds(parse_addr, 2)?;
+        let max_tokens = self.memory.read_byte(parse_addr) as usize;
+        tokens.truncate(max_tokens);
+
+        self.check_bounds(parse_addr, 2 + 4 * tokens.len())?;
         let mut write = self.memory.get_writer(parse_addr + 1);
         write.byte(tokens.len() as u8);
 
         tokens.iter().for_each(|&(dict_addr, len, token_addr)| {
+            if dict_addr == 0 && skip_unknown {
+                write.seek(write.position() + 4);
+                return;
+            }
             write.word(dict_addr as u16);
             write.byte(len as u8);
             write.byte(token_addr as u8);
         });
+
+        Ok(())
     }
 
     fn get_object_addr(&self, object: u16) -> usize {
@@ -653,18 +1219,18 @@ impl<ZUI> Zmachine<ZUI> {
     // Object name is found at the start the object's property table:
     //   text-length   text of short name of object
     //   ---byte----   --some even number of bytes--
-    fn get_object_name(&self, object: u16) -> String {
+    pub(crate) fn get_object_name(&self, object: u16) -> Result<String, ZmachineError> {
         let addr = self.get_object_prop_table_addr(object);
         let text_length = self.memory.read_byte(addr);
 
         if text_length > 0 {
             self.read_zstring(addr + 1)
         } else {
-            String::new()
+            Ok(String::new())
         }
     }
 
-    fn get_parent(&self, object: u16) -> u16 {
+    pub(crate) fn get_parent(&self, object: u16) -> u16 {
         if object == 0 {
             return 0;
         }
@@ -688,7 +1254,7 @@ impl<ZUI> Zmachine<ZUI> {
         }
     }
 
-    fn get_sibling(&self, object: u16) -> u16 {
+    pub(crate) fn get_sibling(&self, object: u16) -> u16 {
         if object == 0 {
             return 0;
         }
@@ -712,7 +1278,7 @@ impl<ZUI> Zmachine<ZUI> {
         }
     }
 
-    fn get_child(&self, object: u16) -> u16 {
+    pub(crate) fn get_child(&self, object: u16) -> u16 {
         if object == 0 {
             return 0;
         }
@@ -794,14 +1360,13 @@ impl<ZUI> Zmachine<ZUI> {
         self.set_sibling(object, parents_first_child);
     }
 
-    fn get_total_object_count(&self) -> u16 {
+    pub(crate) fn get_total_object_count(&self) -> u16 {
         // by convention, the property table for object #1 is located AFTER
         // the last object in the object table:
         let obj_table_end = self.get_object_prop_table_addr(1);
-        let obj_size = self.attr_width + if self.version <= 3 { 3 } else { 9 } + 2;
 
         // v1-3 have a max of 255 objects, v4+ can have up to 65535
-        ((obj_table_end - self.obj_table_addr) / obj_size) as u16
+        ((obj_table_end - self.obj_table_addr) / self.obj_size) as u16
     }
 
     fn add_object_children(&self, parent: &mut Object) {
@@ -841,9 +1406,34 @@ impl<ZUI> Zmachine<ZUI> {
         root
     }
 
+    pub(crate) fn get_object_attributes(&self, object: u16) -> Vec<u16> {
+        (0..(self.attr_width * 8) as u16)
+            .filter(|&attr| self.test_attr(object, attr) == Ok(1))
+            .collect()
+    }
+
+    pub(crate) fn get_attribute_count(&self) -> u16 {
+        (self.attr_width * 8) as u16
+    }
+
+    pub(crate) fn get_object_properties(&self, object: u16) -> Vec<(u8, &[u8])> {
+        let addr = self.get_object_prop_table_addr(object);
+        let str_length = self.memory.read_byte(addr) as usize * 2;
+        let mut prop = self.read_object_prop(addr + str_length + 1);
+
+        let mut properties = vec![];
+        // properties are in decreasing order, so a broken table can't go on forever
+        while prop.num != 0 && properties.len() < 64 {
+            let data = self.memory.read(prop.addr, prop.len as usize);
+            properties.push((prop.num, data));
+            prop = self.read_object_prop(prop.next);
+        }
+        properties
+    }
+
     fn find_object(&self, name: &str) -> Option<u16> {
         for i in 1..self.get_total_object_count() + 1 {
-            if self.get_object_name(i).to_lowercase() == name.to_lowercase() {
+            if self.get_object_name(i).unwrap_or_default().to_lowercase() == name.to_lowercase() {
                 return Some(i);
             }
         }
@@ -857,9 +1447,34 @@ impl<ZUI> Zmachine<ZUI> {
             .or_else(|| self.find_object("yourself"))
     }
 
-    fn test_attr(&self, object: u16, attr: u16) -> u16 {
+    /// A view of one of the game's objects, or `None` if there's no such object.
+    pub fn object(&self, number: u16) -> Option<ObjectView<'_, ZUI>> {
+        ObjectView::new(self, number)
+    }
+
+    /// All of the game's objects, in number order.
+    pub fn objects(&self) -> impl Iterator<Item = ObjectView<'_, ZUI>> {
+        (1..=self.get_total_object_count()).filter_map(move |number| self.object(number))
+    }
+
+    /// The object the player is, going by its name. Games don't mark it any other way.
+    pub fn player(&self) -> Option<ObjectView<'_, ZUI>> {
+        self.find_yourself().and_then(|number| self.object(number))
+    }
+
+    /// Where the player is. V1-3 games keep the room in the first global, for the status line;
+    /// later games don't have to, so we look at what the player object is in.
+    pub fn player_location(&self) -> Option<ObjectView<'_, ZUI>> {
+        if self.version <= 3 {
+            self.object(self.read_global(0).ok()?)
+        } else {
+            self.player().and_then(|player| player.parent())
+        }
+    }
+
+    pub(crate) fn test_attr(&self, object: u16, attr: u16) -> Result<u16, ZmachineError> {
         if attr as usize > self.attr_width * 8 {
-            panic!("Can't test out-of-bounds attribute: {}", attr);
+            return Err(self.illegal_operand("attribute", attr));
         }
 
         let addr = self.get_object_addr(object) + attr as usize / 8;
@@ -867,15 +1482,15 @@ impl<ZUI> Zmachine<ZUI> {
         let bit = attr % 8;
 
         if byte & (128 >> bit) != 0 {
-            1
+            Ok(1)
         } else {
-            0
+            Ok(0)
         }
     }
 
-    fn set_attr(&mut self, object: u16, attr: u16) {
+    fn set_attr(&mut self, object: u16, attr: u16) -> Result<(), ZmachineError> {
         if attr as usize > self.attr_width * 8 {
-            panic!("Can't set out-of-bounds attribute: {}", attr);
+            return Err(self.illegal_operand("attribute", attr));
         }
 
         let addr = self.get_object_addr(object) + attr as usize / 8;
@@ -883,11 +1498,12 @@ impl<ZUI> Zmachine<ZUI> {
         let bit = attr % 8;
 
         self.memory.write_byte(addr, byte | (128 >> bit));
+        Ok(())
     }
 
-    fn clear_attr(&mut self, object: u16, attr: u16) {
+    fn clear_attr(&mut self, object: u16, attr: u16) -> Result<(), ZmachineError> {
         if attr as usize > self.attr_width * 8 {
-            panic!("Can't clear out-of-bounds attribute: {}", attr);
+            return Err(self.illegal_operand("attribute", attr));
         }
 
         let addr = self.get_object_addr(object) + attr as usize / 8;
@@ -895,6 +1511,7 @@ impl<ZUI> Zmachine<ZUI> {
         let bit = attr % 8;
 
         self.memory.write_byte(addr, byte & !(128 >> bit));
+        Ok(())
     }
 
     fn get_default_prop(&self, property_number: u16) -> u16 {
@@ -1042,8 +1659,8 @@ impl<ZUI> Zmachine<ZUI> {
 
     #[allow(dead_code)]
     pub fn get_current_room(&self) -> (u16, String) {
-        let num = self.read_global(0);
-        let name = self.get_object_name(num);
+        let num = self.read_global(0).unwrap_or(0);
+        let name = self.get_object_name(num).unwrap_or_default();
 
         (num, name)
     }
@@ -1055,13 +1672,13 @@ impl<ZUI> Zmachine<ZUI> {
         // 0 => score/turns
         // 1 => AM/PM
         let right = if self.memory.read_byte(0x01) & 0b0000_0010 == 0 {
-            let score = self.read_global(1) as i16;
-            let turns = self.read_global(2);
+            let score = self.read_global(1).unwrap_or(0) as i16;
+            let turns = self.read_global(2).unwrap_or(0);
 
             format!("{}/{}", score, turns)
         } else {
-            let mut hours = self.read_global(1);
-            let minutes = self.read_global(2);
+            let mut hours = self.read_global(1).unwrap_or(0);
+            let minutes = self.read_global(2).unwrap_or(0);
             let am_pm = if hours >= 12 { "PM" } else { "AM" };
             if hours > 12 {
                 hours -= 12;
@@ -1085,72 +1702,112 @@ impl<ZUI: UI> Zmachine<ZUI> {
         self.ui.set_status_bar(&left, &right);
     }
 
+    fn story_id(&self) -> StoryId {
+        let mut serial = [0; 6];
+        serial.copy_from_slice(self.memory.read(0x12, 6));
+
+        StoryId {
+            release: self.memory.read_word(0x02),
+            serial,
+            chksum: self.memory.read_word(0x1C),
+        }
+    }
+
     fn make_save_state(&self, pc: usize) -> Vec<u8> {
         // save the whole dynamic memory region (between 0 and the start of static)
         let dynamic = self.memory.slice(0, self.static_start);
         let original = self.original_dynamic.as_slice();
         let frames = &self.frames;
-        let chksum = self.memory.read_word(0x1c);
-        let release = self.memory.read_word(0x02);
-        let serial = self.memory.read(0x12, 6);
+        let story = self.story_id();
 
-        let save = QuetzalSave::make(pc, dynamic, original, frames, chksum, release, serial);
+        let save = QuetzalSave::make(pc, dynamic, original, frames, &story, &self.save_chunks);
 
         if cfg!(debug_assertions) {
-            let restored = QuetzalSave::from_bytes(&save, original);
+            let restored =
+                QuetzalSave::from_bytes(&save, original).expect("Couldn't read back our own save");
             debug_assert_eq!(pc, restored.pc);
             debug_assert_eq!(dynamic, &restored.memory[..]);
             debug_assert_eq!(frames, &restored.frames);
-            debug_assert_eq!(chksum, restored.chksum);
+            debug_assert_eq!(story, restored.story);
         }
 
         save
     }
 
-    fn restore_state(&mut self, data: &[u8]) {
-        let save = QuetzalSave::from_bytes(data, &self.original_dynamic[..]);
+    // Quetzal saves the address of the save instruction's branch data (V1-3) or store byte (V4+).
+    // Up to V4 save is a one-byte 0OP opcode, so that's the next byte either way; from V5 it's
+    // an EXT opcode with operands, and the store byte is its last.
+    fn save_pc(&self, instr: &Instruction) -> usize {
+        if self.version <= 4 {
+            instr.addr + 1
+        } else {
+            instr.next - 1
+        }
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> Result<(), ZmachineError> {
+        let save = QuetzalSave::from_bytes(data, &self.original_dynamic[..])
+            .map_err(|e| ZmachineError::BadSave(e.to_string()))?;
 
-        // verify that the save if so the right game and that the memory is ok
-        if save.chksum != self.memory.read_word(0x1C) {
-            panic!("Invalid save, checksum is different");
+        // verify that the save is for the right game and that the memory is ok
+        if let Some(reason) = save.story.mismatch(&self.story_id()) {
+            return Err(ZmachineError::BadSave(reason));
         }
 
         if self.static_start < save.memory.len() {
-            panic!("Invalid save, memory is too long");
+            return Err(ZmachineError::BadSave("memory is too long".to_string()));
+        }
+
+        if save.pc >= self.memory.len() {
+            return Err(ZmachineError::BadSave(format!(
+                "its pc {:#x} is past the end of the story",
+                save.pc
+            )));
         }
 
         self.pc = save.pc;
         self.frames = save.frames;
         self.memory.write(0, save.memory.as_slice());
+        self.save_chunks = save.chunks;
+        Ok(())
     }
 
-    fn get_arguments(&mut self, operands: &[Operand]) -> ArrayVec<u16, 8> {
+    fn get_arguments(&mut self, operands: &[Operand]) -> Result<ArrayVec<u16, 8>, ZmachineError> {
         operands
             .iter()
             .map(|operand| match *operand {
-                Operand::Small(val) => u16::from(val),
-                Operand::Large(val) => val,
+                Operand::Small(val) => Ok(u16::from(val)),
+                Operand::Large(val) => Ok(val),
                 Operand::Variable(val) => self.read_variable(val),
             })
             .collect()
     }
 
-    fn return_from_routine(&mut self, value: u16) {
+    fn return_from_routine(&mut self, value: u16) -> Result<(), ZmachineError> {
         let frame = self.frames.pop().expect("Can't pop off last frame!");
         self.pc = frame.resume;
+        self.trace(|t| t.ret(value));
 
         if let Some(index) = frame.store {
-            self.write_variable(index, value);
+            self.trace(|t| t.store(index, value));
+            self.write_variable(index, value)?;
         }
+        Ok(())
     }
 
-    fn process_branch(&mut self, branch: &Branch, next: usize, result: u16) {
+    fn process_branch(
+        &mut self,
+        branch: &Branch,
+        next: usize,
+        result: u16,
+    ) -> Result<(), ZmachineError> {
         let Branch {
             address,
             returns,
             condition,
         } = *branch;
         let result = if result >= 1 { 1 } else { 0 };
+        self.trace(|t| t.branch(result == condition));
 
         if let Some(index) = address {
             self.pc = if result == condition { index } else { next };
@@ -1158,28 +1815,34 @@ impl<ZUI: UI> Zmachine<ZUI> {
 
         if let Some(value) = returns {
             if result == condition {
-                self.return_from_routine(value);
+                self.return_from_routine(value)?;
             } else {
                 self.pc = next
             }
         }
+        Ok(())
     }
 
-    fn process_result(&mut self, instr: &Instruction, value: u16) {
+    fn process_result(&mut self, instr: &Instruction, value: u16) -> Result<(), ZmachineError> {
         // store the result if needed
         if let Some(index) = instr.store {
-            self.write_variable(index, value);
+            self.trace(|t| t.store(index, value));
+            self.write_variable(index, value)?;
         }
 
         // check if we need to branch
         if let Some(ref branch) = instr.branch {
-            self.process_branch(branch, instr.next, value);
+            self.process_branch(branch, instr.next, value)?;
         } else {
             self.pc = instr.next;
         }
+        Ok(())
     }
 
-    fn decode_instruction(&self, addr: usize) -> Instruction {
+    /// Decode the instruction at `addr`, without running it.
+    pub fn decode_instruction(&self, addr: usize) -> Result<Instruction, ZmachineError> {
+        self.check_bounds(addr, 1)?;
+
         let mut read = self.memory.get_reader(addr);
         let first = read.byte();
 
@@ -1189,10 +1852,13 @@ impl<ZUI: UI> Zmachine<ZUI> {
         fn btm_5(num: u8) -> u8 {
             num & 0b0001_1111
         }
-        fn get_opcode(code: u8, offset: u16) -> Opcode {
+        let get_opcode = |code: u8, offset: u16| {
             let num = u16::from(code) + offset;
-            Opcode::try_from(num).expect("Opcode not found!")
-        }
+            Opcode::try_from(num).map_err(|_| ZmachineError::UnknownOpcode {
+                pc: addr,
+                opcode: num,
+            })
+        };
         fn array_vec(slice: &[Operand]) -> ArrayVec<Operand, 8> {
             slice.into_iter().copied().collect()
         }
@@ -1221,44 +1887,44 @@ impl<ZUI: UI> Zmachine<ZUI> {
 
             match first {
                 0xbe => (
-                    get_opcode(read.byte(), 1000),
+                    get_opcode(read.byte(), 1000)?,
                     get_types(&[read.byte()], &mut read),
                 ),
                 0x00..=0x1f => (
-                    get_opcode(btm_5(first), 0),
+                    get_opcode(btm_5(first), 0)?,
                     array_vec(&[Small(read.byte()), Small(read.byte())]),
                 ),
                 0x20..=0x3f => (
-                    get_opcode(btm_5(first), 0),
+                    get_opcode(btm_5(first), 0)?,
                     array_vec(&[Small(read.byte()), Variable(read.byte())]),
                 ),
                 0x40..=0x5f => (
-                    get_opcode(btm_5(first), 0),
+                    get_opcode(btm_5(first), 0)?,
                     array_vec(&[Variable(read.byte()), Small(read.byte())]),
                 ),
                 0x60..=0x7f => (
-                    get_opcode(btm_5(first), 0),
+                    get_opcode(btm_5(first), 0)?,
                     array_vec(&[Variable(read.byte()), Variable(read.byte())]),
                 ),
                 0x80..=0x8f => (
-                    get_opcode(btm_4(first), 128),
+                    get_opcode(btm_4(first), 128)?,
                     array_vec(&[Large(read.word())]),
                 ),
                 0x90..=0x9f => (
-                    get_opcode(btm_4(first), 128),
+                    get_opcode(btm_4(first), 128)?,
                     array_vec(&[Small(read.byte())]),
                 ),
                 0xa0..=0xaf => (
-                    get_opcode(btm_4(first), 128),
+                    get_opcode(btm_4(first), 128)?,
                     array_vec(&[Variable(read.byte())]),
                 ),
-                0xb0..=0xbd | 0xbf => (get_opcode(btm_4(first), 176), ArrayVec::new()), // OP_0
+                0xb0..=0xbd | 0xbf => (get_opcode(btm_4(first), 176)?, ArrayVec::new()), // OP_0
                 0xc0..=0xdf => (
-                    get_opcode(btm_5(first), 0),
+                    get_opcode(btm_5(first), 0)?,
                     get_types(&[read.byte()], &mut read),
                 ),
                 0xe0..=0xff => {
-                    let opcode = get_opcode(btm_5(first), 224);
+                    let opcode = get_opcode(btm_5(first), 224)?;
 
                     if opcode == Opcode::VAR_236 || opcode == Opcode::VAR_250 {
                         (opcode, get_types(&[read.byte(), read.byte()], &mut read))
@@ -1315,7 +1981,7 @@ impl<ZUI: UI> Zmachine<ZUI> {
         };
 
         let text = if Instruction::does_text(opcode) {
-            Some(self.read_zstring(read.position()))
+            Some(self.read_zstring(read.position())?)
         } else {
             None
         };
@@ -1328,7 +1994,7 @@ impl<ZUI: UI> Zmachine<ZUI> {
 
         let next = read.position() + text_length;
 
-        Instruction {
+        Ok(Instruction {
             addr,
             opcode,
             operands,
@@ -1336,14 +2002,16 @@ impl<ZUI: UI> Zmachine<ZUI> {
             branch,
             text,
             next,
-        }
+            version: self.version,
+        })
     }
 
-    pub fn handle_instruction(&mut self, instr: &Instruction) {
+    pub fn handle_instruction(&mut self, instr: &Instruction) -> Result<(), ZmachineError> {
         use self::Opcode::*;
 
         // ~mutably~ gets the arguments (might pop stack)
-        let args = self.get_arguments(instr.operands.as_slice());
+        let args = self.get_arguments(instr.operands.as_slice())?;
+        self.trace(|t| t.instruction(instr, &args));
 
         // Match instructions that return values for storing or branching (or both)
         // `result` is an option. either a matched instruction or none (no match)
@@ -1351,15 +2019,15 @@ impl<ZUI: UI> Zmachine<ZUI> {
             (OP2_1, _) if !args.is_empty() => Some(self.do_je(args[0], &args[1..])),
             (OP2_2, &[a, b]) => Some(self.do_jl(a, b)),
             (OP2_3, &[a, b]) => Some(self.do_jg(a, b)),
-            (OP2_4, &[var, value]) => Some(self.do_dec_chk(var, value)),
-            (OP2_5, &[var, value]) => Some(self.do_inc_chk(var, value)),
+            (OP2_4, &[var, value]) => Some(self.do_dec_chk(var, value)?),
+            (OP2_5, &[var, value]) => Some(self.do_inc_chk(var, value)?),
             (OP2_6, &[obj1, obj2]) => Some(self.do_jin(obj1, obj2)),
             (OP2_7, &[map, flags]) => Some(self.do_test(map, flags)),
             (OP2_8, &[a, b]) => Some(self.do_or(a, b)),
             (OP2_9, &[a, b]) => Some(self.do_and(a, b)),
-            (OP2_10, &[obj, attr]) => Some(self.do_test_attr(obj, attr)),
-            (OP2_15, &[array, index]) => Some(self.do_loadw(array, index)),
-            (OP2_16, &[array, index]) => Some(self.do_loadb(array, index)),
+            (OP2_10, &[obj, attr]) => Some(self.do_test_attr(obj, attr)?),
+            (OP2_15, &[array, index]) => Some(self.do_loadw(array, index)?),
+            (OP2_16, &[array, index]) => Some(self.do_loadb(array, index)?),
             (OP2_17, &[obj, prop]) => Some(self.do_get_prop(obj, prop)),
             (OP2_18, &[obj, prop]) => Some(self.do_get_prop_addr(obj, prop)),
             (OP2_19, &[obj, prop]) => Some(self.do_get_next_prop(obj, prop)),
@@ -1373,13 +2041,14 @@ impl<ZUI: UI> Zmachine<ZUI> {
             (OP1_130, &[obj]) => Some(self.do_get_child(obj)),
             (OP1_131, &[obj]) => Some(self.do_get_parent(obj)),
             (OP1_132, &[addr]) => Some(self.do_get_prop_len(addr)),
-            (OP1_142, &[var]) => Some(self.do_load(var)),
+            (OP1_142, &[var]) => Some(self.do_load(var)?),
             (OP1_143, &[value]) if self.version <= 4 => Some(self.do_not(value)),
             (OP0_185, _) if self.version >= 5 => Some(self.do_catch()),
             (OP0_189, &[]) => Some(self.do_verify()),
             (OP0_191, &[]) => Some(1), // piracy
             (VAR_231, &[range]) => Some(self.do_random(range)),
-            (VAR_233, &[var]) if self.version == 6 => Some(self.do_pull(var)),
+            (VAR_233, &[]) if self.version == 6 => Some(self.stack_pop()),
+            (VAR_233, &[stack]) if self.version == 6 => Some(self.do_pull_stack(stack)?),
             (VAR_247, &[x, table, len, ..]) => {
                 Some(self.do_scan_table(x, table, len, args.get(3).copied()))
             }
@@ -1387,8 +2056,13 @@ impl<ZUI: UI> Zmachine<ZUI> {
             (VAR_255, &[num]) => Some(self.do_check_arg_count(num)),
             (EXT_1002, &[num, places]) => Some(self.do_log_shift(num, places)),
             (EXT_1003, &[num, places]) => Some(self.do_art_shift(num, places)),
-            (EXT_1004, &[font]) => Some(self.do_set_font(font)),
+            (EXT_1004, &[font, ..]) => Some(self.do_set_font(font)),
+            (EXT_1006, &[picture, array]) => Some(self.do_picture_data(picture, array)?),
             (EXT_1009, &[]) => Some(self.do_save_undo(instr)),
+            (EXT_1019, &[window, prop]) => Some(self.do_get_wind_prop(window, prop)?),
+            (EXT_1024, &[value, stack]) => Some(self.do_push_stack(value, stack)?),
+            (EXT_1027, _) => Some(0), // make_menu: we don't have menus to add to
+            (EXT_1029, &[_]) => Some(0), // buffer_screen: we never need to flush
             _ => None,
         };
 
@@ -1396,84 +2070,115 @@ impl<ZUI: UI> Zmachine<ZUI> {
         // either storing it / branching on it / advancing the program counter.
         // Then return early since this instruction is done.
         if let Some(value) = result {
-            self.process_result(instr, value);
-            return;
+            self.process_result(instr, value)?;
+            return Ok(());
         }
 
         // All other instructions (don't produce a value, only a side effect)
         match (instr.opcode, &args[..]) {
-            (OP2_11, &[obj, attr]) => self.do_set_attr(obj, attr),
-            (OP2_12, &[obj, attr]) => self.do_clear_attr(obj, attr),
-            (OP2_13, &[var, value]) => self.do_store(var, value),
+            (OP2_11, &[obj, attr]) => self.do_set_attr(obj, attr)?,
+            (OP2_12, &[obj, attr]) => self.do_clear_attr(obj, attr)?,
+            (OP2_13, &[var, value]) => self.do_store(var, value)?,
             (OP2_14, &[obj, dest]) => self.do_insert_obj(obj, dest),
-            (OP2_25, &[addr, arg]) => self.do_call(instr, addr, &[arg]), // call_2s
-            (OP2_26, &[addr, arg]) => self.do_call(instr, addr, &[arg]), // call_2n
-            (OP2_27, _) => {}                                            // set_colour... ignored!
-            (OP2_28, &[value, frame]) => self.do_throw(value, frame),
-            (OP1_133, &[var]) => self.do_inc(var),
-            (OP1_134, &[var]) => self.do_dec(var),
-            (OP1_135, &[addr]) => self.do_print_addr(addr),
-            (OP1_136, &[addr]) => self.do_call(instr, addr, &[]), // call_1s
+            (OP2_25, &[addr, arg]) => self.do_call(instr, addr, &[arg])?, // call_2s
+            (OP2_26, &[addr, arg]) => self.do_call(instr, addr, &[arg])?, // call_2n
+            (OP2_27, &[foreground, background, ..]) => self.do_set_colour(foreground, background),
+            (OP2_28, &[value, frame]) => self.do_throw(value, frame)?,
+            (OP1_133, &[var]) => self.do_inc(var)?,
+            (OP1_134, &[var]) => self.do_dec(var)?,
+            (OP1_135, &[addr]) => self.do_print_addr(addr)?,
+            (OP1_136, &[addr]) => self.do_call(instr, addr, &[])?, // call_1s
             (OP1_137, &[obj]) => self.do_remove_obj(obj),
-            (OP1_138, &[obj]) => self.do_print_obj(obj),
-            (OP1_139, &[value]) => self.do_ret(value),
+            (OP1_138, &[obj]) => self.do_print_obj(obj)?,
+            (OP1_139, &[value]) => self.do_ret(value)?,
             (OP1_140, &[offset]) => self.do_jump(offset, instr),
-            (OP1_141, &[addr]) => self.do_print_paddr(addr),
-            (OP1_143, &[addr]) if self.version >= 5 => self.do_call(instr, addr, &[]), // call_1n
-            (OP0_176, _) => self.do_rtrue(),
-            (OP0_177, _) => self.do_rfalse(),
+            (OP1_141, &[addr]) => self.do_print_paddr(addr)?,
+            (OP1_143, &[addr]) if self.version >= 5 => self.do_call(instr, addr, &[])?, // call_1n
+            (OP0_176, _) => self.do_rtrue()?,
+            (OP0_177, _) => self.do_rfalse()?,
             (OP0_178, _) => self.do_print(instr),
-            (OP0_179, _) => self.do_print_ret(instr),
+            (OP0_179, _) => self.do_print_ret(instr)?,
             (OP0_180, _) => {}
             // (OP0_181, _) => self.do_save(instr),
             // (OP0_182, _) => self.do_restore(instr),
             (OP0_183, _) => self.do_restart(),
-            (OP0_184, _) => self.do_ret_popped(),
+            (OP0_184, _) => self.do_ret_popped()?,
             (OP0_185, _) if self.version < 5 => self.do_pop(),
             (OP0_187, _) => self.do_newline(),
             (OP0_188, _) => self.do_show_status(),
-            (VAR_224, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..]), // call
-            (VAR_225, &[array, index, value]) => self.do_storew(array, index, value),
-            (VAR_226, &[array, index, value]) => self.do_storeb(array, index, value),
+            (VAR_224, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..])?, // call
+            (VAR_225, &[array, index, value]) => self.do_storew(array, index, value)?,
+            (VAR_226, &[array, index, value]) => self.do_storeb(array, index, value)?,
             (VAR_227, &[obj, prop, value]) => self.do_put_prop(obj, prop, value),
             // (VAR_228, &[text, parse]) => self.do_sread(instr, text, parse),
             (VAR_229, &[chr]) => self.do_print_char(chr),
             (VAR_230, &[num]) => self.do_print_num(num),
             (VAR_232, &[value]) => self.do_push(value),
             (VAR_233, &[var]) => {
-                self.do_pull(var);
+                self.do_pull(var)?;
             }
             (VAR_234, &[lines]) => self.do_split_window(lines),
-            (VAR_235, &[window]) => self.do_set_window(window),
-            (VAR_236, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..]), // call_vs2
-            (VAR_237, &[window]) => self.do_erase_window(window),
-            // (VAR_238, _) => self.do_erase_line(), TODO
+            (VAR_235, &[window]) => self.do_set_window(window)?,
+            (VAR_236, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..])?, // call_vs2
+            (VAR_237, &[window]) => self.do_erase_window(window)?,
+            (VAR_238, &[value]) => self.do_erase_line(value),
             // It's not clear from the spec what to do with a single operand here,
             // but eg. anchorhead seems to use this with just a line number.
-            (VAR_239, &[line]) => self.do_set_cursor(line, 1),
-            (VAR_239, &[line, column]) => self.do_set_cursor(line, column),
+            (VAR_239, &[line]) => self.do_set_cursor(line, 1, None)?,
+            (VAR_239, &[line, column]) => self.do_set_cursor(line, column, None)?,
+            (VAR_239, &[line, column, window]) => self.do_set_cursor(line, column, Some(window))?,
+            (VAR_240, &[array]) => self.do_get_cursor(array)?,
             (VAR_241, &[style]) => self.do_set_text_style(style),
             (VAR_242, _) => (), // set buffering, but does it matter in this day and age?
-            (VAR_243, &[number, ..]) => self.do_output_stream(number, &args[1..]),
-            (VAR_244, &[number]) => self.do_input_stream(number),
-            (VAR_245, _) => (), // sound effects not supported!
-            (VAR_249, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..]), // call_vn
-            (VAR_250, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..]), // call_vn2
-            (VAR_251, &[text_addr, parse_addr]) => self.do_tokenise(text_addr, parse_addr),
-            (VAR_253, &[first, second, size]) => self.do_copy_table(first, second, size),
-            (VAR_254, _) => {
-                self.do_print_table(args[0], args[1], args.get(2).copied(), args.get(3).copied())
-            }
-            (EXT_1010, &[]) => self.do_restore_undo(),
+            (VAR_243, &[number, ..]) => self.do_output_stream(number, &args[1..])?,
+            (VAR_244, &[number]) => self.do_input_stream(number)?,
+            (VAR_245, _) => self.do_sound_effect(&args)?,
+            (VAR_249, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..])?, // call_vn
+            (VAR_250, _) if !args.is_empty() => self.do_call(instr, args[0], &args[1..])?, // call_vn2
+            (VAR_251, &[text_addr, parse_addr, ..]) => self.do_tokenise(
+                text_addr,
+                parse_addr,
+                args.get(2).copied().unwrap_or(0),
+                args.get(3).is_some_and(|flag| *flag != 0),
+            )?,
+            (VAR_253, &[first, second, size]) => self.do_copy_table(first, second, size)?,
+            (VAR_254, &[zstring, width, ..]) => {
+                self.do_print_table(zstring, width, args.get(2).copied(), args.get(3).copied())?
+            }
+            (EXT_1010, &[]) => self.do_restore_undo()?,
+            (EXT_1005, &[picture, ..]) => self.do_draw_picture(picture, &args[1..], false),
+            (EXT_1007, &[picture, ..]) => self.do_draw_picture(picture, &args[1..], true),
+            (EXT_1008, &[left, right, ..]) => {
+                self.do_set_margins(left, right, args.get(2).copied())?
+            }
             (EXT_1011, &[code_point]) => self.do_print_unicode(code_point),
+            (EXT_1013, &[foreground, background, ..]) => {
+                self.do_set_true_colour(foreground, background)
+            }
+            (EXT_1016, &[window, y, x]) => self.do_move_window(window, y, x)?,
+            (EXT_1017, &[window, y, x]) => self.do_window_size(window, y, x)?,
+            (EXT_1018, &[window, flags, ..]) => {
+                self.do_window_style(window, flags, args.get(2).copied())?
+            }
+            // scroll_window: there's no screen for anything to scroll off of
+            (EXT_1020, &[window, _]) => {
+                self.window_number(window)?;
+            }
+            (EXT_1021, &[items, ..]) => self.do_pop_stack(items, args.get(1).copied())?,
+            (EXT_1022, &[array]) => self.do_read_mouse(array)?,
+            (EXT_1023, _) => (), // mouse_window: there's no mouse
+            (EXT_1025, &[window, prop, value]) => self.do_put_wind_prop(window, prop, value)?,
+            (EXT_1026, &[table]) => self.do_print_form(table)?,
+            (EXT_1028, _) => (), // picture_table: a hint to preload pictures, which we don't need
 
-            _ => panic!(
-                "\n\nOpcode not yet implemented: {} ({:?}/{}) @ {:#04x}\n\n",
-                Instruction::name(instr.opcode, self.version),
-                instr.opcode,
-                args.len(),
-                self.pc
-            ),
+            _ => {
+                return Err(ZmachineError::UnimplementedOpcode {
+                    pc: self.pc,
+                    opcode: instr.opcode,
+                    operands: args.len(),
+                    version: self.version,
+                })
+            }
         }
 
         // advance pc to the next instruction
@@ -1481,55 +2186,113 @@ impl<ZUI: UI> Zmachine<ZUI> {
         if instr.advances() && instr.should_advance(self.version) {
             self.pc = instr.next;
         }
+
+        Ok(())
     }
 
     // Web UI only
     #[allow(dead_code)]
-    pub fn step(&mut self) -> Step {
+    pub fn step(&mut self) -> Result<Step, ZmachineError> {
+        let result = self.run_until_paused();
+        if let Err(error) = &result {
+            self.trace(|t| t.error(error));
+        }
+        result
+    }
+
+    fn run_until_paused(&mut self) -> Result<Step, ZmachineError> {
         // loop through instructions until user input is needed
         // (saves/restores need a save name, read instructions need user input)
         // Pauses on these instructions and control is passed back to js
+        let mut budget = self.options.instruction_budget;
+        let mut resumed = true;
         loop {
-            let instr = self.decode_instruction(self.pc);
+            if let Some(remaining) = &mut budget {
+                if *remaining == 0 {
+                    return Ok(Step::Yield);
+                }
+                *remaining -= 1;
+            }
+
+            if let Some(routine) = self.sound_interrupt.take() {
+                self.run_interrupt(routine, &mut budget)?;
+            }
 
-            if self.options.log_instructions {
-                eprintln!("{}", &instr);
+            if let Some(stop) = self.check_debugger(resumed) {
+                return Ok(Step::Break(stop));
             }
+            resumed = false;
+
+            let instr = self.decode_instruction(self.pc)?;
 
             match instr.opcode {
                 // SAVE
                 Opcode::OP0_181 | Opcode::EXT_1000 => {
-                    let pc = instr.next - 1;
-                    let state = self.make_save_state(pc);
+                    self.trace(|t| t.instruction(&instr, &[]));
+                    let state = self.make_save_state(self.save_pc(&instr));
 
                     self.paused_instr = Some(instr);
 
-                    return Step::Save(state);
+                    return Ok(Step::Save(state));
                 }
                 // RESTORE (breaks loop)
                 Opcode::OP0_182 | Opcode::EXT_1001 => {
+                    self.trace(|t| t.instruction(&instr, &[]));
                     self.paused_instr = Some(instr);
-                    return Step::Restore;
+                    return Ok(Step::Restore);
                 }
                 // QUIT (breaks loop)
                 Opcode::OP0_186 => {
-                    return Step::Done;
+                    self.trace(|t| t.instruction(&instr, &[]));
+                    return Ok(Step::Done);
                 }
                 // READ (breaks loop)
                 Opcode::VAR_228 => {
                     let _state = self.make_save_state(self.pc);
                     self.update_status_bar();
+                    self.paused_args = self.get_arguments(instr.operands.as_slice())?;
+                    if let Some(tracer) = &mut self.tracer {
+                        tracer.instruction(&instr, &self.paused_args);
+                    }
                     self.paused_instr = Some(instr);
-                    return Step::ReadLine;
+
+                    if let Some(command) = self.next_command() {
+                        // echo the command, as if the player had typed it
+                        self.ui.print(&command, self.current_style);
+                        self.ui.print("\n", self.current_style);
+                        self.handle_input(command)?;
+                        continue;
+                    }
+
+                    let timeout = self.read_timeout(2);
+                    let terminators = self.terminating_chars();
+                    let preloaded = self.preloaded_input();
+                    return Ok(Step::ReadLine {
+                        timeout,
+                        terminators,
+                        preloaded,
+                    });
                 }
                 // READ_CHAR
                 Opcode::VAR_246 => {
                     let _state = self.make_save_state(self.pc);
+                    self.paused_args = self.get_arguments(instr.operands.as_slice())?;
+                    if let Some(tracer) = &mut self.tracer {
+                        tracer.instruction(&instr, &self.paused_args);
+                    }
                     self.paused_instr = Some(instr);
-                    return Step::ReadChar;
+
+                    if let Some(command) = self.next_command() {
+                        let zch = self.parse_recorded_key(&command);
+                        self.handle_read_char(zch)?;
+                        continue;
+                    }
+
+                    let timeout = self.read_timeout(1);
+                    return Ok(Step::ReadChar { timeout });
                 }
                 _ => {
-                    self.handle_instruction(&instr);
+                    self.handle_instruction(&instr)?;
                 }
             }
         }
@@ -1538,55 +2301,405 @@ impl<ZUI: UI> Zmachine<ZUI> {
     // Web UI only - gives user input to the paused read instruction
     // (passes control back JS afterwards)
     #[allow(dead_code)]
-    pub fn handle_input(&mut self, input: String) {
+    pub fn handle_input(&mut self, input: String) -> Result<(), ZmachineError> {
+        self.handle_terminated_input(input, ZChar::RETURN)
+    }
+
+    /// Like `handle_input`, for a line the player ended with one of the terminators from
+    /// `Step::ReadLine` instead of return. V5+ games get told which key it was.
+    pub fn handle_terminated_input(
+        &mut self,
+        input: String,
+        terminator: ZChar,
+    ) -> Result<(), ZmachineError> {
+        let instr = self
+            .paused_instr
+            .take()
+            .ok_or(ZmachineError::NotPaused { pc: self.pc })?;
+        // the read's arguments were evaluated when we paused
+        let (text_addr, parse_addr) = match self.paused_args[..] {
+            [text_addr] => (text_addr, 0),
+            [text_addr, parse_addr, ..] => (text_addr, parse_addr),
+            _ => {
+                return Err(ZmachineError::UnimplementedOpcode {
+                    pc: self.pc,
+                    opcode: instr.opcode,
+                    operands: self.paused_args.len(),
+                    version: self.version,
+                })
+            }
+        };
+        self.record_input(&input);
+        self.do_sread_second(text_addr, parse_addr, input)?;
+
+        // Keys that aren't in the game's table end the line like return does.
+        let terminator = if self.terminating_chars().contains(&terminator) {
+            terminator
+        } else {
+            ZChar::RETURN
+        };
+        self.process_result(&instr, terminator.0.into())
+    }
+
+    // The characters already in a paused V5+ read's text buffer, counted by its second byte.
+    fn preloaded_input(&self) -> String {
+        let text_addr = self.paused_args.first().copied().unwrap_or(0) as usize;
+        if self.version <= 4 || self.check_bounds(text_addr, 2).is_err() {
+            return String::new();
+        }
+
+        let max_length = self.memory.read_byte(text_addr);
+        let length = self.memory.read_byte(text_addr + 1).min(max_length) as usize;
+        if self.check_bounds(text_addr + 2, length).is_err() {
+            return String::new();
+        }
+        self.bytes_to_string(self.memory.read(text_addr + 2, length))
+    }
+
+    // V5+ games can list extra keys that end a line of input, in a zero-terminated table.
+    fn terminating_chars(&self) -> Vec<ZChar> {
+        let table = match self.version {
+            1..=4 => 0,
+            _ => self.memory.read_word(0x2E) as usize,
+        };
+        if table == 0 {
+            return vec![];
+        }
+
+        let mut terminators = vec![];
+        for addr in table..self.memory.len() {
+            match self.memory.read_byte(addr) {
+                0 => break,
+                // 255 means any function key
+                255 => terminators.extend((129..=154).chain(252..=254).map(ZChar)),
+                code @ (129..=154 | 252..=254) => terminators.push(ZChar(code)),
+                _ => {}
+            }
+        }
+        terminators.sort();
+        terminators.dedup();
+        terminators
+    }
+
+    /// Runs the interrupt routine for a timed read, once its timeout has passed.
+    /// `partial_input` is whatever the player has typed so far, which the routine may inspect.
+    /// Returns true if the routine ended the read, in which case the frontend should discard
+    /// the partial input; otherwise the read carries on as before.
+    ///
+    /// Either way, `step` again next. If the read is still going it pauses again straight away,
+    /// with any output from the routine, and (from V5) the partial input as `preloaded` text.
+    pub fn handle_timeout(&mut self, partial_input: &str) -> Result<bool, ZmachineError> {
+        let is_read_line = match &self.paused_instr {
+            Some(instr) => instr.opcode == Opcode::VAR_228,
+            None => return Err(ZmachineError::NotPaused { pc: self.pc }),
+        };
+
+        let routine = if is_read_line {
+            self.write_text_buffer(self.paused_args[0] as usize, partial_input.to_string())?;
+            self.paused_args.get(3)
+        } else {
+            self.paused_args.get(2)
+        };
+        let routine = routine.copied().unwrap_or(0);
+
+        let mut budget = self.options.instruction_budget;
+        if self.run_interrupt(routine, &mut budget)? == 0 {
+            return Ok(false);
+        }
+
+        // The read ends with the "terminating character" 0, which only gets stored in V5+.
         let instr = self
             .paused_instr
             .take()
-            .expect("Can't handle input, no paused instruction to resume");
-        // explicitly handle read (need to get args first)
-        let args = self.get_arguments(instr.operands.as_slice());
-        self.do_sread_second(args[0], args[1], input);
-        self.pc = instr.next;
+            .ok_or(ZmachineError::NotPaused { pc: self.pc })?;
+        self.process_result(&instr, 0)?;
+        Ok(true)
+    }
+
+    /// Let the game know that a sound has stopped on its own. If it asked to be told, its routine
+    /// runs as an interrupt: right away if the game is waiting for input, or otherwise before
+    /// the next instruction.
+    pub fn handle_sound_finished(&mut self, number: u16) -> Result<(), ZmachineError> {
+        let routine = match self.sound_routine {
+            Some((playing, routine)) if playing == number => routine,
+            _ => return Ok(()),
+        };
+        self.sound_routine = None;
+
+        let is_read = match &self.paused_instr {
+            Some(instr) => instr.opcode == Opcode::VAR_228 || instr.opcode == Opcode::VAR_246,
+            None => false,
+        };
+        if is_read {
+            let mut budget = self.options.instruction_budget;
+            self.run_interrupt(routine, &mut budget)?;
+        } else {
+            self.sound_interrupt = Some(routine);
+        }
+        Ok(())
     }
 
-    pub fn handle_read_char(&mut self, input: ZChar) {
+    pub fn handle_read_char(&mut self, input: ZChar) -> Result<(), ZmachineError> {
         let instr = self
             .paused_instr
             .take()
-            .expect("Can't handle input, no paused instruction to resume");
+            .ok_or(ZmachineError::NotPaused { pc: self.pc })?;
 
-        self.process_result(&instr, input.0.into());
+        if self.record_commands {
+            // Printable keys are recorded as-is, and everything else by its ZSCII code.
+            let command = match input.0 {
+                32..=126 => (input.0 as char).to_string(),
+                code => format!("[{}]", code),
+            };
+            self.ui.record_command(&command);
+        }
+
+        self.process_result(&instr, input.0.into())
     }
 
-    pub fn handle_save_result(&mut self, successful: bool) {
+    pub fn handle_save_result(&mut self, successful: bool) -> Result<(), ZmachineError> {
         let instr = self
             .paused_instr
             .take()
-            .expect("Can't handle input, no paused instruction to resume");
+            .ok_or(ZmachineError::NotPaused { pc: self.pc })?;
 
-        self.process_result(&instr, if successful { 1 } else { 0 });
+        self.process_result(&instr, if successful { 1 } else { 0 })
     }
 
     // NB: since most restores completely blow away the state,
     // this should only be called when a restore fails.
-    pub fn handle_restore_result(&mut self) {
+    pub fn handle_restore_result(&mut self) -> Result<(), ZmachineError> {
         let instr = self
             .paused_instr
             .take()
-            .expect("Can't handle input, no paused instruction to resume");
+            .ok_or(ZmachineError::NotPaused { pc: self.pc })?;
 
-        self.process_result(&instr, 0);
+        self.process_result(&instr, 0)
     }
 
-    pub fn restore(&mut self, data: &[u8]) {
-        self.restore_state(data);
-        self.set_dynamic_headers();
-        self.process_restore_result();
+    // Timed reads are V4+: the time is given in tenths of a second, followed by the routine
+    // to call when it runs out. Both must be nonzero for the timer to be active.
+    fn read_timeout(&self, time_index: usize) -> Option<Duration> {
+        match self.paused_args.get(time_index..time_index + 2) {
+            Some(&[time, routine]) if self.version >= 4 && time > 0 && routine != 0 => {
+                Some(Duration::from_millis(u64::from(time) * 100))
+            }
+            _ => None,
+        }
     }
 
-    // Loads a saved state _without_ processing a restore result (like the above)
-    pub fn load_savestate(&mut self, state: &[u8]) {
-        self.restore_state(state);
+    // Interrupts (like the routine for a timed read) run to completion before we hand control
+    // back to the frontend, so they can't do anything that needs the frontend's help.
+    // The result is pushed on the stack of the interrupted routine, where we pop it off again.
+    //
+    // Their instructions come out of the same budget as the game's, but they can't stop halfway
+    // and yield, so one that runs out is unwound and abandoned. For the same reason they can't
+    // be debugged: the debugger is put aside until they're done.
+    fn run_interrupt(
+        &mut self,
+        routine: u16,
+        budget: &mut Option<usize>,
+    ) -> Result<u16, ZmachineError> {
+        let debugger = self.debugger.take();
+        let result = self.run_interrupt_routine(routine, budget);
+        self.debugger = debugger;
+        result
+    }
+
+    fn run_interrupt_routine(
+        &mut self,
+        routine: u16,
+        budget: &mut Option<usize>,
+    ) -> Result<u16, ZmachineError> {
+        let call = Instruction {
+            addr: self.pc,
+            opcode: Opcode::VAR_224,
+            operands: ArrayVec::new(),
+            store: Some(0),
+            branch: None,
+            text: None,
+            next: self.pc,
+            version: self.version,
+        };
+
+        let depth = self.frames.len();
+        self.do_call(&call, routine, &[])?;
+
+        while self.frames.len() > depth {
+            if let Some(remaining) = budget {
+                if *remaining == 0 {
+                    self.frames.truncate(depth);
+                    self.pc = call.next;
+                    return Err(ZmachineError::InterruptBudget {
+                        routine: self.unpack_routine_addr(routine),
+                    });
+                }
+                *remaining -= 1;
+            }
+
+            let instr = self.decode_instruction(self.pc)?;
+
+            // Save, restore, quit and the reads aren't handled here, so they're errors.
+            self.handle_instruction(&instr)?;
+        }
+
+        Ok(self.stack_pop())
+    }
+
+    pub fn restore(&mut self, data: &[u8]) -> Result<(), ZmachineError> {
+        let preserved_flags = self.preserved_flags();
+        self.restore_state(data)?;
+        self.set_dynamic_headers();
+        self.restore_preserved_flags(preserved_flags);
+        self.process_restore_result()
+    }
+
+    // Loads a saved state _without_ processing a restore result (like the above)
+    pub fn load_savestate(&mut self, state: &[u8]) -> Result<(), ZmachineError> {
+        self.restore_state(state)
+    }
+
+    /// Whether output to the lower window is also being sent to `UI::transcript`.
+    /// Games control this with the SCRIPT / UNSCRIPT commands.
+    pub fn is_transcript_enabled(&self) -> bool {
+        self.memory.read_word(0x10) & 0b0000_0001 != 0
+    }
+
+    /// Turns the transcript (output stream 2) on or off. Games can check and change this too:
+    /// the state lives in the Flags 2 header, so this affects what the game sees.
+    pub fn set_transcript(&mut self, enabled: bool) {
+        let mut flags2 = self.memory.read_word(0x10);
+        if enabled {
+            flags2 |= 0b0000_0001;
+        } else {
+            flags2 &= !0b0000_0001;
+        }
+        self.memory.write_word(0x10, flags2);
+    }
+
+    /// Turns command recording (output stream 4) on or off. While it's on, every line and key
+    /// the player enters is sent to `UI::record_command`.
+    pub fn set_command_recording(&mut self, enabled: bool) {
+        self.record_commands = enabled;
+    }
+
+    /// Check the story file against the checksum in its header, as the `verify` opcode does.
+    /// A failure usually means the file was cut short or corrupted on its way to us.
+    pub fn verify_story(&self) -> bool {
+        match self.memory.read_word(0x1C) {
+            // Some early Inform games never filled in their checksum, so there's nothing to check.
+            0 => self.calculate_checksum().is_some(),
+            checksum => self.calculate_checksum() == Some(checksum),
+        }
+    }
+
+    /// Provide the Blorb file the story came in, so the picture and sound opcodes can find their
+    /// resources.
+    pub fn set_blorb(&mut self, blorb: Blorb) {
+        self.blorb = Some(blorb);
+        self.set_dynamic_headers();
+    }
+
+    /// Report every instruction and its effects to the tracer, or stop tracing with `None`.
+    /// See the `trace` module for some ready-made tracers.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer + Send>>) {
+        self.tracer = tracer;
+    }
+
+    fn trace(&mut self, event: impl FnOnce(&mut dyn Tracer)) {
+        if let Some(tracer) = &mut self.tracer {
+            event(tracer.as_mut());
+        }
+    }
+
+    // A V6 window number, where -3 is the current window.
+    fn window_number(&self, window: u16) -> Result<usize, ZmachineError> {
+        match window as i16 {
+            -3 => Ok(match self.current_window {
+                Window::Lower => 0,
+                Window::Upper => 1,
+                Window::Other(n) => n as usize,
+            }),
+            n @ 0..=7 => Ok(n as usize),
+            _ => Err(self.illegal_operand("window number", window)),
+        }
+    }
+
+    fn ui_window(number: usize) -> Window {
+        match number {
+            0 => Window::Lower,
+            1 => Window::Upper,
+            n => Window::Other(n as u8),
+        }
+    }
+
+    fn update_window(&mut self, number: usize) {
+        self.ui
+            .update_window(Zmachine::<ZUI>::ui_window(number), &self.windows[number]);
+    }
+
+    /// Queues up commands to replay, one per line, in the format written by command recording.
+    /// This selects input stream 1: reads are answered from the queue until it runs out,
+    /// and then `step` goes back to asking the frontend for input.
+    pub fn replay_commands(&mut self, commands: &str) {
+        self.command_file
+            .extend(commands.lines().map(|line| line.to_string()));
+        self.input_from_file = true;
+    }
+
+    /// True if reads are still being answered by `replay_commands`.
+    pub fn is_replaying(&self) -> bool {
+        self.input_from_file && !self.command_file.is_empty()
+    }
+
+    // Once the command file runs dry we switch back to the keyboard.
+    fn next_command(&mut self) -> Option<String> {
+        if !self.input_from_file {
+            return None;
+        }
+
+        let command = self.command_file.pop_front();
+        if command.is_none() {
+            self.input_from_file = false;
+        }
+        command
+    }
+
+    // The reverse of how `handle_read_char` records keys.
+    fn parse_recorded_key(&self, command: &str) -> ZChar {
+        let code = command
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .and_then(|code| code.parse().ok());
+
+        match (code, command.chars().next()) {
+            (Some(code), _) => ZChar(code),
+            (None, Some(ch)) => ZChar::from_char(ch, self.unicode_table()).unwrap_or(ZChar::RETURN),
+            (None, None) => ZChar::RETURN,
+        }
+    }
+
+    // The transcript and fixed-pitch bits of Flags 2 belong to the interpreter,
+    // so they survive restarts and restores.
+    fn preserved_flags(&self) -> u16 {
+        self.memory.read_word(0x10) & 0b0000_0011
+    }
+
+    fn restore_preserved_flags(&mut self, preserved: u16) {
+        let flags2 = self.memory.read_word(0x10) & !0b0000_0011;
+        self.memory.write_word(0x10, flags2 | preserved);
+    }
+
+    // Input is echoed to the transcript, and recorded if we're recording.
+    fn record_input(&mut self, input: &str) {
+        if self.is_transcript_enabled() {
+            self.ui.transcript(input);
+            self.ui.transcript("\n");
+        }
+
+        if self.record_commands {
+            self.ui.record_command(input);
+        }
     }
 
     fn print(&mut self, text: &str) {
@@ -1595,12 +2708,16 @@ impl<ZUI: UI> Zmachine<ZUI> {
                 let mut current_style = self.current_style;
                 if self.current_font == 4 || self.memory.read_word(0x10) & 0b0000_0010 != 0 {
                     // Force fixed-pitch bit is on!
-                    current_style.0 |= 0b1000
+                    current_style.flags |= 0b1000
                 };
 
                 if !self.disable_output {
                     self.ui.print(text, current_style);
                 }
+
+                if self.current_window == Window::Lower && self.is_transcript_enabled() {
+                    self.ui.transcript(text);
+                }
             }
             Some((_, end)) => {
                 let mut writer = self.memory.get_writer(*end);
@@ -1655,31 +2772,23 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP2_4
-    fn do_dec_chk(&mut self, var: u16, value: u16) -> u16 {
-        let before = self.read_indirect_variable(var as u8) as i16;
+    fn do_dec_chk(&mut self, var: u16, value: u16) -> Result<u16, ZmachineError> {
+        let before = self.read_indirect_variable(var as u8)? as i16;
         let after = before.wrapping_sub(1);
 
-        self.write_indirect_variable(var as u8, after as u16);
+        self.write_indirect_variable(var as u8, after as u16)?;
 
-        if after < (value as i16) {
-            1
-        } else {
-            0
-        }
+        Ok(if after < (value as i16) { 1 } else { 0 })
     }
 
     // OP2_5
-    fn do_inc_chk(&mut self, var: u16, value: u16) -> u16 {
-        let before = self.read_indirect_variable(var as u8) as i16;
+    fn do_inc_chk(&mut self, var: u16, value: u16) -> Result<u16, ZmachineError> {
+        let before = self.read_indirect_variable(var as u8)? as i16;
         let after = before.wrapping_add(1);
 
-        self.write_indirect_variable(var as u8, after as u16);
+        self.write_indirect_variable(var as u8, after as u16)?;
 
-        if after > (value as i16) {
-            1
-        } else {
-            0
-        }
+        Ok(if after > (value as i16) { 1 } else { 0 })
     }
 
     // OP2_6
@@ -1711,23 +2820,23 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP2_10
-    fn do_test_attr(&self, obj: u16, attr: u16) -> u16 {
+    fn do_test_attr(&self, obj: u16, attr: u16) -> Result<u16, ZmachineError> {
         self.test_attr(obj, attr)
     }
 
     // OP2_11
-    fn do_set_attr(&mut self, obj: u16, attr: u16) {
+    fn do_set_attr(&mut self, obj: u16, attr: u16) -> Result<(), ZmachineError> {
         self.set_attr(obj, attr)
     }
 
     // OP2_12
-    fn do_clear_attr(&mut self, obj: u16, attr: u16) {
+    fn do_clear_attr(&mut self, obj: u16, attr: u16) -> Result<(), ZmachineError> {
         self.clear_attr(obj, attr)
     }
 
     // OP2_13
-    fn do_store(&mut self, var: u16, value: u16) {
-        self.write_indirect_variable(var as u8, value);
+    fn do_store(&mut self, var: u16, value: u16) -> Result<(), ZmachineError> {
+        self.write_indirect_variable(var as u8, value)
     }
 
     // OP2_14
@@ -1736,18 +2845,20 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP2_15
-    fn do_loadw(&self, array_addr: u16, index: u16) -> u16 {
+    fn do_loadw(&self, array_addr: u16, index: u16) -> Result<u16, ZmachineError> {
         let word_index = index.wrapping_mul(2);
         let word_addr = array_addr.wrapping_add(word_index);
 
-        self.memory.read_word(word_addr as usize)
+        self.check_bounds(word_addr as usize, 2)?;
+        Ok(self.memory.read_word(word_addr as usize))
     }
 
     // OP2_16
-    fn do_loadb(&self, array_addr: u16, index: u16) -> u16 {
+    fn do_loadb(&self, array_addr: u16, index: u16) -> Result<u16, ZmachineError> {
         let byte_addr = array_addr.wrapping_add(index);
 
-        u16::from(self.memory.read_byte(byte_addr as usize))
+        self.check_bounds(byte_addr as usize, 1)?;
+        Ok(u16::from(self.memory.read_byte(byte_addr as usize)))
     }
 
     // OP2_17
@@ -1790,10 +2901,21 @@ impl<ZUI: UI> Zmachine<ZUI> {
         (a as i16 % b as i16) as u16
     }
 
+    // OP2_27
+    fn do_set_colour(&mut self, foreground: u16, background: u16) {
+        // 0 keeps the current colour; -1 (V6's "colour under the cursor") isn't supported.
+        if let Some(colour) = Colour::from_standard(foreground) {
+            self.current_style.foreground = colour;
+        }
+        if let Some(colour) = Colour::from_standard(background) {
+            self.current_style.background = colour;
+        }
+    }
+
     // OP2_28
-    fn do_throw(&mut self, value: u16, stack_frame: u16) {
+    fn do_throw(&mut self, value: u16, stack_frame: u16) -> Result<(), ZmachineError> {
         self.frames.truncate(stack_frame as usize);
-        self.return_from_routine(value);
+        self.return_from_routine(value)
     }
 
     // OP1_128
@@ -1826,25 +2948,26 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP1_133
-    fn do_inc(&mut self, var: u16) {
-        let value = self.read_indirect_variable(var as u8);
+    fn do_inc(&mut self, var: u16) -> Result<(), ZmachineError> {
+        let value = self.read_indirect_variable(var as u8)?;
         let inc = (value as i16).wrapping_add(1);
 
-        self.write_indirect_variable(var as u8, inc as u16);
+        self.write_indirect_variable(var as u8, inc as u16)
     }
 
     // OP1_134
-    fn do_dec(&mut self, var: u16) {
-        let value = self.read_indirect_variable(var as u8);
+    fn do_dec(&mut self, var: u16) -> Result<(), ZmachineError> {
+        let value = self.read_indirect_variable(var as u8)?;
         let dec = (value as i16).wrapping_sub(1);
 
-        self.write_indirect_variable(var as u8, dec as u16);
+        self.write_indirect_variable(var as u8, dec as u16)
     }
 
     // OP1_135
-    fn do_print_addr(&mut self, addr: u16) {
-        let zstring = self.read_zstring(addr as usize);
+    fn do_print_addr(&mut self, addr: u16) -> Result<(), ZmachineError> {
+        let zstring = self.read_zstring(addr as usize)?;
         self.print(&zstring);
+        Ok(())
     }
 
     // OP1_136 : call_1s
@@ -1855,14 +2978,15 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP1_138
-    fn do_print_obj(&mut self, obj: u16) {
-        let name = self.get_object_name(obj);
+    fn do_print_obj(&mut self, obj: u16) -> Result<(), ZmachineError> {
+        let name = self.get_object_name(obj)?;
         self.print(&name);
+        Ok(())
     }
 
     // OP1_139
-    fn do_ret(&mut self, value: u16) {
-        self.return_from_routine(value);
+    fn do_ret(&mut self, value: u16) -> Result<(), ZmachineError> {
+        self.return_from_routine(value)
     }
 
     // OP1_140
@@ -1875,14 +2999,15 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP1_141
-    fn do_print_paddr(&mut self, addr: u16) {
+    fn do_print_paddr(&mut self, addr: u16) -> Result<(), ZmachineError> {
         let paddr = self.unpack_print_paddr(addr);
-        let zstring = self.read_zstring(paddr);
+        let zstring = self.read_zstring(paddr)?;
         self.print(&zstring);
+        Ok(())
     }
 
     // OP1_142
-    fn do_load(&mut self, var: u16) -> u16 {
+    fn do_load(&mut self, var: u16) -> Result<u16, ZmachineError> {
         self.read_indirect_variable(var as u8)
     }
 
@@ -1892,13 +3017,13 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP0_176
-    fn do_rtrue(&mut self) {
-        self.return_from_routine(1);
+    fn do_rtrue(&mut self) -> Result<(), ZmachineError> {
+        self.return_from_routine(1)
     }
 
     // OP0_177
-    fn do_rfalse(&mut self) {
-        self.return_from_routine(0);
+    fn do_rfalse(&mut self) -> Result<(), ZmachineError> {
+        self.return_from_routine(0)
     }
 
     // OP0_178
@@ -1908,53 +3033,61 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // OP0_179
-    fn do_print_ret(&mut self, instr: &Instruction) {
+    fn do_print_ret(&mut self, instr: &Instruction) -> Result<(), ZmachineError> {
         let text = instr.text.as_ref().expect("Can't print with no text!");
         self.print(text);
         self.print("\n");
-        self.return_from_routine(1);
+        self.return_from_routine(1)
     }
 
     // OP0_180 : nop, never actually used
 
-    fn process_restore_result(&mut self) {
+    fn process_restore_result(&mut self) -> Result<(), ZmachineError> {
         // On Versions 3 and 4, attempts to save the game (all questions about filenames are asked
         // by interpreters) and branches if successful. From Version 5 it is a store rather than a
         // branch instruction; the store value is 0 for failure, 1 for "save succeeded" and 2 for
         // "the game is being restored and is resuming execution again from here, the point where it was saved".
         if self.version <= 4 {
-            let instruction = self.decode_instruction(self.pc - 1);
-            self.process_result(&instruction, 2);
+            let instruction = self.decode_instruction(self.pc - 1)?;
+            self.process_result(&instruction, 2)?;
         } else {
             // We don't know where the instruction starts... it might be the extended version.
             // But at 5 and above save is always a store, so just implement that directly.
+            self.check_bounds(self.pc, 1)?;
             let index = self.memory.read_byte(self.pc);
-            self.write_variable(index, 2);
+            self.write_variable(index, 2)?;
             self.pc += 1;
         }
+        Ok(())
     }
 
     // OP0_183
     fn do_restart(&mut self) {
         self.ui.split_window(0);
+        self.ui.set_window(Window::Lower);
         self.ui.erase_window(Window::Lower);
         self.ui.set_cursor(1, 1);
+        self.current_window = Window::Lower;
         self.current_style = TextStyle::default();
         self.disable_output = false;
         self.memory_output.clear();
+        self.sound_routine = None;
+        self.sound_interrupt = None;
 
-        self.pc = self.initial_pc;
-        self.frames.clear();
-        self.frames.push(Frame::empty());
+        let preserved_flags = self.preserved_flags();
         self.memory.write(0, self.original_dynamic.as_slice());
+        self.start();
+        self.save_chunks.clear();
 
         self.set_dynamic_headers();
+        self.restore_preserved_flags(preserved_flags);
+        self.reset_windows();
     }
 
     // OP0_184
-    fn do_ret_popped(&mut self) {
+    fn do_ret_popped(&mut self) -> Result<(), ZmachineError> {
         let value = self.stack_pop();
-        self.return_from_routine(value);
+        self.return_from_routine(value)
     }
 
     // OP0_185
@@ -1978,7 +3111,11 @@ impl<ZUI: UI> Zmachine<ZUI> {
 
     // OP0_189
     fn do_verify(&self) -> u16 {
-        1
+        if self.verify_story() {
+            1
+        } else {
+            0
+        }
     }
 
     // All calls:
@@ -1988,15 +3125,24 @@ impl<ZUI: UI> Zmachine<ZUI> {
     // The only difference between the different opcodes is number of arguments
     // and whether or not to store or branch based on the result of the call
     //
-    fn do_call(&mut self, instr: &Instruction, addr: u16, args: &[u16]) {
+    fn do_call(
+        &mut self,
+        instr: &Instruction,
+        addr: u16,
+        args: &[u16],
+    ) -> Result<(), ZmachineError> {
         // weird edge case: addr 0 means do nothing, then store/branch on 0
         if addr == 0 {
-            self.process_result(instr, 0);
-            return;
+            return self.process_result(instr, 0);
         }
 
         // decode routine / prepopulate routine local variables
         let routine_addr = self.unpack_routine_addr(addr);
+        self.check_bounds(routine_addr, 1)?;
+        self.trace(|t| t.call(routine_addr, args));
+        if let Some(debugger) = &mut self.debugger {
+            debugger.called(routine_addr);
+        }
         let mut read = self.memory.get_reader(routine_addr);
 
         let mut locals = Vec::new();
@@ -2014,19 +3160,24 @@ impl<ZUI: UI> Zmachine<ZUI> {
 
         self.pc = first_instr;
         self.frames.push(frame);
+        Ok(())
     }
 
     // VAR_225
-    fn do_storew(&mut self, array_addr: u16, index: u16, value: u16) {
+    fn do_storew(&mut self, array_addr: u16, index: u16, value: u16) -> Result<(), ZmachineError> {
         let word_index = index.wrapping_mul(2);
         let word_addr = array_addr.wrapping_add(word_index);
+        self.check_bounds(word_addr as usize, 2)?;
         self.memory.write_word(word_addr as usize, value);
+        Ok(())
     }
 
     // VAR_226
-    fn do_storeb(&mut self, array: u16, index: u16, value: u16) {
+    fn do_storeb(&mut self, array: u16, index: u16, value: u16) -> Result<(), ZmachineError> {
         let word_addr = array.wrapping_add(index);
+        self.check_bounds(word_addr as usize, 1)?;
         self.memory.write_byte(word_addr as usize, value as u8);
+        Ok(())
     }
 
     // VAR_227
@@ -2035,36 +3186,62 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // VAR_228 (blocking)
-    fn do_sread_second(&mut self, text_addr: u16, parse_addr: u16, mut raw: String) {
-        let text_addr = text_addr as usize;
-        let parse_addr = parse_addr as usize;
+    fn do_sread_second(
+        &mut self,
+        text_addr: u16,
+        parse_addr: u16,
+        raw: String,
+    ) -> Result<(), ZmachineError> {
+        let input = self.write_text_buffer(text_addr as usize, raw)?;
+
+        // skip tokenization step if parse_addr is 0
+        if parse_addr != 0 {
+            self.tokenise(&input, parse_addr as usize, 0, false)?;
+        }
+
+        Ok(())
+    }
 
+    // Store input in a read's text buffer as lowercase ZSCII, returning the bytes as they were
+    // stored. Characters that ZSCII can't represent become question marks.
+    fn write_text_buffer(
+        &mut self,
+        text_addr: usize,
+        raw: String,
+    ) -> Result<Vec<u8>, ZmachineError> {
         // versions 1-4 have to store an extra 0, so the max length is 1 less
+        self.check_bounds(text_addr, 2)?;
         let mut max_length = self.memory.read_byte(text_addr as usize);
+        self.check_bounds(text_addr, max_length as usize + 2)?;
         if self.version <= 4 {
-            max_length -= 1;
+            max_length = max_length.saturating_sub(1);
         }
 
-        raw.truncate(max_length as usize);
-        let input = &raw.to_lowercase();
-
-        let bytes = input.as_bytes();
+        let table = self.unicode_table();
+        let mut bytes: Vec<u8> = raw
+            .chars()
+            .flat_map(char::to_lowercase)
+            .map(|ch| match ZChar::from_char(ch, table) {
+                Some(ZChar(zscii @ (32..=126 | 155..=251))) => zscii,
+                // tabs and stray newlines
+                _ if ch.is_whitespace() => b' ',
+                _ => b'?',
+            })
+            .collect();
+        bytes.truncate(max_length as usize);
         let len = bytes.len();
 
         // ver 1-4 start storing @ byte 1, ending with a terminating 0
         // ver 5+ save the input length @1, start storing @2, and DON'T end with 0
         if self.version <= 4 {
-            self.memory.write(text_addr + 1, bytes);
+            self.memory.write(text_addr + 1, &bytes);
             self.memory.write_byte(text_addr + 1 + len, 0);
         } else {
             self.memory.write_byte(text_addr + 1, len as u8);
-            self.memory.write(text_addr + 2, bytes);
+            self.memory.write(text_addr + 2, &bytes);
         }
 
-        // skip tokenization step if parse_addr is 0
-        if parse_addr != 0 {
-            self.tokenise(input, parse_addr);
-        }
+        Ok(bytes)
     }
 
     // VAR_229
@@ -2098,30 +3275,61 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     // VAR_233
-    fn do_pull(&mut self, var: u16) -> u16 {
+    fn do_pull(&mut self, var: u16) -> Result<u16, ZmachineError> {
         let value = self.stack_pop();
-        self.write_indirect_variable(var as u8, value);
+        self.write_indirect_variable(var as u8, value)?;
 
-        value
+        Ok(value)
+    }
+
+    // VAR_233, in V6
+    fn do_pull_stack(&mut self, stack: u16) -> Result<u16, ZmachineError> {
+        // The first word of a user stack is the number of free slots left, so this is the
+        // reverse of `do_push_stack`.
+        let stack = stack as usize;
+        self.check_bounds(stack, 2)?;
+        let free = self.memory.read_word(stack).wrapping_add(1);
+        let slot = stack + 2 * free as usize;
+        self.check_bounds(slot, 2)?;
+        self.memory.write_word(stack, free);
+        Ok(self.memory.read_word(slot))
     }
 
     // VAR_234
     fn do_split_window(&mut self, lines: u16) {
+        if self.version == 6 {
+            // Window 1 takes over the top of the screen, and window 0 gets the rest.
+            let (_, screen_height) = self.options.dimensions;
+            let lines = lines.min(screen_height);
+            let upper = &mut self.windows[1];
+            upper.set(window::Y_COORD, 1);
+            upper.set(window::X_COORD, 1);
+            upper.set(window::Y_SIZE, lines);
+            upper.set(window::X_SIZE, self.options.dimensions.0);
+            let lower = &mut self.windows[0];
+            lower.set(window::Y_COORD, lines + 1);
+            lower.set(window::Y_SIZE, screen_height - lines);
+            self.update_window(0);
+            self.update_window(1);
+        }
         self.ui.split_window(lines);
     }
 
     // VAR_235
-    fn do_set_window(&mut self, window: u16) {
+    fn do_set_window(&mut self, window: u16) -> Result<(), ZmachineError> {
         let window = match window {
             0 => Window::Lower,
             1 => Window::Upper,
-            other => panic!("Illegal window number: {}", other),
+            other if self.version == 6 => Zmachine::<ZUI>::ui_window(self.window_number(other)?),
+            other => return Err(self.illegal_operand("window number", other)),
         };
+        self.current_window = window;
         self.ui.set_window(window);
+        Ok(())
     }
 
     // VAR_237
-    fn do_erase_window(&mut self, window: u16) {
+    fn do_erase_window(&mut self, window: u16) -> Result<(), ZmachineError> {
         match window as i16 {
             0 => {
                 self.ui.erase_window(Window::Lower);
@@ -2129,6 +3337,9 @@ impl<ZUI: UI> Zmachine<ZUI> {
             1 => {
                 self.ui.erase_window(Window::Upper);
             }
+            2..=7 if self.version == 6 => {
+                self.ui.erase_window(Window::Other(window as u8));
+            }
             -1 => {
                 self.ui.split_window(0);
                 self.ui.erase_window(Window::Lower);
@@ -2137,25 +3348,67 @@ impl<ZUI: UI> Zmachine<ZUI> {
                 self.ui.erase_window(Window::Lower);
                 self.ui.erase_window(Window::Upper);
             }
-            other => panic!("Illegal window number: {}", other),
+            _ => return Err(self.illegal_operand("window number", window)),
         };
+        Ok(())
     }
 
-    fn do_set_cursor(&mut self, line: u16, column: u16) {
+    fn do_set_cursor(
+        &mut self,
+        line: u16,
+        column: u16,
+        window: Option<u16>,
+    ) -> Result<(), ZmachineError> {
+        if self.version == 6 {
+            // -1 and -2 turn the cursor off and on, which doesn't matter to us.
+            if (line as i16) < 0 {
+                return Ok(());
+            }
+            let current = self.window_number(0xFFFD)?;
+            let number = match window {
+                Some(window) => self.window_number(window)?,
+                None => current,
+            };
+            self.windows[number].set(window::Y_CURSOR, line);
+            self.windows[number].set(window::X_CURSOR, column);
+            if number != current {
+                return Ok(());
+            }
+        }
+
         self.ui.set_cursor(line, column);
+        Ok(())
+    }
+
+    // VAR_238
+    fn do_erase_line(&mut self, value: u16) {
+        // Any value other than 1 does nothing, at least outside of V6.
+        if value == 1 {
+            self.ui.erase_line();
+        }
+    }
+
+    // VAR_240
+    fn do_get_cursor(&mut self, array: u16) -> Result<(), ZmachineError> {
+        self.check_bounds(array as usize, 4)?;
+        let (line, column) = self.ui.get_cursor();
+        self.memory.write_word(array as usize, line);
+        self.memory.write_word(array as usize + 2, column);
+        Ok(())
     }
 
     // VAR_241
     fn do_set_text_style(&mut self, style: u16) {
-        self.current_style = TextStyle::new(style);
+        // Colours are set separately, and survive a change of style.
+        self.current_style.flags = style;
     }
 
     // VAR_243
-    fn do_output_stream(&mut self, number: u16, args: &[u16]) {
+    fn do_output_stream(&mut self, number: u16, args: &[u16]) -> Result<(), ZmachineError> {
         let number = number as i16;
         let (enabled, stream) = match number.cmp(&0) {
             Ordering::Less => (false, -number),
-            Ordering::Equal => return,
+            Ordering::Equal => return Ok(()),
             Ordering::Greater => (true, number),
         };
 
@@ -2164,37 +3417,38 @@ impl<ZUI: UI> Zmachine<ZUI> {
                 self.disable_output = !enabled;
             }
             2 => {
-                // We don't keep a transcript, so, ignore this.
+                self.set_transcript(enabled);
             }
             3 => {
                 if enabled {
-                    let table_addr = args[0] as usize;
+                    let table_addr = match args.first() {
+                        Some(addr) => *addr as usize,
+                        None => return Err(self.illegal_operand("output table", 0)),
+                    };
+                    self.check_bounds(table_addr, 2)?;
                     self.memory_output.push((table_addr, table_addr + 2));
                 } else if let Some((start, end)) = self.memory_output.pop() {
                     self.memory.write_word(start, (end - start - 2) as u16);
                 }
             }
             4 => {
-                // This is another type of transcript; ignore it too.
+                self.set_command_recording(enabled);
             }
-            _ => panic!("Illegal output stream number! {}", number),
+            _ => return Err(self.illegal_operand("output stream", number as u16)),
         }
+
+        Ok(())
     }
 
     // VAR_244
-    fn do_input_stream(&mut self, number: u16) {
+    fn do_input_stream(&mut self, number: u16) -> Result<(), ZmachineError> {
         match number {
-            1 => {}
-            2 => {
-                unimplemented!("Transcript not implemented!")
-            }
-            3 => {
-                unimplemented!("In-memory input not implemented!")
-            }
-            4 => {
-                unimplemented!("Extra transcript not implemented!")
+            // 0 is the keyboard, and 1 is the commands queued up by `replay_commands`.
+            0 | 1 => {
+                self.input_from_file = number == 1;
+                Ok(())
             }
-            _ => panic!("Illegal output stream number! {}", number),
+            _ => Err(self.illegal_operand("input stream", number)),
         }
     }
 
@@ -2231,30 +3485,51 @@ impl<ZUI: UI> Zmachine<ZUI> {
     // VAR_248 do_not() (same as OP1_143)
 
     // VAR_251
-    fn do_tokenise(&mut self, text_addr: u16, parse_addr: u16) {
+    fn do_tokenise(
+        &mut self,
+        text_addr: u16,
+        parse_addr: u16,
+        dictionary: u16,
+        skip_unknown: bool,
+    ) -> Result<(), ZmachineError> {
+        self.check_bounds(text_addr as usize, 2)?;
         let max_chars = self.memory.read_byte(text_addr as usize);
         let num_chars = self.memory.read_byte(text_addr as usize + 1);
-        assert!(num_chars <= max_chars);
-        let string = self.memory.read(text_addr as usize + 2, num_chars as usize);
-        let text: String = self.bytes_to_string(string);
-        self.tokenise(&text, parse_addr as usize);
-    }
-
-    fn do_copy_table(&mut self, first: u16, second: u16, size: u16) {
+        if num_chars > max_chars {
+            return Err(self.illegal_operand("text length", num_chars as u16));
+        }
+        self.check_bounds(text_addr as usize + 2, num_chars as usize)?;
+        let text = self
+            .memory
+            .read(text_addr as usize + 2, num_chars as usize)
+            .to_vec();
+        self.tokenise(
+            &text,
+            parse_addr as usize,
+            dictionary as usize,
+            skip_unknown,
+        )
+    }
+
+    fn do_copy_table(&mut self, first: u16, second: u16, size: u16) -> Result<(), ZmachineError> {
         let size = size as i16;
         let force_forward = size < 0;
         let count = size.abs() as usize;
         let first = first as usize;
         let second = second as usize;
 
+        self.check_bounds(first, count)?;
+
         if second == 0 {
             let mut writer = self.memory.get_writer(first);
             for _ in 0..count {
                 writer.byte(0);
             }
-            return;
+            return Ok(());
         }
 
+        self.check_bounds(second, count)?;
+
         let is_forward = if force_forward { true } else { first > second };
 
         if is_forward {
@@ -2268,6 +3543,8 @@ impl<ZUI: UI> Zmachine<ZUI> {
                     .write_byte(second + i, self.memory.read_byte(first + i));
             }
         };
+
+        Ok(())
     }
 
     fn do_print_table(
@@ -2276,13 +3553,14 @@ impl<ZUI: UI> Zmachine<ZUI> {
         width: u16,
         height: Option<u16>,
         skip: Option<u16>,
-    ) {
+    ) -> Result<(), ZmachineError> {
         // TODO: this isn't quite the right behaviour in the upper window...
         // IIUC, we should be returning to the original column for each line instead of column 1,
         // which is the current behaviour on \n.
         let mut height = height.unwrap_or(1);
         let stride = width + skip.unwrap_or(0);
         while height > 0 {
+            self.check_bounds(zstring as usize, width as usize)?;
             let data = self.memory.read(zstring as usize, width as usize).to_vec();
             self.print(&self.bytes_to_string(&data));
 
@@ -2292,6 +3570,8 @@ impl<ZUI: UI> Zmachine<ZUI> {
             height -= 1;
             zstring = zstring.wrapping_add(stride);
         }
+
+        Ok(())
     }
 
     // VAR_255
@@ -2356,8 +3636,7 @@ impl<ZUI: UI> Zmachine<ZUI> {
     }
 
     fn do_save_undo(&mut self, instr: &Instruction) -> u16 {
-        let pc = instr.next - 1;
-        let state = self.make_save_state(pc);
+        let state = self.make_save_state(self.save_pc(instr));
 
         if self.options.undo_limit == 0 {
             return 0; // undos not supported
@@ -2372,11 +3651,12 @@ impl<ZUI: UI> Zmachine<ZUI> {
         1 // successful!
     }
 
-    fn do_restore_undo(&mut self) {
+    fn do_restore_undo(&mut self) -> Result<(), ZmachineError> {
         if let Some(save_contents) = self.undos.pop_back() {
-            self.restore_state(&save_contents);
-            self.process_restore_result();
+            self.restore_state(&save_contents)?;
+            self.process_restore_result()?;
         }
+        Ok(())
     }
 
     fn do_print_unicode(&mut self, code_point: u16) {
@@ -2384,17 +3664,382 @@ impl<ZUI: UI> Zmachine<ZUI> {
             self.print(&ch.to_string());
         }
     }
+
+    // VAR_245
+    fn do_sound_effect(&mut self, args: &[u16]) -> Result<(), ZmachineError> {
+        // With no operands, this is just a bleep.
+        let number = args.first().copied().unwrap_or(1);
+        let effect = args.get(1).copied().unwrap_or(2);
+
+        if number == 1 || number == 2 {
+            self.ui.beep(number == 1);
+            return Ok(());
+        }
+
+        match effect {
+            // prepare and start
+            1 | 2 => {
+                let blorb = match &self.blorb {
+                    Some(blorb) => blorb,
+                    None => return Ok(()),
+                };
+                // Games may ask for sounds that aren't there, and that should be silent.
+                let resource = match blorb.resource(Usage::Sound, number as u32) {
+                    Some(resource) => resource,
+                    None => return Ok(()),
+                };
+                let sound = Sound {
+                    number,
+                    kind: &resource.kind,
+                    data: blorb.data(resource),
+                };
+
+                if effect == 1 {
+                    self.ui.prepare_sound(&sound);
+                    return Ok(());
+                }
+
+                // The low byte is the volume from 1 to 8, with 255 for "as loud as possible";
+                // from V5 on, the high byte is the repeat count, and V3 uses the Blorb's instead.
+                let [repeats, volume] = args.get(2).copied().unwrap_or(0x00FF).to_be_bytes();
+                let volume = volume.clamp(1, 8);
+                let repeats = if self.version >= 5 {
+                    repeats.max(1)
+                } else {
+                    match blorb.sound_repeats(number as u32) {
+                        Some(0) => 255,
+                        Some(count) => count.min(255) as u8,
+                        None => 1,
+                    }
+                };
+                self.ui.play_sound(&sound, volume, repeats);
+
+                let routine = args.get(3).copied().unwrap_or(0);
+                self.sound_routine = if self.version >= 5 && routine != 0 {
+                    Some((number, routine))
+                } else {
+                    None
+                };
+            }
+            // stop, and finish with
+            3 | 4 => {
+                if number == 0 || self.sound_routine.is_some_and(|(n, _)| n == number) {
+                    self.sound_routine = None;
+                }
+                self.ui.stop_sound(number);
+                if effect == 4 {
+                    self.ui.unload_sound(number);
+                }
+            }
+            _ => return Err(self.illegal_operand("sound effect", effect)),
+        }
+
+        Ok(())
+    }
+
+    // EXT_1005 and EXT_1007
+    fn do_draw_picture(&mut self, number: u16, position: &[u16], erase: bool) {
+        let (y, x) = match *position {
+            [y, x, ..] => (y, x),
+            [y] => (y, 0),
+            [] => (0, 0),
+        };
+
+        let blorb = match &self.blorb {
+            Some(blorb) => blorb,
+            None => return,
+        };
+        let resource = match blorb.resource(Usage::Picture, number as u32) {
+            Some(resource) => resource,
+            None => return,
+        };
+        let picture = Picture {
+            number,
+            kind: &resource.kind,
+            data: blorb.data(resource),
+            size: blorb
+                .image_size(resource)
+                .map(|(width, height)| (height as u16, width as u16)),
+        };
+
+        if erase {
+            self.ui.erase_picture(&picture, y, x);
+        } else {
+            self.ui.draw_picture(&picture, y, x);
+        }
+    }
+
+    // EXT_1006
+    fn do_picture_data(&mut self, number: u16, array: u16) -> Result<u16, ZmachineError> {
+        let array = array as usize;
+        self.check_bounds(array, 4)?;
+
+        let blorb = match &self.blorb {
+            Some(blorb) => blorb,
+            None => return Ok(0),
+        };
+
+        // Picture 0 asks for the number of pictures, and the release number of the picture file.
+        if number == 0 {
+            let count = blorb
+                .resources()
+                .iter()
+                .filter(|r| r.usage == Usage::Picture)
+                .count() as u16;
+            self.memory.write_word(array, count);
+            self.memory.write_word(array + 2, 0);
+            return Ok(if count > 0 { 1 } else { 0 });
+        }
+
+        let size = blorb
+            .resource(Usage::Picture, number as u32)
+            .and_then(|r| blorb.image_size(r));
+        match size {
+            Some((width, height)) => {
+                self.memory.write_word(array, height as u16);
+                self.memory.write_word(array + 2, width as u16);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    // EXT_1008
+    fn do_set_margins(
+        &mut self,
+        left: u16,
+        right: u16,
+        window: Option<u16>,
+    ) -> Result<(), ZmachineError> {
+        let number = self.window_number(window.unwrap_or(0xFFFD))?;
+        self.windows[number].set(window::LEFT_MARGIN, left);
+        self.windows[number].set(window::RIGHT_MARGIN, right);
+        self.update_window(number);
+        Ok(())
+    }
+
+    // EXT_1013
+    fn do_set_true_colour(&mut self, foreground: u16, background: u16) {
+        fn true_colour(value: u16) -> Option<Colour> {
+            match value as i16 {
+                -1 => Some(Colour::Default),
+                0..=0x7FFF => Some(Colour::True(value)),
+                // -2 is the current colour, and -3 (V6 only) is the colour under the cursor.
+                _ => None,
+            }
+        }
+
+        if let Some(colour) = true_colour(foreground) {
+            self.current_style.foreground = colour;
+        }
+        if let Some(colour) = true_colour(background) {
+            self.current_style.background = colour;
+        }
+    }
+
+    // EXT_1016
+    fn do_move_window(&mut self, window: u16, y: u16, x: u16) -> Result<(), ZmachineError> {
+        let number = self.window_number(window)?;
+        self.windows[number].set(window::Y_COORD, y);
+        self.windows[number].set(window::X_COORD, x);
+        self.update_window(number);
+        Ok(())
+    }
+
+    // EXT_1017
+    fn do_window_size(&mut self, window: u16, y: u16, x: u16) -> Result<(), ZmachineError> {
+        let number = self.window_number(window)?;
+        self.windows[number].set(window::Y_SIZE, y);
+        self.windows[number].set(window::X_SIZE, x);
+        self.update_window(number);
+        Ok(())
+    }
+
+    // EXT_1018
+    fn do_window_style(
+        &mut self,
+        window: u16,
+        flags: u16,
+        operation: Option<u16>,
+    ) -> Result<(), ZmachineError> {
+        let number = self.window_number(window)?;
+        let attributes = self.windows[number].attributes();
+        let attributes = match operation.unwrap_or(0) {
+            0 => flags,
+            1 => attributes | flags,
+            2 => attributes & !flags,
+            3 => attributes ^ flags,
+            other => return Err(self.illegal_operand("window style operation", other)),
+        };
+        self.windows[number].set(window::ATTRIBUTES, attributes);
+        self.update_window(number);
+        Ok(())
+    }
+
+    // EXT_1019
+    fn do_get_wind_prop(&mut self, window: u16, prop: u16) -> Result<u16, ZmachineError> {
+        let number = self.window_number(window)?;
+
+        // The UI keeps track of the cursor as text is printed, so ask it about the current window.
+        let current = self.window_number(0xFFFD)?;
+        if number == current && (prop == window::Y_CURSOR || prop == window::X_CURSOR) {
+            let (line, column) = self.ui.get_cursor();
+            return Ok(if prop == window::Y_CURSOR {
+                line
+            } else {
+                column
+            });
+        }
+
+        match self.windows[number].get(prop) {
+            Some(value) => Ok(value),
+            None => Err(self.illegal_operand("window property", prop)),
+        }
+    }
+
+    // EXT_1021
+    fn do_pop_stack(&mut self, items: u16, stack: Option<u16>) -> Result<(), ZmachineError> {
+        match stack {
+            Some(stack) => {
+                self.check_bounds(stack as usize, 2)?;
+                let free = self.memory.read_word(stack as usize);
+                self.memory
+                    .write_word(stack as usize, free.wrapping_add(items));
+            }
+            None => {
+                for _ in 0..items {
+                    self.stack_pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // EXT_1022
+    fn do_read_mouse(&mut self, array: u16) -> Result<(), ZmachineError> {
+        // y, x, buttons and menu selection, none of which we have.
+        self.check_bounds(array as usize, 8)?;
+        for i in 0..4 {
+            self.memory.write_word(array as usize + i * 2, 0);
+        }
+        Ok(())
+    }
+
+    // EXT_1024
+    fn do_push_stack(&mut self, value: u16, stack: u16) -> Result<u16, ZmachineError> {
+        let stack = stack as usize;
+        self.check_bounds(stack, 2)?;
+        let free = self.memory.read_word(stack);
+        if free == 0 {
+            return Ok(0);
+        }
+
+        let slot = stack + 2 * free as usize;
+        self.check_bounds(slot, 2)?;
+        self.memory.write_word(slot, value);
+        self.memory.write_word(stack, free - 1);
+        Ok(1)
+    }
+
+    // EXT_1025
+    fn do_put_wind_prop(
+        &mut self,
+        window: u16,
+        prop: u16,
+        value: u16,
+    ) -> Result<(), ZmachineError> {
+        let number = self.window_number(window)?;
+        if !self.windows[number].set(prop, value) {
+            return Err(self.illegal_operand("window property", prop));
+        }
+        self.update_window(number);
+        Ok(())
+    }
+
+    // EXT_1026
+    fn do_print_form(&mut self, table: u16) -> Result<(), ZmachineError> {
+        // A series of lines, each a word length followed by that many characters, ending with 0.
+        let mut addr = table as usize;
+        let mut first = true;
+        loop {
+            self.check_bounds(addr, 2)?;
+            let length = self.memory.read_word(addr) as usize;
+            addr += 2;
+            if length == 0 {
+                break;
+            }
+
+            self.check_bounds(addr, length)?;
+            let line = self.bytes_to_string(self.memory.read(addr, length));
+            if !first {
+                self.print("\n");
+            }
+            self.print(&line);
+            addr += length;
+            first = false;
+        }
+        Ok(())
+    }
 }
 
 // debug functions
 #[allow(dead_code)]
 impl<ZUI: UI> Zmachine<ZUI> {
     pub fn get_dictionary(&self) -> Vec<String> {
-        let mut words: Vec<String> = self.dictionary.keys().cloned().collect();
+        let mut words: Vec<String> = (0..self.dictionary.entry_count)
+            .map(|n| {
+                self.read_zstring(self.dictionary.entry(n))
+                    .unwrap_or_default()
+            })
+            .collect();
         words.sort();
         words
     }
 
+    /// Every entry in the standard dictionary, in the order they're stored.
+    pub fn get_dictionary_entries(&self) -> Vec<DictionaryEntry> {
+        let word_length = self.encoded_word_length();
+        (0..self.dictionary.entry_count)
+            .map(|n| {
+                let addr = self.dictionary.entry(n);
+                DictionaryEntry {
+                    addr,
+                    word: self.read_zstring(addr).unwrap_or_default(),
+                    data: self
+                        .memory
+                        .read(
+                            addr + word_length,
+                            self.dictionary.entry_length - word_length,
+                        )
+                        .to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// The ZSCII characters that the dictionary splits words on, as well as spaces.
+    pub fn get_word_separators(&self) -> &[u8] {
+        &self.dictionary.separators
+    }
+
+    /// The expansion of each abbreviation: none in V1, 32 in V2 and 96 from V3 on. Any that
+    /// can't be decoded are left empty.
+    pub fn get_abbreviations(&self) -> Vec<String> {
+        let count = match self.version {
+            1 => 0,
+            2 => 32,
+            _ => 96,
+        };
+        (0..count)
+            .map(|index| self.get_abbrev(index, &mut vec![index]).unwrap_or_default())
+            .collect()
+    }
+
+    /// The three alphabets, A0 to A2, that zchars 6 to 31 pick from.
+    pub fn get_alphabets(&self) -> &[Vec<String>; 3] {
+        &self.alphabet
+    }
+
     fn get_object_number(&self, input: &str) -> u16 {
         if let Ok(num) = input.parse() {
             num
@@ -2405,3 +4050,782 @@ impl<ZUI: UI> Zmachine<ZUI> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::BaseUI;
+
+    fn output_text(zvm: &mut Zmachine<BaseUI>) -> String {
+        zvm.ui
+            .drain_output()
+            .into_iter()
+            .map(|output| output.content)
+            .collect()
+    }
+
+    // A story with no code, just enough header for `Zmachine::new` and a couple of zstrings.
+    fn story_with_strings(version: u8, strings: &[(usize, &[u8])]) -> Zmachine<BaseUI> {
+        let mut data = vec![0; 0x100];
+        data[0x00] = version;
+        data[0x08..0x0A].copy_from_slice(&0x40u16.to_be_bytes()); // dictionary
+        data[0x0E..0x10].copy_from_slice(&0x40u16.to_be_bytes()); // static memory
+        data[0x18..0x1A].copy_from_slice(&0x50u16.to_be_bytes()); // abbreviations
+        data[0x41] = 7; // no separators or entries in the dictionary
+        data[0x50..0x52].copy_from_slice(&0x30u16.to_be_bytes()); // abbreviation 0 is at 0x60
+
+        for (addr, zchars) in strings {
+            let mut zchars = zchars.to_vec();
+            while zchars.len() % 3 != 0 {
+                zchars.push(5);
+            }
+            for (i, chunk) in zchars.chunks(3).enumerate() {
+                let mut word = (chunk[0] as u16) << 10 | (chunk[1] as u16) << 5 | chunk[2] as u16;
+                if (i + 1) * 3 == zchars.len() {
+                    word |= 0x8000;
+                }
+                data[(addr + i * 2)..(addr + i * 2 + 2)].copy_from_slice(&word.to_be_bytes());
+            }
+        }
+
+        Zmachine::new(data, BaseUI::new(), Options::default())
+    }
+
+    #[test]
+    fn test_v1_zstrings() {
+        // h i <newline> <lock A1> H I <shift A2> 0 J
+        let zvm = story_with_strings(1, &[(0x80, &[13, 14, 1, 4, 13, 14, 2, 7, 15])]);
+        assert_eq!(zvm.read_zstring(0x80).unwrap(), "hi\nHI0J");
+    }
+
+    #[test]
+    fn test_v2_zstrings() {
+        // <abbrev 0> <lock A2> 0 <shift A1> H 5
+        let zvm = story_with_strings(2, &[(0x60, &[13, 14]), (0x80, &[1, 0, 5, 8, 3, 13, 13])]);
+        assert_eq!(zvm.read_zstring(0x80).unwrap(), "hi0H5");
+    }
+
+    #[test]
+    fn test_bad_story_data() {
+        // abbreviation 0 is itself
+        let mut zvm = story_with_strings(3, &[(0x60, &[1, 0]), (0x80, &[1, 0])]);
+        assert_eq!(
+            zvm.read_zstring(0x80),
+            Err(ZmachineError::IllegalOperand {
+                pc: zvm.pc,
+                operand: "abbreviation",
+                value: 0,
+            })
+        );
+
+        // store 255 1: the last global is well past the end of this story
+        zvm.memory.write(0xA0, &[0x0D, 0xFF, 0x01]);
+        zvm.pc = 0xA0;
+        assert_eq!(
+            zvm.step(),
+            Err(ZmachineError::IllegalOperand {
+                pc: 0xA0,
+                operand: "variable",
+                value: 255,
+            })
+        );
+
+        // nothing is waiting for the player
+        let not_paused = Err(ZmachineError::NotPaused { pc: zvm.pc });
+        assert_eq!(zvm.handle_read_char(ZChar::RETURN), not_paused);
+        assert_eq!(zvm.handle_save_result(true), not_paused);
+        assert_eq!(zvm.handle_restore_result(), not_paused);
+    }
+
+    #[test]
+    fn test_v6_windows() {
+        let mut zvm = story_with_strings(6, &[]);
+        let (width, height) = zvm.options.dimensions;
+
+        zvm.do_split_window(3);
+        assert_eq!(zvm.do_get_wind_prop(1, window::Y_SIZE).unwrap(), 3);
+        assert_eq!(zvm.do_get_wind_prop(1, window::X_SIZE).unwrap(), width);
+        assert_eq!(zvm.do_get_wind_prop(0, window::Y_COORD).unwrap(), 4);
+        assert_eq!(zvm.do_get_wind_prop(0, window::Y_SIZE).unwrap(), height - 3);
+
+        zvm.do_put_wind_prop(5, window::LEFT_MARGIN, 7).unwrap();
+        assert_eq!(zvm.windows[5].margins(), (7, 0));
+        zvm.do_window_style(5, window::SCROLLING, Some(1)).unwrap();
+        assert_eq!(
+            zvm.do_get_wind_prop(5, window::ATTRIBUTES).unwrap(),
+            window::WRAPPING | window::SCROLLING
+        );
+        assert!(zvm.do_get_wind_prop(8, 0).is_err());
+        assert!(zvm.do_get_wind_prop(0, 18).is_err());
+
+        zvm.do_set_window(5).unwrap();
+        assert_eq!(zvm.current_window, Window::Other(5));
+    }
+
+    #[test]
+    fn test_user_stacks() {
+        let mut zvm = story_with_strings(6, &[]);
+        // a user stack at 0x30 with room for two values
+        zvm.memory.write_word(0x30, 2);
+
+        assert_eq!(zvm.do_push_stack(10, 0x30).unwrap(), 1);
+        assert_eq!(zvm.do_push_stack(20, 0x30).unwrap(), 1);
+        assert_eq!(zvm.do_push_stack(30, 0x30).unwrap(), 0);
+
+        assert_eq!(zvm.do_pull_stack(0x30).unwrap(), 20);
+        zvm.do_pop_stack(1, Some(0x30)).unwrap();
+        assert_eq!(zvm.memory.read_word(0x30), 2);
+    }
+
+    #[test]
+    fn test_sound_effects() {
+        use crate::traits::SoundEvent;
+
+        // A Blorb with a single sound: FORM header, then a one-entry index, then the AIFF chunk.
+        let mut blorb = b"FORM\0\0\0\x26IFRS".to_vec();
+        blorb.extend(b"RIdx\0\0\0\x10\0\0\0\x01Snd \0\0\0\x03\0\0\0\x24");
+        blorb.extend(b"AIFF\0\0\0\x02ok");
+        let blorb = Blorb::from_bytes(blorb).unwrap();
+
+        let mut zvm = story_with_strings(5, &[]);
+        zvm.set_blorb(blorb);
+        assert_eq!(zvm.memory.read_word(0x10) & 0x80, 0);
+
+        // The end routine prints an x: print_char 'x', rtrue. Then the main code just quits.
+        zvm.memory.write(0x80, &[0x00, 0xE5, 0x7F, b'x', 0xB0]);
+        zvm.memory.write(0xA0, &[0xBA]);
+        zvm.pc = 0xA0;
+
+        zvm.do_sound_effect(&[]).unwrap();
+        zvm.do_sound_effect(&[3, 2, 0x0208, 0x20]).unwrap();
+        // sounds that aren't in the Blorb are ignored
+        zvm.do_sound_effect(&[4, 2, 0x0008, 0x20]).unwrap();
+        assert_eq!(
+            zvm.ui.drain_sounds(),
+            vec![
+                SoundEvent::Beep { high: true },
+                SoundEvent::Play {
+                    number: 3,
+                    volume: 8,
+                    repeats: 2
+                },
+            ]
+        );
+
+        // Only the sound that's playing has an end routine, and it runs before the next instruction.
+        zvm.handle_sound_finished(4).unwrap();
+        assert_eq!(zvm.sound_interrupt, None);
+        zvm.handle_sound_finished(3).unwrap();
+        assert!(matches!(zvm.step().unwrap(), Step::Done));
+        assert_eq!(output_text(&mut zvm), "x");
+
+        // Stopping a sound means its routine never runs.
+        zvm.do_sound_effect(&[3, 2, 0x0108, 0x20]).unwrap();
+        zvm.do_sound_effect(&[3, 3]).unwrap();
+        zvm.handle_sound_finished(3).unwrap();
+        assert_eq!(zvm.sound_interrupt, None);
+    }
+
+    #[test]
+    fn test_verify_story() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let mut zvm = Zmachine::new(data.clone(), BaseUI::new(), Options::default());
+        assert!(zvm.verify_story());
+
+        // Writing to dynamic memory doesn't count as corruption...
+        zvm.memory.write_byte(zvm.static_start - 1, 0xFF);
+        assert!(zvm.verify_story());
+
+        // ...but damage to the file does, as does losing the end of it.
+        let mut corrupt = data.clone();
+        corrupt[0x1000] ^= 0x01;
+        assert!(!Zmachine::new(corrupt, BaseUI::new(), Options::default()).verify_story());
+        let truncated = data[..data.len() - 1].to_vec();
+        assert!(!Zmachine::new(truncated, BaseUI::new(), Options::default()).verify_story());
+
+        let curses = include_bytes!("../tests/curses.z3").to_vec();
+        assert!(Zmachine::new(curses, BaseUI::new(), Options::default()).verify_story());
+    }
+
+    #[test]
+    fn test_encode_zstring() {
+        let zvm = story_with_strings(3, &[]);
+        // h e l l o <pad>
+        assert_eq!(zvm.encode_zstring("hello"), [0x35, 0x51, 0xC6, 0x85]);
+        // <shift A2> 2 <shift A2> <escape> and @ as a 10-bit code
+        assert_eq!(zvm.encode_zstring("2@"), [0x15, 0x45, 0x98, 0x40]);
+
+        // V1 and V2 shift with 2 and 3 instead: <shift A1> A
+        let zvm = story_with_strings(2, &[]);
+        assert_eq!(zvm.encode_zstring("A"), [0x08, 0xC5, 0x94, 0xA5]);
+
+        // Later versions get nine zchars, and escape anything that isn't in the alphabets.
+        let zvm = story_with_strings(5, &[]);
+        assert_eq!(
+            zvm.encode_zstring("é!"),
+            [0x14, 0xC5, 0x28, 0xB4, 0x94, 0xA5]
+        );
+    }
+
+    #[test]
+    fn test_check_dict() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+        let dictionary = &zvm.dictionary;
+        assert!(dictionary.sorted);
+
+        let mailbox = zvm.check_dict(dictionary, "mailbox");
+        assert_ne!(mailbox, 0);
+        assert_eq!(zvm.read_zstring(mailbox).unwrap(), "mailbo");
+        assert_eq!(zvm.check_dict(dictionary, "mailbo"), mailbox);
+        assert_eq!(zvm.check_dict(dictionary, "qwerty"), 0);
+        assert_eq!(zvm.check_dict(dictionary, "@"), 0);
+
+        let words = zvm.get_dictionary();
+        assert_eq!(words.len(), dictionary.entry_count);
+        assert!(words
+            .iter()
+            .all(|word| zvm.check_dict(dictionary, word) != 0));
+    }
+
+    #[test]
+    fn test_tokenise_user_dictionary() {
+        let mut zvm = story_with_strings(5, &[]);
+
+        // An unsorted user dictionary at 0x80, with a comma for a separator and two 6-byte entries.
+        let (zebra, apple) = (zvm.encode_zstring("zebra"), zvm.encode_zstring("apple"));
+        zvm.memory.write(0x80, &[1, b',', 6, 0xFF, 0xFE]);
+        zvm.memory.write(0x85, &zebra);
+        zvm.memory.write(0x8B, &apple);
+
+        let text = b"apple,pear zebra";
+        zvm.memory.write(0xC0, &[20, text.len() as u8]);
+        zvm.memory.write(0xC2, text);
+        zvm.memory.write(0xE0, &[4; 18]);
+        zvm.memory.write(0xE2, &[0xAA; 16]);
+
+        // Unknown words keep whatever was in the parse buffer before.
+        zvm.do_tokenise(0xC0, 0xE0, 0x80, true).unwrap();
+        assert_eq!(zvm.memory.read_byte(0xE1), 4);
+        assert_eq!(zvm.memory.read(0xE2, 4), [0x00, 0x8B, 5, 2]);
+        assert_eq!(zvm.memory.read(0xE6, 8), [0xAA; 8]);
+        assert_eq!(zvm.memory.read(0xEE, 4), [0x00, 0x85, 5, 13]);
+
+        // Without the flag they're written as 0, and the buffer only takes as many as it has room for.
+        zvm.memory.write_byte(0xE0, 2);
+        zvm.do_tokenise(0xC0, 0xE0, 0x80, false).unwrap();
+        assert_eq!(zvm.memory.read_byte(0xE1), 2);
+        assert_eq!(zvm.memory.read(0xE6, 4), [0x00, 0x00, 1, 7]);
+        assert_eq!(zvm.check_dict(&zvm.dictionary, "zebra"), 0);
+    }
+
+    #[test]
+    fn test_zscii_input() {
+        let mut zvm = story_with_strings(5, &[]);
+        zvm.memory.write(0xC0, &[20]);
+        zvm.memory.write(0xE0, &[4]);
+
+        // Accented letters come from the unicode table, and anything else becomes a question mark.
+        zvm.do_sread_second(0xC0, 0xE0, "Ça\tva☺".to_string())
+            .unwrap();
+        let cedilla = ZChar::from_char('ç', DEFAULT_UNICODE_TABLE).unwrap().0;
+        assert_eq!(
+            zvm.memory.read(0xC1, 7),
+            [6, cedilla, b'a', b' ', b'v', b'a', b'?']
+        );
+
+        // Token positions count ZSCII characters, not UTF-8 bytes.
+        assert_eq!(zvm.memory.read_byte(0xE1), 2);
+        assert_eq!(zvm.memory.read(0xE2, 8), [0, 0, 2, 2, 0, 0, 3, 5]);
+    }
+
+    #[test]
+    fn test_terminating_chars() {
+        let mut zvm = story_with_strings(5, &[]);
+        zvm.memory.write(0xC0, &[20]);
+        zvm.memory.write(0x90, &[133, 13, 0]);
+        zvm.memory.write_word(0x2E, 0x90);
+
+        // aread 0xC0 0 -> sp, twice
+        zvm.memory.write(0xA0, &[0xE4, 0x5F, 0xC0, 0x00, 0x00]);
+        zvm.memory.write(0xA5, &[0xE4, 0x5F, 0xC0, 0x00, 0x00]);
+        zvm.pc = 0xA0;
+
+        // Only real function keys count, so return isn't listed.
+        let step = zvm.step().unwrap();
+        assert_eq!(
+            step,
+            Step::ReadLine {
+                timeout: None,
+                terminators: vec![ZChar(133)],
+                preloaded: String::new(),
+            }
+        );
+        zvm.handle_terminated_input("north".to_string(), ZChar(133))
+            .unwrap();
+        assert_eq!(zvm.stack_pop(), 133);
+
+        // The store is return for keys the game didn't ask for, and for ordinary input.
+        zvm.step().unwrap();
+        zvm.handle_terminated_input("south".to_string(), ZChar::UP)
+            .unwrap();
+        assert_eq!(zvm.stack_pop(), 13);
+
+        // 255 stands for all the function keys at once.
+        zvm.memory.write(0x90, &[255, 0]);
+        let terminators = zvm.terminating_chars();
+        assert_eq!(terminators.len(), 29);
+        assert!(terminators.contains(&ZChar::UP));
+    }
+
+    #[test]
+    fn test_preloaded_input() {
+        let mut zvm = story_with_strings(5, &[]);
+        zvm.memory.write(0xC0, &[20, 3]);
+        zvm.memory.write(0xC2, b"nor");
+
+        // aread 0xC0 0 -> sp
+        zvm.memory.write(0xA0, &[0xE4, 0x5F, 0xC0, 0x00, 0x00]);
+        zvm.pc = 0xA0;
+
+        match zvm.step().unwrap() {
+            Step::ReadLine { preloaded, .. } => assert_eq!(preloaded, "nor"),
+            step => panic!("expected a line read, got {:?}", step),
+        }
+
+        // The frontend hands back the whole line, including the part that was already there.
+        zvm.handle_input("north".to_string()).unwrap();
+        assert_eq!(zvm.memory.read(0xC1, 6), [5, b'n', b'o', b'r', b't', b'h']);
+
+        // there's nothing left waiting for input
+        assert_eq!(
+            zvm.handle_input("south".to_string()),
+            Err(ZmachineError::NotPaused { pc: zvm.pc })
+        );
+    }
+
+    #[test]
+    fn test_timed_input() {
+        let mut zvm = story_with_strings(5, &[]);
+        assert_eq!(
+            zvm.handle_timeout(""),
+            Err(ZmachineError::NotPaused { pc: zvm.pc })
+        );
+
+        zvm.memory.write(0xC0, &[20, 0]);
+        // aread 0xC0 0 10 R -> sp; quit
+        zvm.memory
+            .write(0xA0, &[0xE4, 0x55, 0xC0, 0x00, 0x0A, 0x2C, 0x00, 0xBA]);
+        // the routine at 0xB0 (packed 2C) returns false, and the one at 0xB4 (2D) true
+        zvm.memory
+            .write(0xB0, &[0x00, 0xB1, 0x00, 0x00, 0x00, 0xB0]);
+        zvm.pc = 0xA0;
+
+        match zvm.step().unwrap() {
+            Step::ReadLine { timeout, .. } => assert_eq!(timeout, Some(Duration::from_secs(1))),
+            step => panic!("expected a line read, got {:?}", step),
+        }
+
+        // Returning false carries on with the read, with the partial input in the buffer...
+        assert_eq!(zvm.handle_timeout("nor"), Ok(false));
+        assert_eq!(zvm.memory.read(0xC1, 4), [3, b'n', b'o', b'r']);
+        match zvm.step().unwrap() {
+            Step::ReadLine { preloaded, .. } => assert_eq!(preloaded, "nor"),
+            step => panic!("expected a line read, got {:?}", step),
+        }
+
+        // ...and returning true ends it, with 0 as the terminating character.
+        zvm.memory.write_byte(0xA5, 0x2D);
+        zvm.step().unwrap();
+        assert_eq!(zvm.handle_timeout("nort"), Ok(true));
+        assert_eq!(zvm.pc, 0xA7);
+        assert_eq!(zvm.frames.last().unwrap().stack(), [0]);
+        assert_eq!(zvm.step().unwrap(), Step::Done);
+    }
+
+    #[test]
+    fn test_v6_main_routine() {
+        let mut data = story_with_strings(6, &[]).memory.slice(0, 0x100).to_vec();
+        data[0x06..0x08].copy_from_slice(&0x28u16.to_be_bytes()); // main routine at 0xA0
+
+        // 2 locals; add L01 5 -> L02; quit
+        data[0xA0..0xA6].copy_from_slice(&[0x02, 0x54, 0x01, 0x05, 0x02, 0xBA]);
+
+        let mut opts = Options::default();
+        opts.instruction_budget = Some(1);
+        let mut zvm = Zmachine::new(data, BaseUI::new(), opts);
+        assert_eq!(zvm.pc, 0xA1);
+        assert_eq!(zvm.frames.len(), 1);
+
+        assert_eq!(zvm.step().unwrap(), Step::Yield);
+        assert_eq!(zvm.pc, 0xA5);
+        assert_eq!(zvm.read_variable(2).unwrap(), 5);
+
+        // restarting calls it again, with fresh locals
+        zvm.do_restart();
+        assert_eq!(zvm.pc, 0xA1);
+        assert_eq!(zvm.frames.len(), 1);
+        assert_eq!(zvm.read_variable(2).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_instruction_budget() {
+        let mut zvm = story_with_strings(5, &[]);
+        zvm.options.instruction_budget = Some(100);
+
+        // jump -1, which jumps to itself forever
+        zvm.memory.write(0xA0, &[0x8C, 0xFF, 0xFF]);
+        zvm.pc = 0xA0;
+
+        assert_eq!(zvm.step().unwrap(), Step::Yield);
+        assert_eq!(zvm.pc, 0xA0);
+        assert_eq!(zvm.step().unwrap(), Step::Yield);
+    }
+
+    #[test]
+    fn test_interrupt_budget() {
+        let mut zvm = story_with_strings(5, &[]);
+        zvm.options.instruction_budget = Some(100);
+
+        // aread 0xC0 0 10 R -> sp, where R (at 0xB0) jumps to itself forever
+        zvm.memory.write(0xC0, &[20, 0]);
+        zvm.memory
+            .write(0xA0, &[0xE4, 0x55, 0xC0, 0x00, 0x0A, 0x2C, 0x00]);
+        zvm.memory.write(0xB0, &[0x00, 0x8C, 0xFF, 0xFF]);
+        zvm.pc = 0xA0;
+        assert!(matches!(zvm.step().unwrap(), Step::ReadLine { .. }));
+
+        assert_eq!(
+            zvm.handle_timeout("n"),
+            Err(ZmachineError::InterruptBudget { routine: 0xB0 })
+        );
+
+        // the routine is abandoned, and the read carries on
+        assert_eq!(zvm.frames.len(), 1);
+        assert!(zvm.frames[0].stack().is_empty());
+        assert_eq!(zvm.pc, 0xA0);
+        match zvm.step().unwrap() {
+            Step::ReadLine { preloaded, .. } => assert_eq!(preloaded, "n"),
+            step => panic!("expected a line read, got {:?}", step),
+        }
+    }
+
+    #[test]
+    fn test_interrupts_not_debugged() {
+        let mut zvm = story_with_strings(5, &[]);
+
+        // aread 0xC0 0 10 R -> sp, where R (at 0xB0) is `rfalse`
+        zvm.memory.write(0xC0, &[20, 0]);
+        zvm.memory
+            .write(0xA0, &[0xE4, 0x55, 0xC0, 0x00, 0x0A, 0x2C, 0x00]);
+        zvm.memory.write(0xB0, &[0x00, 0xB1]);
+        zvm.pc = 0xA0;
+        zvm.debugger().add_routine_breakpoint(0xB0);
+        zvm.debugger().add_breakpoint(0xB1);
+        assert!(matches!(zvm.step().unwrap(), Step::ReadLine { .. }));
+
+        // the routine runs straight through, and doesn't leave a stop behind for later
+        assert_eq!(zvm.handle_timeout(""), Ok(false));
+        assert!(matches!(zvm.step().unwrap(), Step::ReadLine { .. }));
+    }
+
+    #[test]
+    fn test_story_details() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+
+        let abbreviations = zvm.get_abbreviations();
+        assert_eq!(abbreviations.len(), 96);
+        assert_eq!(abbreviations[0], "the ");
+
+        let entries = zvm.get_dictionary_entries();
+        assert_eq!(entries.len(), zvm.get_dictionary().len());
+        assert_eq!(entries[1].word, ".");
+        assert_eq!(entries[1].data, [0x04, 0xf1, 0x00]);
+
+        fn find(object: &Object, number: u16) -> Option<&Object> {
+            if object.number() == number {
+                return Some(object);
+            }
+            object
+                .children()
+                .iter()
+                .find_map(|child| find(child, number))
+        }
+        let tree = zvm.get_object_tree();
+        let mailbox = find(&tree, 167).unwrap();
+        assert_eq!(mailbox.name(), "small mailbox");
+        assert_eq!(mailbox.attributes(), [9, 11, 18]);
+        assert_eq!(mailbox.properties()[0], (18, vec![0x42, 0xc5]));
+        assert!(tree
+            .to_detailed_string()
+            .contains("attributes: [9, 11, 18]"));
+    }
+
+    #[test]
+    fn test_object_view() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+
+        assert!(zvm.object(0).is_none());
+        assert_eq!(zvm.objects().count(), zvm.get_total_object_count() as usize);
+
+        let mailbox = zvm.object(167).unwrap();
+        assert_eq!(mailbox.name(), "small mailbox");
+        assert_eq!(mailbox.attribute_count(), 32);
+        assert_eq!(mailbox.attributes(), [9, 11, 18]);
+        assert!(mailbox.has_attribute(9) && !mailbox.has_attribute(10));
+        assert!(!mailbox.has_attribute(32));
+        assert_eq!(mailbox.property(18), Some(&[0x42, 0xc5][..]));
+        assert_eq!(mailbox.property(12), None);
+
+        let room = mailbox.parent().unwrap();
+        assert!(room.children().any(|object| object == mailbox));
+        assert!(mailbox.is_in(&room));
+        assert!(!room.is_in(&mailbox));
+
+        assert_eq!(room.name(), "West of House");
+        assert_eq!(
+            room.to_string(),
+            format!("West of House ({})", room.number())
+        );
+
+        // V3 games keep the player's location in the first global
+        let room = room.number();
+        let mut zvm = zvm;
+        zvm.poke_variable(16, room);
+        assert_eq!(zvm.player_location().unwrap().number(), room);
+
+        // V4+ objects are bigger, and the last one is still reachable
+        let data = include_bytes!("../tests/praxix.z5").to_vec();
+        let zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+        assert_eq!(zvm.objects().count(), 25);
+        let gestalt = zvm.object(25).unwrap();
+        assert_eq!(gestalt.name(), "(gestalt)");
+        assert_eq!(gestalt.attribute_count(), 48);
+        assert!(zvm.object(26).is_none());
+    }
+
+    #[test]
+    fn test_restore_other_interpreters_save() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let mut zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+        let story = zvm.story_id();
+
+        // A save made from the `save ?~L` at 575f (in the routine at 575e), with the address of
+        // its branch data as the pc, the way other interpreters write it. The score (g1) is 5.
+        fn make_save(story: &StoryId, score_addr: usize) -> Vec<u8> {
+            let mut ifhd = story.release.to_be_bytes().to_vec();
+            ifhd.extend(story.serial);
+            ifhd.extend(story.chksum.to_be_bytes());
+            ifhd.extend([0x00, 0x57, 0x60]);
+
+            let mut cmem = vec![];
+            let mut skip = score_addr;
+            while skip > 256 {
+                cmem.extend([0, 255]);
+                skip -= 256;
+            }
+            cmem.extend([0, skip as u8 - 1, 5]);
+
+            #[rustfmt::skip]
+            let stks = [
+                0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0x50, 0x00, 0x10, 0, 0, 0, 0,
+            ];
+
+            let mut form_body = b"IFZS".to_vec();
+            QuetzalSave::write_chunk(&mut form_body, "IFhd", &ifhd);
+            QuetzalSave::write_chunk(&mut form_body, "CMem", &cmem);
+            QuetzalSave::write_chunk(&mut form_body, "Stks", &stks);
+            QuetzalSave::write_chunk(&mut form_body, "ANNO", b"before the troll");
+            let mut bytes = vec![];
+            QuetzalSave::write_chunk(&mut bytes, "FORM", &form_body);
+            bytes
+        }
+        let score_addr = zvm.globals_addr + 3;
+
+        let other_game = StoryId {
+            release: story.release + 1,
+            ..story
+        };
+        match zvm.restore(&make_save(&other_game, score_addr)) {
+            Err(ZmachineError::BadSave(reason)) => assert!(reason.contains("but this is release")),
+            other => panic!("restored a save from another release: {:?}", other),
+        }
+
+        zvm.restore(&make_save(&story, score_addr)).unwrap();
+        // the save succeeded, so it doesn't branch to the failure message
+        assert_eq!(zvm.pc, 0x5761);
+        assert_eq!(zvm.read_global(1).unwrap(), 5);
+        assert_eq!(zvm.frames.len(), 2);
+        assert_eq!(zvm.frames[0].store, None);
+
+        let resaved =
+            QuetzalSave::from_bytes(&zvm.make_save_state(0x5760), &zvm.original_dynamic).unwrap();
+        assert_eq!(resaved.story, story);
+        assert_eq!(
+            resaved.chunks,
+            [("ANNO".to_string(), b"before the troll".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_transcript() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let mut zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+
+        // The game turns the transcript on by setting bit 0 of Flags 2, as SCRIPT does.
+        zvm.memory.write_word(0x10, zvm.memory.read_word(0x10) | 1);
+        assert!(zvm.is_transcript_enabled());
+        zvm.step().unwrap();
+        assert!(zvm.ui.drain_transcript().contains("West of House"));
+
+        // The player's input goes in too, followed by whatever it prints.
+        zvm.handle_input("open mailbox".to_string()).unwrap();
+        zvm.step().unwrap();
+        let transcript = zvm.ui.drain_transcript();
+        assert!(transcript.contains("open mailbox\n"));
+        assert!(transcript.contains("Opening the small mailbox reveals a leaflet."));
+
+        zvm.set_transcript(false);
+        zvm.handle_input("look".to_string()).unwrap();
+        zvm.step().unwrap();
+        assert_eq!(zvm.ui.drain_transcript(), "");
+    }
+
+    #[test]
+    fn test_command_recording() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let mut zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+        zvm.step().unwrap();
+
+        zvm.set_command_recording(true);
+        zvm.handle_input("open mailbox".to_string()).unwrap();
+        zvm.step().unwrap();
+        assert_eq!(zvm.ui.drain_commands(), ["open mailbox"]);
+
+        // Save with the transcript off...
+        zvm.handle_input("save".to_string()).unwrap();
+        let save = match zvm.step().unwrap() {
+            Step::Save(data) => data,
+            step => panic!("expected a save, got {:?}", step),
+        };
+        zvm.handle_save_result(true).unwrap();
+        zvm.step().unwrap();
+        assert_eq!(zvm.ui.drain_commands(), ["save"]);
+
+        // ...and it stays on after restoring, and after restarting, since it's the player's choice.
+        zvm.set_transcript(true);
+        zvm.restore(&save).unwrap();
+        assert!(zvm.is_transcript_enabled());
+        zvm.do_restart();
+        assert!(zvm.is_transcript_enabled());
+
+        zvm.step().unwrap();
+        zvm.handle_input("look".to_string()).unwrap();
+        zvm.step().unwrap();
+        assert_eq!(zvm.ui.drain_commands(), ["look"]);
+        assert!(zvm.ui.drain_transcript().contains("look\n"));
+    }
+
+    #[test]
+    fn test_restore_fixture_saves() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/saves");
+        let saves: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sav"))
+            .collect();
+        assert!(!saves.is_empty(), "no .sav files in tests/saves");
+
+        for path in saves {
+            let name = path.display();
+            let data = include_bytes!("../tests/minizork.z3").to_vec();
+            let mut zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+            zvm.step().unwrap();
+            output_text(&mut zvm);
+
+            let save = std::fs::read(&path).unwrap();
+            if let Err(e) = zvm.restore(&save) {
+                panic!("couldn't restore {}: {}", name, e);
+            }
+
+            // Every interpreter saves from the same `save ?~L` at 575f, so the game carries on
+            // just past its branch data, 2 moves in, holding the leaflet from the open mailbox.
+            assert_eq!(zvm.pc, 0x5761, "{}", name);
+            assert_eq!(zvm.frames.len(), 5, "{}", name);
+            assert_eq!(zvm.read_global(2).unwrap(), 2, "{}", name);
+            let leaflet = zvm.objects().find(|o| o.name() == "leaflet").unwrap();
+            assert_eq!(leaflet.parent(), zvm.player(), "{}", name);
+            assert_eq!(zvm.player_location().unwrap().name(), "West of House");
+            assert!(matches!(zvm.step().unwrap(), Step::ReadLine { .. }));
+
+            zvm.handle_input("read leaflet".to_string()).unwrap();
+            zvm.step().unwrap();
+            assert!(output_text(&mut zvm).contains("WELCOME TO ZORK"));
+        }
+    }
+
+    #[test]
+    fn test_debugger() {
+        let mut zvm = story_with_strings(5, &[]);
+        zvm.globals_addr = 0xC0;
+
+        // store g0 5; inc g0; jump -1
+        zvm.memory
+            .write(0xA0, &[0x0D, 0x10, 0x05, 0x95, 0x10, 0x8C, 0xFF, 0xFF]);
+        zvm.pc = 0xA0;
+
+        zvm.debugger().interrupt();
+        assert_eq!(zvm.step().unwrap(), Step::Break(Stop::Interrupted));
+        zvm.debugger().watch(Watch::Global(0));
+        zvm.debugger().add_breakpoint(0xA3);
+
+        assert_eq!(
+            zvm.step().unwrap(),
+            Step::Break(Stop::Watch {
+                watch: Watch::Global(0),
+                pc: 0xA0,
+                old: vec![0],
+                new: vec![5],
+            })
+        );
+        assert_eq!(zvm.step().unwrap(), Step::Break(Stop::Breakpoint(0xA3)));
+        assert!(zvm.poke_variable(16, 10));
+        zvm.debugger().unwatch(&Watch::Global(0));
+        zvm.debugger().set_stepping(true);
+        assert_eq!(zvm.step().unwrap(), Step::Break(Stop::Stepped));
+        assert_eq!(zvm.pc(), 0xA5);
+        assert_eq!(zvm.peek_variable(16), Some(11));
+        assert_eq!(zvm.peek_variable(1), None);
+        assert_eq!(zvm.frames().len(), 1);
+
+        zvm.detach_debugger();
+        zvm.options.instruction_budget = Some(10);
+        assert_eq!(zvm.step().unwrap(), Step::Yield);
+    }
+
+    #[test]
+    fn test_replay_commands() {
+        let data = include_bytes!("../tests/minizork.z3").to_vec();
+        let mut zvm = Zmachine::new(data, BaseUI::new(), Options::default());
+        zvm.replay_commands("open mailbox\nread leaflet\n");
+        assert!(zvm.is_replaying());
+
+        // The replayed commands are consumed without bothering the frontend...
+        let step = zvm.step().unwrap();
+        assert!(matches!(step, Step::ReadLine { .. }));
+        assert!(!zvm.is_replaying());
+
+        let output = output_text(&mut zvm);
+        assert!(output.contains("open mailbox\n"));
+        assert!(output.contains("Opening the small mailbox reveals a leaflet."));
+        assert!(output.contains("WELCOME TO ZORK"));
+
+        // ...and once they run out, input comes from the frontend as usual.
+        zvm.handle_input("look".to_string()).unwrap();
+        zvm.step().unwrap();
+        assert!(output_text(&mut zvm).contains("West of House"));
+    }
+}
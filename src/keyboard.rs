@@ -10,6 +10,9 @@ const KEYBOARD_WIDTH: i32 = 1260;
 const KEY_HEIGHT: i32 = 60;
 const KEY_PADDING: i32 = 10;
 const LABEL_HEIGHT: i32 = 33;
+// The blank space left of the space bar, where we put any extra keys that can end a line.
+const EXTRA_KEYS_WIDTH: i32 = 360;
+const EXTRA_KEY_WIDTH: i32 = 90;
 
 #[derive(Clone, Copy, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub enum KeyPress {
@@ -26,6 +29,8 @@ pub struct Key {
 
 pub struct Keyboard {
     keys: Vec<Vec<Key>>,
+    font: &'static Font<'static>,
+    extra_keys: usize,
     pub shift: usize,
 }
 
@@ -164,7 +169,7 @@ impl Keyboard {
             ],
             vec![
                 Key {
-                    width: 360,
+                    width: EXTRA_KEYS_WIDTH,
                     special: true,
                     align: 0.0,
                     chars: vec![],
@@ -202,7 +207,53 @@ impl Keyboard {
             ],
         ];
 
-        Keyboard { keys, shift: 0 }
+        Keyboard {
+            keys,
+            font,
+            extra_keys: 0,
+            shift: 0,
+        }
+    }
+
+    /// Show the function and keypad keys that the game accepts to end a line of input, in the
+    /// space to the left of the space bar. (The arrow keys are always on the keyboard.)
+    pub fn set_terminators(&mut self, terminators: &[ZChar]) {
+        let font = self.font;
+        let mut keys: Vec<Key> = terminators
+            .iter()
+            .filter_map(|zch| {
+                let label = match zch.0 {
+                    133..=144 => format!("F{}", zch.0 - 132),
+                    145..=154 => format!("K{}", zch.0 - 145),
+                    _ => return None,
+                };
+                Some(Key {
+                    width: EXTRA_KEY_WIDTH,
+                    special: true,
+                    align: 0.5,
+                    chars: vec![(
+                        Text::literal(LABEL_HEIGHT, font, &label),
+                        KeyPress::ZChar(*zch),
+                    )],
+                })
+            })
+            .take((EXTRA_KEYS_WIDTH / EXTRA_KEY_WIDTH) as usize)
+            .collect();
+
+        let extra_keys = keys.len();
+        keys.insert(
+            0,
+            Key {
+                width: EXTRA_KEYS_WIDTH - EXTRA_KEY_WIDTH * extra_keys as i32,
+                special: true,
+                align: 0.0,
+                chars: vec![],
+            },
+        );
+
+        let bottom_row = self.keys.last_mut().expect("the keyboard has rows");
+        bottom_row.splice(0..(self.extra_keys + 1), keys);
+        self.extra_keys = extra_keys;
     }
 }
 
@@ -9,7 +9,9 @@ use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
 use armrest::ml;
 
@@ -28,9 +30,11 @@ use itertools::{Itertools, Position};
 use rusttype::Font;
 use serde::{Deserialize, Serialize};
 
+use encrusted_heart::babel::StoryInfo;
+use encrusted_heart::blorb::Blorb;
 use encrusted_heart::options::Options;
-use encrusted_heart::traits::{BaseOutput, BaseUI, TextStyle};
-use encrusted_heart::zmachine::{Step, Zmachine};
+use encrusted_heart::traits::{BaseOutput, BaseUI, SoundEvent, TextStyle};
+use encrusted_heart::zmachine::{Step, Zmachine, ZmachineError};
 use encrusted_heart::zscii::ZChar;
 use regex::Regex;
 
@@ -50,6 +54,11 @@ const MONOSPACE_LINE_HEIGHT: i32 = 33;
 const LINE_LENGTH: i32 = 1006;
 const TEXT_AREA_HEIGHT: i32 = 32 * LINE_HEIGHT;
 const CHARS_PER_LINE: usize = 64;
+// Enough for any sensible game to get to its next prompt, but short enough that a stuck one
+// doesn't freeze the tablet for long.
+const INSTRUCTION_BUDGET: usize = 5_000_000;
+// Cover art is shrunk down to fit where the book icon would go.
+const COVER_SIZE: u32 = 44;
 
 const SECTION_BREAK: &str = ">   >   >";
 
@@ -78,16 +87,16 @@ lazy_static! {
         .unwrap()
         .to_rgb8()
     );
-    static ref GAME_ICON: Image = Image::new(
+    static ref GAME_ICON: Arc<Image> = Arc::new(Image::new(
         image::load_from_memory_with_format(include_bytes!("book.png"), image::ImageFormat::Png)
             .unwrap()
             .to_rgb8()
-    );
-    static ref SAVE_ICON: Image = Image::new(
+    ));
+    static ref SAVE_ICON: Arc<Image> = Arc::new(Image::new(
         image::load_from_memory_with_format(include_bytes!("restore.png"), image::ImageFormat::Png)
             .unwrap()
             .to_rgb8()
-    );
+    ));
 }
 
 #[derive(Clone, Debug)]
@@ -99,8 +108,18 @@ enum Msg {
     LoadGame(PathBuf),
     Restore(PathBuf, SaveMeta),
     Resume,
+    Replay,
+    KeepWaiting,
+    ListGames,
     ReadChar(ZChar),
     Shift(usize),
+    Timeout(usize),
+}
+
+// Work for the background thread: ink to recognize, or a timed read to wake the game up for.
+enum Background {
+    Ink(Ink, Arc<Dict>, usize),
+    Timer(Duration, usize),
 }
 
 enum UserInput {
@@ -125,7 +144,7 @@ enum Element {
         contents: UserInput,
     },
     File {
-        icon: &'static Image,
+        icon: Arc<Image>,
         big_text: Text,
         small_text: Text,
         message: Option<Msg>,
@@ -135,12 +154,7 @@ enum Element {
 }
 
 impl Element {
-    fn file_display(
-        icon: &'static Image,
-        big_text: &str,
-        path_str: &str,
-        msg: Option<Msg>,
-    ) -> Element {
+    fn file_display(icon: Arc<Image>, big_text: &str, path_str: &str, msg: Option<Msg>) -> Element {
         Element::File {
             icon,
             big_text: Text::literal(LINE_HEIGHT * 4 / 3, &*ROMAN, &big_text),
@@ -152,6 +166,15 @@ impl Element {
             message: msg,
         }
     }
+
+    fn game_display(icon: Arc<Image>, title: &str, byline: &str, msg: Option<Msg>) -> Element {
+        Element::File {
+            icon,
+            big_text: Text::literal(LINE_HEIGHT * 4 / 3, &*ROMAN, title),
+            small_text: Text::literal(LINE_HEIGHT * 2 / 3, &*ITALIC, byline),
+            message: msg,
+        }
+    }
 }
 
 #[derive(Hash)]
@@ -518,17 +541,79 @@ struct Session {
     pages: Pages,
     restore: Option<Pages>,
     save_root: PathBuf,
+    // How many times the game has been stepped, so a timer knows if its read is still going.
+    steps: usize,
+}
+
+// We can't show colours on e-ink, but light-on-dark text still reads as reverse video...
+fn inverted(style: TextStyle) -> bool {
+    match (style.foreground.gray(), style.background.gray()) {
+        (Some(fg), Some(bg)) if fg > bg => !style.reverse_video(),
+        _ => style.reverse_video(),
+    }
+}
+
+// ...and coloured text in the story is usually meant to stand out, so we italicize it.
+fn emphasised(style: TextStyle) -> bool {
+    let coloured = match style.foreground.gray() {
+        Some(gray) => gray > 0,
+        None => false,
+    };
+    style.italic() || coloured
 }
 
 fn blank(line: &[BaseOutput]) -> bool {
     line.iter().all(|o| o.content.trim().is_empty())
 }
 
+fn append_to_file(path: &Path, contents: &str) {
+    let result = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()));
+
+    if let Err(ioerr) = result {
+        eprintln!("Error when writing to {}: {}", path.display(), ioerr);
+    }
+}
+
 impl Session {
-    pub fn restore(&mut self, path: &Path) {
-        let save_data = fs::read(path).expect("reading save data from path");
+    pub fn restore(&mut self, path: &Path) -> Result<(), ZmachineError> {
+        let save_data = fs::read(path).map_err(|ioerr| {
+            ZmachineError::BadSave(format!("couldn't read {}: {}", path.display(), ioerr))
+        })?;
         eprintln!("Restoring from save at {}", path.display());
-        self.zvm.restore(&save_data);
+        self.zvm.restore(&save_data)
+    }
+
+    // Every session that starts from the beginning is recorded, so it can be replayed later.
+    fn session_record(&self) -> PathBuf {
+        self.save_root.join("session.rec")
+    }
+
+    fn last_session_record(&self) -> PathBuf {
+        self.save_root.join("last-session.rec")
+    }
+
+    pub fn replay_last_session(&mut self) -> io::Result<()> {
+        let commands = fs::read_to_string(self.last_session_record())?;
+        eprintln!(
+            "Replaying {} commands from the last session",
+            commands.lines().count()
+        );
+        self.zvm.replay_commands(&commands);
+        Ok(())
+    }
+
+    // A session that picks up from a save can't be replayed from the start, so stop recording it.
+    fn forget_session(&mut self) {
+        self.zvm.set_command_recording(false);
+        if let Err(ioerr) = fs::remove_file(self.session_record()) {
+            if ioerr.kind() != io::ErrorKind::NotFound {
+                eprintln!("Error when removing the session record: {}", ioerr);
+            }
+        }
     }
 
     fn load_saves(&self) -> io::Result<Vec<PathBuf>> {
@@ -570,11 +655,55 @@ impl Session {
             .words(continue_message)
             .wrap(LINE_LENGTH, true);
 
+        for widget in lines {
+            page.push_element(Element::Line(false, widget));
+        }
+
+        if initial_run && self.last_session_record().exists() {
+            let lines = Text::builder(LINE_HEIGHT, &*ROMAN)
+                .words("You can also ")
+                .message(Msg::Replay)
+                .words("tap here")
+                .no_message()
+                .words(" to replay your last session.")
+                .wrap(LINE_LENGTH, true);
+
+            for widget in lines {
+                page.push_element(Element::Line(false, widget));
+            }
+        }
+
+        page.push_advance_space();
+        Session::push_saves(&mut page, saves);
+
+        self.restore = Some(page);
+    }
+
+    // Shown when the game can't continue: the only ways forward are to restore or to give up.
+    pub fn error_menu(&mut self, error: &ZmachineError) {
+        eprintln!("Error while running the game: {}", error);
+        let saves = self.load_saves().unwrap_or_default();
+        let mut page = Pages::new(&[]);
+
+        let lines = Text::builder(LINE_HEIGHT, &*ROMAN)
+            .words(&format!("The game has stopped with an error: {}. ", error))
+            .words("Select a saved game to restore from the list below, or ")
+            .message(Msg::ListGames)
+            .words("tap here")
+            .no_message()
+            .words(" to return to the list of games.")
+            .wrap(LINE_LENGTH, true);
+
         for widget in lines {
             page.push_element(Element::Line(false, widget));
         }
         page.push_advance_space();
+        Session::push_saves(&mut page, saves);
 
+        self.restore = Some(page);
+    }
+
+    fn push_saves(page: &mut Pages, saves: Vec<PathBuf>) {
         for path in saves {
             let text = path.to_string_lossy().to_string();
 
@@ -591,14 +720,12 @@ impl Session {
             };
 
             page.push_element(Element::file_display(
-                &*SAVE_ICON,
+                SAVE_ICON.clone(),
                 &meta.slug(),
                 &text,
                 Some(Msg::Restore(path, meta)),
             ));
         }
-
-        self.restore = Some(page);
     }
 
     pub fn append_buffer(&mut self, buffer: Vec<BaseOutput>) {
@@ -656,7 +783,7 @@ impl Session {
             for BaseOutput { style, content } in line {
                 // In theory we may want to support multiple of these at once,
                 // but we're not required to, so we don't just yet.
-                let font = match (style.fixed_pitch(), style.bold(), style.italic()) {
+                let font = match (style.fixed_pitch(), style.bold(), emphasised(style)) {
                     (true, _, _) => &*MONOSPACE,
                     (_, true, _) => &*BOLD,
                     (_, _, true) => &*ITALIC,
@@ -685,7 +812,7 @@ impl Session {
         }
     }
 
-    pub fn advance(&mut self) -> Step {
+    pub fn advance(&mut self) -> Result<Step, ZmachineError> {
         // Remove any trailing cruft that shouldn't stay on the page, like menus.
         while matches!(
             self.pages.last().body.last(),
@@ -706,8 +833,41 @@ impl Session {
         // if the upper window is displaying some big quote box, collapse it down.
         self.zvm.ui.resolve_upper_height();
 
-        let result = self.zvm.step();
+        let result = match self.zvm.step() {
+            // A runaway interrupt has been abandoned, and the game can carry on without it.
+            Err(error @ ZmachineError::InterruptBudget { .. }) => {
+                eprintln!("{}", error);
+                self.zvm.step()?
+            }
+            result => result?,
+        };
         self.zvm_state = result.clone();
+        self.steps += 1;
+
+        // The game turns the transcript on and off itself, eg. with the SCRIPT command.
+        let transcript = self.zvm.ui.drain_transcript();
+        if !transcript.is_empty() {
+            append_to_file(&self.save_root.join("transcript.txt"), &transcript);
+        }
+        for command in self.zvm.ui.drain_commands() {
+            append_to_file(&self.session_record(), &format!("{}\n", command));
+        }
+        // The tablet has no speaker, so sounds end as soon as they start.
+        for event in self.zvm.ui.drain_sounds() {
+            if let SoundEvent::Play {
+                number, repeats, ..
+            } = event
+            {
+                if repeats != 255 {
+                    match self.zvm.handle_sound_finished(number) {
+                        Err(error @ ZmachineError::InterruptBudget { .. }) => {
+                            eprintln!("{}", error)
+                        }
+                        result => result?,
+                    }
+                }
+            }
+        }
 
         if self.zvm.ui.is_cleared() {
             self.pages.push_section_break();
@@ -745,7 +905,7 @@ impl Session {
             // The index of the first non-reverse-video line (ie. no longer the status)
             let cut_index = upper_window
                 .iter()
-                .position(|p| p.first().map_or(true, |(s, _)| !s.reverse_video()))
+                .position(|p| p.first().map_or(true, |(s, _)| !inverted(*s)))
                 .unwrap_or(upper_window.len());
 
             fn body_text(line: &[(TextStyle, char)]) -> Text<Msg> {
@@ -760,27 +920,27 @@ impl Session {
 
             fn blank_line(line: &[(TextStyle, char)]) -> bool {
                 line.iter()
-                    .all(|(s, c)| !s.reverse_video() & c.is_ascii_whitespace())
+                    .all(|(s, c)| !inverted(*s) & c.is_ascii_whitespace())
             }
 
             fn reversed_span(line: &[(TextStyle, char)]) -> Option<(usize, usize)> {
                 let mut start = None;
                 let mut end = None;
                 for (i, (style, char)) in line.iter().enumerate() {
-                    if !style.reverse_video() && *char != ' ' {
+                    if !inverted(*style) && *char != ' ' {
                         return None;
                     }
 
                     if start.is_none() {
-                        if style.reverse_video() {
+                        if inverted(*style) {
                             start = Some(i);
                         }
                     } else if end.is_none() {
-                        if !style.reverse_video() {
+                        if !inverted(*style) {
                             end = Some(i);
                         }
                     } else {
-                        if style.reverse_video() {
+                        if inverted(*style) {
                             return None;
                         }
                     }
@@ -867,6 +1027,12 @@ impl Session {
         self.pages.push_advance_space();
         self.append_buffer(buffer);
 
+        // We only lay out text in one column, so any V6 windows beyond the first two follow on.
+        for (_window, buffer) in self.zvm.ui.drain_other_output() {
+            self.pages.push_advance_space();
+            self.append_buffer(buffer);
+        }
+
         match result {
             Step::Save(data) => {
                 let now = chrono::offset::Local::now();
@@ -887,7 +1053,7 @@ impl Session {
                 };
 
                 let meta = SaveMeta {
-                    location: None,
+                    location: self.zvm.player_location().map(|room| room.name()),
                     score_and_turn: None,
                     status_line,
                 };
@@ -897,37 +1063,70 @@ impl Session {
 
                 self.pages.push_advance_space();
                 self.pages.push_element(Element::file_display(
-                    &*SAVE_ICON,
+                    SAVE_ICON.clone(),
                     &meta.slug(),
                     save_path.to_string_lossy().borrow(),
                     None,
                 ));
                 self.pages.push_advance_space();
-                self.zvm.handle_save_result(true);
+                self.zvm.handle_save_result(true)?;
                 self.advance()
             }
-            Step::Restore => result,
-            Step::ReadChar => {
+            Step::Restore => Ok(result),
+            Step::Yield => {
+                let lines = Text::builder(LINE_HEIGHT, &*ITALIC)
+                    .words("The game is taking a long time to respond. ")
+                    .message(Msg::KeepWaiting)
+                    .words("Tap here")
+                    .no_message()
+                    .words(" to keep waiting, or ")
+                    .message(Msg::ListGames)
+                    .words("here")
+                    .no_message()
+                    .words(" to return to the list of games.")
+                    .wrap(LINE_LENGTH, true);
+
+                self.pages.push_advance_space();
+                for widget in lines {
+                    self.pages.push_element(Element::Line(false, widget));
+                }
+                Ok(result)
+            }
+            Step::ReadChar { .. } => {
                 if force_keyboard {
                     self.pages.show_keyboard = true;
                 } else {
                     self.pages.push_advance_space();
                     self.pages.push_element(Element::CharInput);
                 }
-                result
+                Ok(result)
             }
             _ => {
                 self.pages.show_keyboard = false;
+                // Text left over from an interrupted line is easiest to carry on with by keyboard.
+                let mut contents = UserInput::Ink(vec![]);
+                if let Step::ReadLine {
+                    terminators,
+                    preloaded,
+                    ..
+                } = &result
+                {
+                    self.pages.keyboard.set_terminators(terminators);
+                    if !preloaded.is_empty() {
+                        self.pages.show_keyboard = true;
+                        contents = UserInput::String(preloaded.clone());
+                    }
+                }
 
                 // Add a prompt
                 self.pages.maybe_new_page(LINE_HEIGHT * 4);
                 self.pages.push_advance_space();
                 self.pages.push_element(Element::Input {
                     active: true,
-                    contents: UserInput::Ink(vec![]),
+                    contents,
                 });
 
-                result
+                Ok(result)
             }
         }
     }
@@ -942,8 +1141,10 @@ enum GameState {
 
 struct Game {
     state: GameState,
-    ink_tx: mpsc::Sender<(Ink, Arc<Dict>, usize)>,
+    ink_tx: mpsc::Sender<Background>,
     awaiting_ink: usize,
+    // The step of the game that the last timer was set for.
+    timer_step: usize,
     root_dir: PathBuf,
 }
 
@@ -956,7 +1157,10 @@ impl Game {
                 let path = entry.path();
                 if path.is_file() {
                     if let Some(ext) = path.extension() {
-                        if ext == "z3" || ext == "z4" || ext == "z5" || ext == "z8" {
+                        let story = ext == "z3" || ext == "z4" || ext == "z5" || ext == "z8";
+                        let blorb = ext == "zblorb" || ext == "zblb" || ext == "blb";
+                        // .blb files are often Glulx games, which we can't play.
+                        if story || (blorb && Game::has_story(&path)) {
                             games.push(path);
                         }
                     }
@@ -966,30 +1170,86 @@ impl Game {
         Ok(games)
     }
 
+    fn has_story(path: &Path) -> bool {
+        fs::read(path)
+            .ok()
+            .and_then(|data| Blorb::from_bytes(data).ok())
+            .map_or(false, |blorb| blorb.story().is_some())
+    }
+
+    // The story's bibliographic info, and its cover art if it's packaged in a Blorb.
+    fn describe_game(path: &Path) -> io::Result<(StoryInfo, Option<Arc<Image>>)> {
+        let data = fs::read(path)?;
+        if !Blorb::is_blorb(&data) {
+            return Ok((StoryInfo::new(&data, None), None));
+        }
+
+        let blorb =
+            Blorb::from_bytes(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let info = StoryInfo::new(blorb.story().unwrap_or_default(), Some(&blorb));
+        let cover = blorb
+            .cover_image()
+            .and_then(|cover| image::load_from_memory(blorb.data(cover)).ok())
+            .map(|image| {
+                let thumbnail = image.thumbnail(COVER_SIZE, COVER_SIZE);
+                Arc::new(Image::new(thumbnail.to_rgb8()))
+            });
+
+        Ok((info, cover))
+    }
+
+    // eg. "An Interactive Fantasy by Dave Lebling and Marc Blank, 1980"
+    fn byline(info: &StoryInfo) -> Option<String> {
+        let mut byline = info.headline.clone().unwrap_or_default();
+        if let Some(author) = &info.author {
+            if !byline.is_empty() {
+                byline.push(' ');
+            }
+            byline.push_str("by ");
+            byline.push_str(author);
+        }
+        if let Some(year) = info.year() {
+            if !byline.is_empty() {
+                byline.push_str(", ");
+            }
+            byline.push_str(&year.to_string());
+        }
+
+        if byline.is_empty() {
+            None
+        } else {
+            Some(byline)
+        }
+    }
+
     fn load_game(&self, path: &Path) -> io::Result<Session> {
         let mut data = Vec::new();
         let mut file = File::open(path)?;
         file.read_to_end(&mut data)?;
 
-        let version = data[0];
+        // V6 games need to keep the Blorb around, for the pictures.
+        let (data, blorb) =
+            Blorb::split_story(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
+        let version = data.first().copied().unwrap_or(0);
         if version == 0 || version > 8 {
-            println!(
-                "\n\
-                 \"{}\" has an unsupported game version: {}\n\
-                 Is this a valid game file?\n",
-                path.to_string_lossy(),
-                version
-            );
-            unimplemented!();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported game version {}", version),
+            ));
         }
 
         let ui = BaseUI::new();
 
         let mut opts = Options::default();
         opts.dimensions.0 = CHARS_PER_LINE as u16;
+        opts.instruction_budget = Some(INSTRUCTION_BUDGET);
 
-        let zvm = Zmachine::new(data, ui, opts);
+        let mut zvm = Zmachine::new(data, ui, opts);
+        zvm.set_command_recording(true);
+        if let Some(blorb) = blorb {
+            zvm.set_blorb(blorb);
+        }
 
         let dict = Dict(zvm.get_dictionary().into_iter().collect());
 
@@ -999,7 +1259,23 @@ impl Game {
         if !save_root.exists() {
             fs::create_dir(&save_root)?;
         }
-        let pages = Pages::new(zvm.unicode_table());
+        let mut pages = Pages::new(zvm.unicode_table());
+
+        // Files copied over USB sometimes get cut short, so warn before the game falls over.
+        if !zvm.verify_story() {
+            eprintln!("Story file failed verification: {}", path.to_string_lossy());
+            let lines = Text::builder(LINE_HEIGHT, &*ITALIC)
+                .words(
+                    "This game file doesn't match its checksum, so it may be damaged or incomplete. \
+                     If the game misbehaves, try copying it over again.",
+                )
+                .wrap(LINE_LENGTH, true);
+            for widget in lines {
+                pages.push_element(Element::Line(false, widget));
+            }
+            pages.push_advance_space();
+        }
+
         let session = Session {
             zvm,
             zvm_state: Step::Done,
@@ -1007,11 +1283,41 @@ impl Game {
             pages: pages,
             restore: None,
             save_root,
+            steps: 0,
         };
 
+        // Keep the previous session around, in case the player wants to replay it.
+        if session.session_record().exists() {
+            fs::rename(session.session_record(), session.last_session_record())?;
+        }
+
         Ok(session)
     }
 
+    fn load_error_page(path: &Path, error: &io::Error) -> Pages {
+        eprintln!("Unable to load {}: {}", path.to_string_lossy(), error);
+        let mut page = Pages::new(&[]);
+
+        let lines = Text::builder(LINE_HEIGHT, &*ROMAN)
+            .words("Couldn't load ")
+            .font(&*MONOSPACE)
+            .scale(MONOSPACE_LINE_HEIGHT as f32)
+            .literal(&path.to_string_lossy())
+            .font(&*ROMAN)
+            .scale(LINE_HEIGHT as f32)
+            .words(&format!(": {}. ", error))
+            .message(Msg::ListGames)
+            .words("Tap here")
+            .no_message()
+            .words(" to return to the list of games.")
+            .wrap(LINE_LENGTH, true);
+
+        for widget in lines {
+            page.push_element(Element::Line(false, widget));
+        }
+        page
+    }
+
     fn game_page(root_dir: &Path) -> Pages {
         let mut games = Pages::new(&[]);
 
@@ -1042,26 +1348,33 @@ impl Game {
 
         for game_path in game_vec {
             let path_str = game_path.to_string_lossy().to_string();
-            let slug = game_path
-                .file_stem()
-                .map_or("unknown".to_string(), |os| os.to_string_lossy().to_string());
-
-            elements.push((slug, game_path, path_str));
+            let (info, cover) = Game::describe_game(&game_path).unwrap_or_else(|e| {
+                eprintln!("Unable to read metadata from {}: {}", path_str, e);
+                (StoryInfo::default(), None)
+            });
+            let title = info.title.clone().unwrap_or_else(|| {
+                game_path
+                    .file_stem()
+                    .map_or("unknown".to_string(), |os| os.to_string_lossy().to_string())
+            });
+
+            elements.push((title, info, cover, game_path, path_str));
         }
 
-        elements.sort_by_key(|(slug, _, _)| slug.to_owned());
+        elements.sort_by_key(|(title, ..)| title.to_lowercase());
 
-        for (slug, path, path_str) in elements {
-            games.push_element(Element::file_display(
-                &*GAME_ICON,
-                &slug,
-                &path_str,
-                Some(Msg::LoadGame(path)),
-            ));
+        for (title, info, cover, path, path_str) in elements {
+            let icon = cover.unwrap_or_else(|| GAME_ICON.clone());
+            let msg = Some(Msg::LoadGame(path));
+            let element = match Game::byline(&info) {
+                Some(byline) => Element::game_display(icon, &title, &byline, msg),
+                None => Element::file_display(icon, &title, &path_str, msg),
+            };
+            games.push_element(element);
         }
 
         let more_games = Text::builder(LINE_HEIGHT, &*ROMAN)
-            .words("To load more games, copy a .z3, .z4, .z5, or .z8 file to ")
+            .words("To load more games, copy a .z3, .z4, .z5, .z8 or .zblorb file to ")
             .font(&*MONOSPACE)
             .scale(MONOSPACE_LINE_HEIGHT as f32)
             .literal(&root_dir.to_string_lossy())
@@ -1077,17 +1390,77 @@ impl Game {
         games
     }
 
-    fn init(ink_tx: mpsc::Sender<(Ink, Arc<Dict>, usize)>, root_dir: PathBuf) -> Game {
+    fn init(ink_tx: mpsc::Sender<Background>, root_dir: PathBuf) -> Game {
         Game {
             state: GameState::Init {
                 games: Game::game_page(&root_dir),
             },
             ink_tx,
             awaiting_ink: 0,
+            timer_step: 0,
             root_dir,
         }
     }
 
+    // Run the game until it needs something from the player, and set up the UI to match.
+    fn advance_session(&mut self) {
+        if let GameState::Playing { session } = &mut self.state {
+            match session.advance() {
+                Ok(Step::Restore) => {
+                    // Start a new page unless the current page is empty
+                    // session.maybe_new_page(TEXT_AREA_HEIGHT);
+                    let saves = session.load_saves().unwrap();
+                    session.restore_menu(saves, false);
+                }
+                Ok(Step::Done) => {
+                    self.state = GameState::Init {
+                        games: Game::game_page(&self.root_dir),
+                    };
+                }
+                Ok(_) => {}
+                Err(error) => session.error_menu(&error),
+            };
+        }
+    }
+
+    // V5+ games hand back the line they were interrupted in as preloaded text, but earlier ones
+    // leave it to the interpreter.
+    fn keep_partial_input(&mut self, partial: String) {
+        if let GameState::Playing { session } = &mut self.state {
+            if partial.is_empty() || !matches!(session.zvm_state, Step::ReadLine { .. }) {
+                return;
+            }
+            if let Some(Element::Input {
+                active: true,
+                contents,
+            }) = &mut session.pages.last_mut().body.last_mut()
+            {
+                if contents.is_empty() {
+                    *contents = UserInput::String(partial);
+                    session.pages.show_keyboard = true;
+                }
+            }
+        }
+    }
+
+    // Timed reads need waking up once their time is up, unless the player gets there first.
+    fn set_timer(&mut self) {
+        if let GameState::Playing { session } = &self.state {
+            let timeout = match &session.zvm_state {
+                Step::ReadChar { timeout } | Step::ReadLine { timeout, .. } => *timeout,
+                _ => None,
+            };
+            if let Some(timeout) = timeout {
+                if self.timer_step != session.steps {
+                    self.timer_step = session.steps;
+                    self.ink_tx
+                        .send(Background::Timer(timeout, session.steps))
+                        .unwrap();
+                }
+            }
+        }
+    }
+
     fn pages_mut(&mut self) -> &mut Pages {
         match &mut self.state {
             GameState::Playing { session, .. } => match &mut session.restore {
@@ -1138,7 +1511,11 @@ impl Applet for Game {
                             merged.append(i.clone(), 0.5);
                         }
                         self.ink_tx
-                            .send((merged, session.dict.clone(), self.awaiting_ink))
+                            .send(Background::Ink(
+                                merged,
+                                session.dict.clone(),
+                                self.awaiting_ink,
+                            ))
                             .unwrap();
                     }
                 }
@@ -1147,68 +1524,106 @@ impl Applet for Game {
             Msg::RecognizedText(n, text) => {
                 if let GameState::Playing { session } = &mut self.state {
                     if n == self.awaiting_ink {
-                        match session.zvm_state.clone() {
-                            Step::ReadChar => {
+                        let input_result = match session.zvm_state.clone() {
+                            Step::ReadChar { .. } => {
                                 let c = text.chars().next().unwrap_or('\n');
-                                session.zvm.handle_read_char(ZChar(c as u8));
-                            }
-                            Step::ReadLine => {
-                                session.zvm.handle_input(text);
+                                session.zvm.handle_read_char(ZChar(c as u8))
                             }
+                            Step::ReadLine { .. } => session.zvm.handle_input(text),
                             other => {
                                 unimplemented!("Got input in unexpected state: {:?}", other);
                             }
-                        }
-                        match session.advance() {
-                            Step::Restore => {
-                                // Start a new page unless the current page is empty
-                                // session.maybe_new_page(TEXT_AREA_HEIGHT);
-                                let saves = session.load_saves().unwrap();
-                                session.restore_menu(saves, false);
-                            }
-                            Step::Done => {
-                                self.state = GameState::Init {
-                                    games: Game::game_page(&self.root_dir),
-                                };
-                            }
-                            _ => {}
                         };
+                        match input_result {
+                            Ok(()) => self.advance_session(),
+                            Err(error) => session.error_menu(&error),
+                        }
                     }
                 }
             }
             Msg::LoadGame(game_path) => {
-                let mut session = self.load_game(&game_path).unwrap();
-                let saves = session.load_saves().unwrap();
-                if saves.is_empty() {
-                    let state = session.advance();
-                    assert!(state == Step::ReadLine || state == Step::ReadChar);
-                } else {
-                    session.restore_menu(saves, true)
-                }
+                let loaded = self.load_game(&game_path).and_then(|mut session| {
+                    let saves = session.load_saves()?;
+                    if saves.is_empty() && !session.last_session_record().exists() {
+                        match session.advance() {
+                            Ok(state) => assert!(matches!(
+                                state,
+                                Step::ReadLine { .. } | Step::ReadChar { .. } | Step::Yield
+                            )),
+                            Err(error) => session.error_menu(&error),
+                        }
+                    } else {
+                        session.restore_menu(saves, true)
+                    }
+                    Ok(session)
+                });
 
-                self.state = GameState::Playing { session }
+                self.state = match loaded {
+                    Ok(session) => GameState::Playing { session },
+                    Err(error) => GameState::Init {
+                        games: Game::load_error_page(&game_path, &error),
+                    },
+                };
             }
             Msg::Restore(path, _meta) => {
                 if let GameState::Playing { session } = &mut self.state {
                     session.zvm.ui = BaseUI::new();
-                    session.restore(&path);
-                    let _state = session.advance();
-                    // restoring inserts a page break by default, which is boring.
-                    session.pages.page_relative(1);
-                    session.restore = None;
+                    match session.restore(&path).and_then(|()| session.advance()) {
+                        Ok(_state) => {
+                            // restoring inserts a page break by default, which is boring.
+                            session.pages.page_relative(1);
+                            session.restore = None;
+                            session.forget_session();
+                        }
+                        Err(error) => session.error_menu(&error),
+                    }
                 }
             }
             Msg::Resume => {
                 if let GameState::Playing { session } = &mut self.state {
-                    if session.zvm_state == Step::Restore {
-                        session.zvm.handle_restore_result();
+                    let resumed = if session.zvm_state == Step::Restore {
+                        session.zvm.handle_restore_result()
+                    } else {
+                        Ok(())
+                    };
+                    match resumed.and_then(|()| session.advance()) {
+                        Ok(_state) => session.restore = None,
+                        Err(error) => session.error_menu(&error),
+                    }
+                }
+            }
+            Msg::Replay => {
+                if let GameState::Playing { session } = &mut self.state {
+                    if let Err(ioerr) = session.replay_last_session() {
+                        eprintln!("Error when reading the last session: {}", ioerr);
+                    }
+                    match session.advance() {
+                        Ok(_state) => session.restore = None,
+                        Err(error) => session.error_menu(&error),
                     }
-                    let _state = session.advance();
-                    session.restore = None;
                 }
             }
+            Msg::KeepWaiting => {
+                // Old prompts stay on the page, so check that the game is actually still busy.
+                let waiting = match &self.state {
+                    GameState::Playing { session } => session.zvm_state == Step::Yield,
+                    _ => false,
+                };
+                if waiting {
+                    self.advance_session();
+                }
+            }
+            Msg::ListGames => {
+                self.state = GameState::Init {
+                    games: Game::game_page(&self.root_dir),
+                };
+            }
             Msg::ReadChar(zch) => {
                 if let GameState::Playing { session } = &mut self.state {
+                    let is_terminator = match &session.zvm_state {
+                        Step::ReadLine { terminators, .. } => terminators.contains(&zch),
+                        _ => false,
+                    };
                     if let Some(Element::Input {
                         active: _,
                         contents,
@@ -1218,22 +1633,10 @@ impl Applet for Game {
                             ZChar::ESC => {}
                             ZChar::RETURN => {
                                 if let UserInput::String(s) = contents {
-                                    session.zvm.handle_input(s.clone());
-                                    // FIXME: share this with the read-line code
-                                    match session.advance() {
-                                        Step::Restore => {
-                                            // Start a new page unless the current page is empty
-                                            // session.maybe_new_page(TEXT_AREA_HEIGHT);
-                                            let saves = session.load_saves().unwrap();
-                                            session.restore_menu(saves, false);
-                                        }
-                                        Step::Done => {
-                                            self.state = GameState::Init {
-                                                games: Game::game_page(&self.root_dir),
-                                            };
-                                        }
-                                        _ => {}
-                                    };
+                                    match session.zvm.handle_input(s.clone()) {
+                                        Ok(()) => self.advance_session(),
+                                        Err(error) => session.error_menu(&error),
+                                    }
                                 }
                             }
                             ZChar::DELETE => {
@@ -1241,6 +1644,16 @@ impl Applet for Game {
                                     s.pop();
                                 }
                             }
+                            _ if is_terminator => {
+                                let text = match contents {
+                                    UserInput::String(s) => s.clone(),
+                                    UserInput::Ink(_) => String::new(),
+                                };
+                                match session.zvm.handle_terminated_input(text, zch) {
+                                    Ok(()) => self.advance_session(),
+                                    Err(error) => session.error_menu(&error),
+                                }
+                            }
                             _ => {
                                 let ch = zch.to_char(session.zvm.unicode_table());
                                 match contents {
@@ -1252,22 +1665,10 @@ impl Applet for Game {
                             }
                         }
                     } else {
-                        session.zvm.handle_read_char(zch);
-                        // FIXME: share this with the read-line code
-                        match session.advance() {
-                            Step::Restore => {
-                                // Start a new page unless the current page is empty
-                                // session.maybe_new_page(TEXT_AREA_HEIGHT);
-                                let saves = session.load_saves().unwrap();
-                                session.restore_menu(saves, false);
-                            }
-                            Step::Done => {
-                                self.state = GameState::Init {
-                                    games: Game::game_page(&self.root_dir),
-                                };
-                            }
-                            _ => {}
-                        };
+                        match session.zvm.handle_read_char(zch) {
+                            Ok(()) => self.advance_session(),
+                            Err(error) => session.error_menu(&error),
+                        }
                     }
                 }
             }
@@ -1282,19 +1683,54 @@ impl Applet for Game {
                     contents,
                 }) = &mut pages.last_mut().body.last_mut()
                 {
-                    *contents = if show_keyboard {
-                        UserInput::String(String::new())
-                    } else {
-                        UserInput::Ink(vec![])
-                    };
+                    if !show_keyboard {
+                        *contents = UserInput::Ink(vec![]);
+                    } else if let UserInput::Ink(_) = contents {
+                        *contents = UserInput::String(String::new());
+                    }
                 }
             }
             Msg::Shift(depth) => {
                 let shift = &mut self.pages_mut().keyboard.shift;
                 *shift = if *shift == depth { 0 } else { depth };
             }
+            Msg::Timeout(step) => {
+                if let GameState::Playing { session } = &mut self.state {
+                    // Only if the game is still waiting on the read the timer was set for.
+                    let waiting = session.restore.is_none()
+                        && session.steps == step
+                        && matches!(
+                            session.zvm_state,
+                            Step::ReadChar { .. } | Step::ReadLine { .. }
+                        );
+                    if waiting {
+                        // Handwriting can't be passed on until it's recognized, but typing can.
+                        let partial = match session.pages.last().body.last() {
+                            Some(Element::Input {
+                                contents: UserInput::String(s),
+                                ..
+                            }) => s.clone(),
+                            _ => String::new(),
+                        };
+                        match session.zvm.handle_timeout(&partial) {
+                            Ok(_) => {}
+                            // The routine ran away, and was abandoned, so the read carries on.
+                            Err(error @ ZmachineError::InterruptBudget { .. }) => {
+                                eprintln!("{}", error)
+                            }
+                            Err(error) => {
+                                session.error_menu(&error);
+                                return None;
+                            }
+                        }
+                        self.advance_session();
+                        self.keep_partial_input(partial);
+                    }
+                }
+            }
         }
 
+        self.set_timer();
         None
     }
 }
@@ -1322,7 +1758,7 @@ fn main() {
 
     let mut app = armrest::app::App::new();
 
-    let (ink_tx, ink_rx) = mpsc::channel::<(Ink, Arc<Dict>, usize)>();
+    let (ink_tx, ink_rx) = mpsc::channel::<Background>();
     let wakeup = app.wakeup();
 
     let mut ink_log = OpenOptions::new()
@@ -1338,13 +1774,39 @@ fn main() {
         let _thread = thread::spawn(move || {
             let mut recognizer: Recognizer<Spline> = ml::Recognizer::new().unwrap();
 
-            while let Ok(mut msg) = ink_rx.recv() {
-                // We only care about the last message!
-                while let Ok(m) = ink_rx.try_recv() {
-                    msg = m;
-                }
+            let mut timer: Option<(Instant, usize)> = None;
+            loop {
+                let msg = match timer {
+                    None => ink_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    Some((deadline, _)) => {
+                        ink_rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                };
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if let Some((_, step)) = timer.take() {
+                            text_tx.send(Msg::Timeout(step));
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
 
-                let (i, dict, n) = msg;
+                // We only care about the last ink, and the last timer.
+                let mut ink = None;
+                for msg in std::iter::once(msg).chain(ink_rx.try_iter()) {
+                    match msg {
+                        Background::Ink(i, dict, n) => ink = Some((i, dict, n)),
+                        Background::Timer(timeout, step) => {
+                            timer = Some((Instant::now() + timeout, step))
+                        }
+                    }
+                }
+                let (i, dict, n) = match ink {
+                    Some(ink) => ink,
+                    None => continue,
+                };
 
                 let string = recognizer
                     .recognize(